@@ -0,0 +1,105 @@
+use std::io::Write as _;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::Serialize;
+
+use crate::error::Error;
+
+/// Size at which the log is rotated to `<path>.1`, replacing the previous rotation.
+const MAX_BYTES: u64 = 1024 * 1024;
+
+/// Append-only record of the commands bash mode executed.
+#[derive(Debug, Clone)]
+pub struct AuditLog {
+    path: PathBuf,
+    max_bytes: u64,
+}
+
+#[derive(Debug, Serialize)]
+struct Entry<'a> {
+    timestamp: u64,
+    command: &'a str,
+    status: Option<i32>,
+    cwd: Option<&'a Path>,
+}
+
+impl AuditLog {
+    pub fn new(path: PathBuf) -> Self {
+        Self {
+            path,
+            max_bytes: MAX_BYTES,
+        }
+    }
+
+    /// `audit.log` in the state dir.
+    pub fn default_path() -> Option<PathBuf> {
+        rgpt_utils::paths::state_dir().map(|dir| dir.join("audit.log"))
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Append one JSON line for an executed command. `status` is `None` if it was killed by a
+    /// signal.
+    pub fn record(&self, command: &str, status: Option<i32>) -> Result<(), Error> {
+        if let Some(dir) = self.path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        self.rotate()?;
+        let cwd = std::env::current_dir().ok();
+        let entry = Entry {
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or_default(),
+            command,
+            status,
+            cwd: cwd.as_deref(),
+        };
+        let mut line = serde_json::to_vec(&entry)?;
+        line.push(b'\n');
+        std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?
+            .write_all(&line)?;
+        Ok(())
+    }
+
+    fn rotate(&self) -> Result<(), Error> {
+        let Ok(metadata) = std::fs::metadata(&self.path) else {
+            return Ok(());
+        };
+        if metadata.len() >= self.max_bytes {
+            let mut rotated = self.path.clone().into_os_string();
+            rotated.push(".1");
+            std::fs::rename(&self.path, rotated)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_record_and_rotate() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut log = AuditLog::new(dir.path().join("audit.log"));
+        log.max_bytes = 64;
+
+        log.record("ls -la", Some(0)).unwrap();
+        let content = std::fs::read_to_string(log.path()).unwrap();
+        let entry: serde_json::Value = serde_json::from_str(content.trim()).unwrap();
+        assert_eq!(entry["command"], "ls -la");
+        assert_eq!(entry["status"], 0);
+
+        log.record("false", Some(1)).unwrap();
+        let content = std::fs::read_to_string(log.path()).unwrap();
+        assert_eq!(content.lines().count(), 1);
+        assert!(dir.path().join("audit.log.1").exists());
+    }
+}
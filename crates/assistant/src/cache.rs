@@ -0,0 +1,191 @@
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime};
+
+use rgpt_types::completion::{
+    Content, ContentBlock, DeltaUsage, MessageDelta, MessageStartData, Response, TextEvent, Usage,
+};
+
+use crate::{error::Error, TextBlocks};
+
+/// Answers to requests sent before, one JSON file per request hash.
+#[derive(Debug, Clone)]
+pub struct Cache {
+    dir: PathBuf,
+    /// How long an answer is used for, forever if `None`.
+    ttl: Option<Duration>,
+}
+
+impl Cache {
+    pub fn new(dir: PathBuf, ttl: Option<Duration>) -> Self {
+        Self { dir, ttl }
+    }
+
+    /// In `~/.cache/rgpt`, or wherever `XDG_CACHE_HOME` says.
+    pub fn open(ttl: Option<Duration>) -> Option<Self> {
+        rgpt_utils::paths::cache_dir().map(|dir| Self::new(dir, ttl))
+    }
+
+    fn path(&self, key: &str) -> PathBuf {
+        self.dir.join(format!("{}.json", key))
+    }
+
+    /// The answer cached under `key`, unless there is none or it has expired.
+    pub fn get(&self, key: &str) -> Option<Response> {
+        let path = self.path(key);
+        let modified = std::fs::metadata(&path).and_then(|m| m.modified()).ok()?;
+        let age = SystemTime::now()
+            .duration_since(modified)
+            .unwrap_or_default();
+        if self.ttl.is_some_and(|ttl| age >= ttl) {
+            return None;
+        }
+        match serde_json::from_slice(&std::fs::read(&path).ok()?) {
+            Ok(response) => Some(response),
+            Err(e) => {
+                tracing::warn!("ignoring cached answer {}: {}", path.display(), e);
+                None
+            }
+        }
+    }
+
+    pub fn put(&self, key: &str, response: &Response) -> Result<(), Error> {
+        std::fs::create_dir_all(&self.dir)?;
+        // Atomic so a concurrent run never reads half an answer
+        rgpt_utils::fs::write_atomic(&self.path(key), &serde_json::to_vec(response)?)?;
+        Ok(())
+    }
+}
+
+/// A cached answer as the events of a stream, text and thinking blocks only.
+pub fn replay(response: Response) -> Vec<TextEvent> {
+    let mut events = vec![TextEvent::MessageStart {
+        message: MessageStartData {
+            id: response.id,
+            type_: response.type_,
+            role: response.role,
+            model: response.model,
+            content: vec![],
+            stop_reason: None,
+            stop_sequence: None,
+            usage: Usage {
+                input_tokens: response.usage.input_tokens,
+                output_tokens: 0,
+            },
+        },
+    }];
+    let blocks = response
+        .content
+        .into_iter()
+        .filter_map(|content| match content {
+            Content::Text { text } => Some(ContentBlock::Text { text }),
+            Content::Thinking { thinking } => Some(ContentBlock::Thinking { thinking }),
+            _ => None,
+        });
+    for (index, content_block) in blocks.enumerate() {
+        events.push(TextEvent::ContentBlockStart {
+            index,
+            content_block,
+        });
+        events.push(TextEvent::ContentBlockStop { index });
+    }
+    events.push(TextEvent::MessageDelta {
+        delta: MessageDelta {
+            stop_reason: response.stop_reason,
+            stop_sequence: response.stop_sequence,
+        },
+        usage: Some(DeltaUsage {
+            output_tokens: response.usage.output_tokens,
+        }),
+    });
+    events.push(TextEvent::MessageStop);
+    events
+}
+
+/// The answer of a stream put together from its events, to cache it. Only its text is kept.
+#[derive(Debug, Default)]
+pub struct Recorder {
+    start: Option<MessageStartData>,
+    text: TextBlocks,
+    delta: Option<MessageDelta>,
+    output_tokens: usize,
+    stopped: bool,
+}
+
+impl Recorder {
+    pub fn add(&mut self, event: &TextEvent) {
+        match event {
+            TextEvent::MessageStart { message } => self.start = Some(message.clone()),
+            TextEvent::MessageDelta { delta, usage } => {
+                self.delta = Some(delta.clone());
+                if let Some(usage) = usage {
+                    self.output_tokens = usage.output_tokens;
+                }
+            }
+            TextEvent::MessageStop => self.stopped = true,
+            _ => {}
+        }
+        self.text.add(event.clone());
+    }
+
+    /// The answer, if the stream got to its end.
+    pub fn finish(self) -> Option<Response> {
+        let start = self.start.filter(|_| self.stopped)?;
+        let delta = self.delta;
+        Some(Response {
+            stop_reason: delta.as_ref().and_then(|delta| delta.stop_reason.clone()),
+            stop_sequence: delta.and_then(|delta| delta.stop_sequence),
+            content: vec![Content::Text {
+                text: self.text.join(),
+            }],
+            model: start.model,
+            id: start.id,
+            type_: start.type_,
+            role: start.role,
+            usage: Usage {
+                input_tokens: start.usage.input_tokens,
+                output_tokens: self.output_tokens,
+            },
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn response() -> Response {
+        serde_json::from_str(
+            r#"{"id":"msg_01","type":"message","role":"assistant","model":"claude-3-5-sonnet-20240620","content":[{"type":"text","text":"Use ls."}],"stop_reason":"end_turn","stop_sequence":null,"usage":{"input_tokens":12,"output_tokens":4}}"#,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_cache() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = Cache::new(dir.path().to_path_buf(), None);
+        assert!(cache.get("abc").is_none());
+        cache.put("abc", &response()).unwrap();
+        assert_eq!(cache.get("abc").unwrap().id, "msg_01");
+
+        let expired = Cache::new(dir.path().to_path_buf(), Some(Duration::ZERO));
+        assert!(expired.get("abc").is_none());
+    }
+
+    #[test]
+    fn test_replay_records() {
+        let mut recorder = Recorder::default();
+        for event in replay(response()) {
+            recorder.add(&event);
+        }
+        let recorded = recorder.finish().unwrap();
+        assert_eq!(recorded.content[0].text().as_deref(), Some("Use ls."));
+        assert_eq!(recorded.usage, response().usage);
+        assert_eq!(recorded.stop_reason, response().stop_reason);
+
+        // Nothing to cache from a stream that broke off
+        let mut recorder = Recorder::default();
+        recorder.add(&replay(response())[0]);
+        assert!(recorder.finish().is_none());
+    }
+}
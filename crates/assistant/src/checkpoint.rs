@@ -0,0 +1,119 @@
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::{error::Error, pagetree::SerializedTree};
+
+/// Checkpoint file of a single session, kept in the state dir.
+#[derive(Debug)]
+pub struct Checkpoint {
+    path: PathBuf,
+    /// Held while the session runs so no other session takes the checkpoint over, and let go
+    /// of by the OS if it crashes.
+    _lock: Option<File>,
+}
+
+/// An exclusive lock on the lock file next to the checkpoint at `path`, unless another
+/// session holds it.
+fn lock(path: &Path) -> Option<File> {
+    let lock = path.with_extension("lock");
+    std::fs::create_dir_all(lock.parent()?).ok()?;
+    let file = File::options()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(lock)
+        .ok()?;
+    file.try_lock().ok()?;
+    Some(file)
+}
+
+impl Checkpoint {
+    fn dir() -> Option<PathBuf> {
+        rgpt_utils::paths::state_dir().map(|dir| dir.join("checkpoints"))
+    }
+
+    /// A new checkpoint, named after the start time and pid so concurrent sessions don't
+    /// clobber each other.
+    pub fn new() -> Option<Self> {
+        let started = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or_default();
+        let name = format!("{}-{}.json", started, std::process::id());
+        let path = Self::dir()?.join(name);
+        Some(Self {
+            _lock: lock(&path),
+            path,
+        })
+    }
+
+    pub fn from_path(path: PathBuf) -> Self {
+        Self {
+            _lock: lock(&path),
+            path,
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Most recently written checkpoint of a session that is no longer running, locked for
+    /// this one.
+    pub fn latest() -> Option<Self> {
+        let mut entries = std::fs::read_dir(Self::dir()?)
+            .ok()?
+            .filter_map(Result::ok)
+            .filter(|entry| entry.path().extension().is_some_and(|ext| ext == "json"))
+            .collect::<Vec<_>>();
+        entries.sort_by_key(|entry| entry.metadata().and_then(|m| m.modified()).ok());
+        entries.into_iter().rev().find_map(|entry| {
+            let path = entry.path();
+            Some(Self {
+                _lock: Some(lock(&path)?),
+                path,
+            })
+        })
+    }
+
+    pub fn save(&self, tree: &SerializedTree) -> Result<(), Error> {
+        if let Some(dir) = self.path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        rgpt_utils::fs::write_atomic(&self.path, &serde_json::to_vec(tree)?)?;
+        tracing::debug!("saved checkpoint {}", self.path.display());
+        Ok(())
+    }
+
+    pub fn load(&self) -> Result<SerializedTree, Error> {
+        Ok(serde_json::from_slice(&std::fs::read(&self.path)?)?)
+    }
+
+    pub fn remove(&self) -> Result<(), Error> {
+        for path in [self.path.clone(), self.path.with_extension("lock")] {
+            match std::fs::remove_file(path) {
+                Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e.into()),
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lock() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("1-1.json");
+        let checkpoint = Checkpoint::from_path(path.clone());
+        assert!(checkpoint._lock.is_some());
+        // Another running session's checkpoint isn't taken over
+        assert!(lock(&path).is_none());
+        drop(checkpoint);
+        assert!(lock(&path).is_some());
+    }
+}
@@ -0,0 +1,35 @@
+use crate::error::Error;
+
+/// System clipboard, if one is available.
+/// On X11 the copied text is only served while the clipboard is alive, so keep this around
+/// rather than creating one per copy.
+pub struct Clipboard {
+    inner: Option<arboard::Clipboard>,
+}
+
+impl Default for Clipboard {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Clipboard {
+    pub fn new() -> Self {
+        let inner = arboard::Clipboard::new()
+            .inspect_err(|e| tracing::warn!("no clipboard available: {}", e))
+            .ok();
+        Self { inner }
+    }
+
+    pub fn is_available(&self) -> bool {
+        self.inner.is_some()
+    }
+
+    pub fn set_text(&mut self, text: String) -> Result<(), Error> {
+        self.inner
+            .as_mut()
+            .ok_or_else(|| Error::Clipboard("no clipboard available".to_string()))?
+            .set_text(text)
+            .map_err(|e| Error::Clipboard(e.to_string()))
+    }
+}
@@ -1,13 +1,100 @@
-use rgpt_types::message::{Message, Role};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use rgpt_types::{
+    completion::{Request, Tool},
+    message::{Message, RichMessage, Role},
+};
 use serde::{Deserialize, Serialize};
 
+use crate::{error::Error, keymap::Keymap, palette::Palette};
+
+/// Label of the assistant's output when no persona is configured.
+pub const DEFAULT_PERSONA_LABEL: &str = "Assistant";
+
+/// Tool calls answered in a row before [`Assistant::run_tools`](crate::Assistant::run_tools)
+/// gives up.
+pub const DEFAULT_MAX_TOOL_ROUNDS: usize = 10;
+
+/// Times a request that isn't streamed is sent again after a transient error.
+pub const DEFAULT_RETRY_ATTEMPTS: usize = 2;
+
+/// Everything is optional in a config file, missing fields take their default.
 #[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default)]
 pub struct Config {
     pub messages: Option<Vec<Message>>,
     pub model: Option<String>,
     pub temperature: Option<f32>,
+    /// Nucleus sampling, the probability mass tokens are sampled from.
+    pub top_p: Option<f32>,
+    /// Sample from only this many of the most likely tokens.
+    pub top_k: Option<usize>,
+    /// Cap on the length of an answer, instead of the provider's default.
+    pub max_tokens: Option<usize>,
+    /// Text the answer ends before.
+    pub stop_sequences: Option<Vec<String>>,
     pub stream: bool,
     pub mode: Mode,
+    pub palette: Palette,
+    pub show_thinking: bool,
+    pub autosave: Option<Autosave>,
+    pub persona_label: Option<String>,
+    /// Restart a stream that drops mid-generation, continuing from the text received so far.
+    pub resume_on_drop: bool,
+    /// Where executed commands are logged, instead of the state dir.
+    pub audit_log: Option<PathBuf>,
+    /// Execute the first command in bash mode without asking. Only set from the command line, a
+    /// config file can't turn it on.
+    #[serde(skip)]
+    pub skip_confirm: bool,
+    /// Ask before running the command picked in bash mode.
+    pub confirm_execute: bool,
+    /// Offer to apply the diffs in the answer in code mode.
+    pub apply: bool,
+    /// Shell command the answer is piped through before it is printed in query mode.
+    pub post_process: Option<String>,
+    /// File the plain answer is also written to in query mode.
+    pub output: Option<PathBuf>,
+    pub format: Format,
+    /// Temperature 0, no resumed streams, and the request hash is shown so runs can be compared.
+    /// The API doesn't guarantee identical answers even then.
+    pub deterministic: bool,
+    /// Models a session prompt is sent to side by side, one sibling branch each.
+    pub compare_models: Vec<String>,
+    /// Archive finished sessions, keeping this many of the most recent.
+    pub keep_sessions: Option<usize>,
+    /// Answer a request sent before from the cache dir instead of the API.
+    pub cache: bool,
+    /// Minutes a cached answer is used for, forever if unset.
+    pub cache_ttl_minutes: Option<u64>,
+    /// Print the tokens a query used after its answer.
+    pub show_usage: bool,
+    /// No spinner while a query waits for an answer that isn't streamed.
+    pub quiet: bool,
+    /// Draw session answers as Markdown, with code blocks highlighted.
+    pub render_markdown: bool,
+    /// Show the focused pane, model and branch on a line under a session, with a spinner while
+    /// an answer streams in.
+    pub status_line: bool,
+    /// Keys of the session's actions.
+    pub keymap: Keymap,
+    /// Replaces the mode's system message.
+    pub system_prompt: Option<String>,
+    /// Sent after the system message, e.g. example exchanges.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub extra_messages: Vec<Message>,
+    /// Changes to the built-in modes, e.g. `[modes.dev]` in a config file.
+    #[serde(skip_serializing_if = "HashMap::is_empty")]
+    pub modes: HashMap<Mode, ModeConfig>,
+    /// Tools the model may call, sent with every request.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub tools: Vec<Tool>,
+    /// Rounds of tool calls `run_tools` answers before giving up.
+    pub max_tool_rounds: usize,
+    /// Times a request that isn't streamed is sent again after a timeout or a dropped
+    /// connection. Server errors are already retried with backoff by the client.
+    pub retry_attempts: usize,
 }
 
 impl Default for Config {
@@ -16,17 +103,66 @@ impl Default for Config {
             messages: None,
             model: None,
             temperature: None,
+            top_p: None,
+            top_k: None,
+            max_tokens: None,
+            stop_sequences: None,
             stream: true,
             mode: Mode::General,
+            palette: Palette::default(),
+            show_thinking: false,
+            autosave: None,
+            persona_label: None,
+            resume_on_drop: false,
+            audit_log: None,
+            skip_confirm: false,
+            confirm_execute: true,
+            apply: false,
+            post_process: None,
+            output: None,
+            format: Format::Text,
+            deterministic: false,
+            compare_models: Vec::new(),
+            keep_sessions: None,
+            cache: false,
+            cache_ttl_minutes: None,
+            show_usage: false,
+            quiet: false,
+            render_markdown: false,
+            status_line: false,
+            keymap: Keymap::default(),
+            system_prompt: None,
+            extra_messages: Vec::new(),
+            modes: HashMap::new(),
+            tools: Vec::new(),
+            max_tool_rounds: DEFAULT_MAX_TOOL_ROUNDS,
+            retry_attempts: DEFAULT_RETRY_ATTEMPTS,
         }
     }
 }
 
-#[derive(Debug, Copy, Clone, Default, Serialize, Deserialize, Eq, PartialEq)]
+/// Overrides of a built-in mode.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+#[serde(default)]
+pub struct ModeConfig {
+    /// Replaces the mode's system message.
+    pub system: Option<String>,
+}
+
+/// When a session writes a checkpoint, whichever comes first.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Autosave {
+    pub every_turns: Option<usize>,
+    pub every_minutes: Option<u64>,
+}
+
+#[derive(Debug, Copy, Clone, Default, Serialize, Deserialize, Eq, PartialEq, Hash)]
 #[serde(rename_all = "lowercase")]
 pub enum Mode {
     Dev,
     Bash,
+    /// Changes to code as unified diffs.
+    Code,
     #[default]
     General,
 }
@@ -36,16 +172,60 @@ impl From<&str> for Mode {
         match mode {
             "dev" => Mode::Dev,
             "bash" => Mode::Bash,
+            "code" => Mode::Code,
             _ => Mode::General,
         }
     }
 }
 
+/// Strict version of `From<&str>`, for user input.
+impl std::str::FromStr for Mode {
+    type Err = String;
+
+    fn from_str(mode: &str) -> Result<Self, Self::Err> {
+        match mode {
+            "general" => Ok(Mode::General),
+            "dev" | "bash" | "code" => Ok(mode.into()),
+            _ => Err(format!(
+                "unknown mode '{}', expected one of: general, dev, bash, code",
+                mode
+            )),
+        }
+    }
+}
+
+/// How query mode prints the answer.
+#[derive(Debug, Copy, Clone, Default, Serialize, Deserialize, Eq, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Format {
+    /// Streamed as colored text.
+    #[default]
+    Text,
+    /// One JSON object with the model, text, stop reason and usage, once complete.
+    Json,
+}
+
+impl std::str::FromStr for Format {
+    type Err = String;
+
+    fn from_str(format: &str) -> Result<Self, Self::Err> {
+        match format {
+            "text" => Ok(Format::Text),
+            "json" => Ok(Format::Json),
+            _ => Err(format!(
+                "unknown format '{}', expected one of: text, json",
+                format
+            )),
+        }
+    }
+}
+
 impl Mode {
     pub fn config(&self) -> Config {
         match self {
             Mode::Dev => dev_config(),
             Mode::Bash => bash_config(),
+            Mode::Code => code_config(),
             Mode::General => general_config(),
         }
     }
@@ -57,7 +237,40 @@ pub struct Builder {
     messages: Vec<Message>,
     model: Option<String>,
     temperature: Option<f32>,
+    top_p: Option<f32>,
+    top_k: Option<usize>,
+    max_tokens: Option<usize>,
+    stop_sequences: Option<Vec<String>>,
     stream: Option<bool>,
+    palette: Palette,
+    show_thinking: bool,
+    autosave: Option<Autosave>,
+    persona_label: Option<String>,
+    resume_on_drop: bool,
+    audit_log: Option<PathBuf>,
+    skip_confirm: bool,
+    confirm_execute: Option<bool>,
+    apply: bool,
+    post_process: Option<String>,
+    output: Option<PathBuf>,
+    format: Format,
+    deterministic: bool,
+    compare_models: Vec<String>,
+    keep_sessions: Option<usize>,
+    cache: bool,
+    cache_ttl_minutes: Option<u64>,
+    show_usage: bool,
+    quiet: bool,
+    render_markdown: bool,
+    status_line: bool,
+    keymap: Keymap,
+    system_prompt: Option<String>,
+    appended_system: Option<String>,
+    extra_messages: Vec<Message>,
+    modes: HashMap<Mode, ModeConfig>,
+    tools: Vec<Tool>,
+    max_tool_rounds: Option<usize>,
+    retry_attempts: Option<usize>,
 }
 
 impl Builder {
@@ -86,46 +299,392 @@ impl Builder {
         self
     }
 
+    pub fn top_p(mut self, top_p: Option<f32>) -> Self {
+        self.top_p = top_p;
+        self
+    }
+
+    pub fn top_k(mut self, top_k: Option<usize>) -> Self {
+        self.top_k = top_k;
+        self
+    }
+
+    pub fn max_tokens(mut self, max_tokens: Option<usize>) -> Self {
+        self.max_tokens = max_tokens;
+        self
+    }
+
+    pub fn stop_sequences(mut self, stop_sequences: Option<Vec<String>>) -> Self {
+        self.stop_sequences = stop_sequences;
+        self
+    }
+
     pub fn stream(mut self, stream: bool) -> Self {
         self.stream = Some(stream);
         self
     }
 
+    pub fn palette(mut self, palette: Palette) -> Self {
+        self.palette = palette;
+        self
+    }
+
+    pub fn show_thinking(mut self, show_thinking: bool) -> Self {
+        self.show_thinking = show_thinking;
+        self
+    }
+
+    pub fn autosave(mut self, autosave: Option<Autosave>) -> Self {
+        self.autosave = autosave;
+        self
+    }
+
+    pub fn persona_label(mut self, persona_label: Option<String>) -> Self {
+        self.persona_label = persona_label;
+        self
+    }
+
+    pub fn resume_on_drop(mut self, resume_on_drop: bool) -> Self {
+        self.resume_on_drop = resume_on_drop;
+        self
+    }
+
+    pub fn audit_log(mut self, audit_log: Option<PathBuf>) -> Self {
+        self.audit_log = audit_log;
+        self
+    }
+
+    pub fn skip_confirm(mut self, skip_confirm: bool) -> Self {
+        self.skip_confirm = skip_confirm;
+        self
+    }
+
+    pub fn confirm_execute(mut self, confirm_execute: bool) -> Self {
+        self.confirm_execute = Some(confirm_execute);
+        self
+    }
+
+    pub fn apply(mut self, apply: bool) -> Self {
+        self.apply = apply;
+        self
+    }
+
+    pub fn post_process(mut self, post_process: Option<String>) -> Self {
+        self.post_process = post_process;
+        self
+    }
+
+    pub fn output(mut self, output: Option<PathBuf>) -> Self {
+        self.output = output;
+        self
+    }
+
+    pub fn format(mut self, format: Format) -> Self {
+        self.format = format;
+        self
+    }
+
+    pub fn deterministic(mut self, deterministic: bool) -> Self {
+        self.deterministic = deterministic;
+        self
+    }
+
+    pub fn compare_models(mut self, compare_models: Vec<String>) -> Self {
+        self.compare_models = compare_models;
+        self
+    }
+
+    pub fn keep_sessions(mut self, keep_sessions: Option<usize>) -> Self {
+        self.keep_sessions = keep_sessions;
+        self
+    }
+
+    pub fn cache(mut self, cache: bool) -> Self {
+        self.cache = cache;
+        self
+    }
+
+    pub fn cache_ttl_minutes(mut self, cache_ttl_minutes: Option<u64>) -> Self {
+        self.cache_ttl_minutes = cache_ttl_minutes;
+        self
+    }
+
+    pub fn show_usage(mut self, show_usage: bool) -> Self {
+        self.show_usage = show_usage;
+        self
+    }
+
+    pub fn quiet(mut self, quiet: bool) -> Self {
+        self.quiet = quiet;
+        self
+    }
+
+    pub fn render_markdown(mut self, render_markdown: bool) -> Self {
+        self.render_markdown = render_markdown;
+        self
+    }
+
+    pub fn status_line(mut self, status_line: bool) -> Self {
+        self.status_line = status_line;
+        self
+    }
+
+    pub fn keymap(mut self, keymap: Keymap) -> Self {
+        self.keymap = keymap;
+        self
+    }
+
+    pub fn system_prompt(mut self, system_prompt: Option<String>) -> Self {
+        self.system_prompt = system_prompt;
+        self
+    }
+
+    /// Text added to the end of the system prompt, such as a template's system message.
+    pub fn append_system(mut self, system: String) -> Self {
+        self.appended_system = Some(system);
+        self
+    }
+
+    pub fn extra_messages(mut self, extra_messages: Vec<Message>) -> Self {
+        self.extra_messages = extra_messages;
+        self
+    }
+
+    pub fn modes(mut self, modes: HashMap<Mode, ModeConfig>) -> Self {
+        self.modes = modes;
+        self
+    }
+
+    pub fn tools(mut self, tools: Vec<Tool>) -> Self {
+        self.tools = tools;
+        self
+    }
+
+    pub fn max_tool_rounds(mut self, max_tool_rounds: usize) -> Self {
+        self.max_tool_rounds = Some(max_tool_rounds);
+        self
+    }
+
+    pub fn retry_attempts(mut self, retry_attempts: usize) -> Self {
+        self.retry_attempts = Some(retry_attempts);
+        self
+    }
+
+    /// The messages, with the system message replaced if the mode's config has one.
+    fn messages_for_mode(&self) -> Vec<Message> {
+        match self
+            .modes
+            .get(&self.mode)
+            .and_then(|mode| mode.system.clone())
+        {
+            Some(system) => with_system(self.messages.clone(), system),
+            None => self.messages.clone(),
+        }
+    }
+
     pub fn build(self) -> Config {
+        let messages = self.messages_for_mode();
+        // Added to the system message there is, so the request still has a single one
+        let system_prompt = match self.appended_system {
+            Some(appended) => match self.system_prompt.or_else(|| {
+                messages
+                    .iter()
+                    .find(|message| message.role == Role::System)
+                    .map(|message| message.content.clone())
+            }) {
+                Some(system) => Some(format!("{}\n\n{}", system, appended)),
+                None => Some(appended),
+            },
+            None => self.system_prompt,
+        };
         Config {
-            messages: Some(self.messages),
+            messages: Some(messages),
             model: self.model,
-            temperature: self.temperature,
+            temperature: match self.deterministic {
+                true => Some(0.0),
+                false => self.temperature,
+            },
+            top_p: self.top_p,
+            top_k: self.top_k,
+            max_tokens: self.max_tokens,
+            stop_sequences: self.stop_sequences,
             stream: self.stream.unwrap_or(Config::default().stream),
             mode: self.mode,
+            palette: self.palette,
+            show_thinking: self.show_thinking,
+            autosave: self.autosave,
+            persona_label: self.persona_label,
+            resume_on_drop: self.resume_on_drop && !self.deterministic,
+            audit_log: self.audit_log,
+            skip_confirm: self.skip_confirm,
+            confirm_execute: self
+                .confirm_execute
+                .unwrap_or(Config::default().confirm_execute),
+            apply: self.apply,
+            post_process: self.post_process,
+            output: self.output,
+            format: self.format,
+            deterministic: self.deterministic,
+            compare_models: self.compare_models,
+            keep_sessions: self.keep_sessions,
+            cache: self.cache,
+            cache_ttl_minutes: self.cache_ttl_minutes,
+            show_usage: self.show_usage,
+            quiet: self.quiet,
+            render_markdown: self.render_markdown,
+            status_line: self.status_line,
+            keymap: self.keymap,
+            system_prompt,
+            extra_messages: self.extra_messages,
+            modes: self.modes,
+            tools: self.tools,
+            max_tool_rounds: self
+                .max_tool_rounds
+                .unwrap_or(Config::default().max_tool_rounds),
+            retry_attempts: self
+                .retry_attempts
+                .unwrap_or(Config::default().retry_attempts),
         }
     }
 }
 
+/// Settings of a single request that take the place of the config's, for callers that send
+/// turns to different models. Unset ones are left to the config.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RequestOverrides {
+    pub model: Option<String>,
+    pub temperature: Option<f32>,
+    pub max_tokens: Option<usize>,
+    pub stream: Option<bool>,
+}
+
 impl Config {
     pub fn builder() -> Builder {
         Builder::new()
     }
+
+    /// The config with `overrides` applied.
+    pub fn overridden(&self, overrides: RequestOverrides) -> Config {
+        Config {
+            model: overrides.model.or(self.model.clone()),
+            temperature: overrides.temperature.or(self.temperature),
+            max_tokens: overrides.max_tokens.or(self.max_tokens),
+            stream: overrides.stream.unwrap_or(self.stream),
+            ..self.clone()
+        }
+    }
+
+    /// The messages every conversation starts with: the mode's, with `system_prompt` and
+    /// `extra_messages` applied.
+    pub fn init_messages(&self) -> Vec<Message> {
+        let mut messages = self.messages.clone().unwrap_or_default();
+        if let Some(system) = &self.system_prompt {
+            messages = with_system(messages, system.clone());
+        }
+        let at = match messages.first() {
+            Some(message) if message.role == Role::System => 1,
+            _ => 0,
+        };
+        messages.splice(at..at, self.extra_messages.iter().cloned());
+        messages
+    }
+
+    /// The request for `messages` with the configured model and sampling.
+    pub fn request<M: Into<RichMessage>>(&self, messages: Vec<M>) -> Request {
+        let messages = messages.into_iter().map(Into::into).collect::<Vec<_>>();
+        let has_system = messages.iter().any(|message| message.role == Role::System);
+        let mut builder = Request::builder()
+            .messages(messages)
+            .temperature(self.temperature)
+            .top_p(self.top_p)
+            .top_k(self.top_k)
+            .stop_sequences(self.stop_sequences.clone())
+            .stream(self.stream);
+        if let Some(max_tokens) = self.max_tokens {
+            builder = builder.max_tokens(max_tokens);
+        }
+        if let Some(model) = &self.model {
+            builder = builder.model(model.clone());
+        }
+        // A system message is already the prompt, maybe edited in the session, so it is left alone
+        if !has_system {
+            if let Some(system) = &self.system_prompt {
+                builder = builder.system(system.clone());
+            }
+        }
+        if !self.tools.is_empty() {
+            builder = builder.tools(Some(self.tools.clone()));
+        }
+        builder.build()
+    }
+
+    /// `config.toml` in the config dir.
+    pub fn default_path() -> Option<PathBuf> {
+        rgpt_utils::paths::config_dir().map(|dir| dir.join("config.toml"))
+    }
+
+    /// Read a TOML config file, or JSON if it has a `.json` extension.
+    pub fn from_file(path: &Path) -> Result<Self, Error> {
+        let contents = std::fs::read_to_string(path)?;
+        match path.extension().is_some_and(|ext| ext == "json") {
+            true => Ok(serde_json::from_str(&contents)?),
+            false => toml::from_str(&contents)
+                .map_err(|e| Error::Config(format!("{}: {}", path.display(), e))),
+        }
+    }
+
+    /// The config at `path`, or else at the default path if there is one there.
+    pub fn load(path: Option<&Path>) -> Result<Self, Error> {
+        if let Some(path) = path {
+            return Self::from_file(path);
+        }
+        match Self::default_path() {
+            Some(path) if path.exists() => Self::from_file(&path),
+            _ => Ok(Self::default()),
+        }
+    }
+
+    pub fn persona_label(&self) -> &str {
+        self.persona_label
+            .as_deref()
+            .unwrap_or(DEFAULT_PERSONA_LABEL)
+    }
+}
+
+/// `messages` with its system message replaced by `system`, or `system` first if it had none.
+fn with_system(mut messages: Vec<Message>, system: String) -> Vec<Message> {
+    match messages
+        .iter_mut()
+        .find(|message| message.role == Role::System)
+    {
+        Some(message) => message.content = system,
+        None => messages.insert(0, Message::system(system)),
+    }
+    messages
 }
 
 fn dev_config() -> Config {
     Config {
         messages: Some(vec![
-            Message {
-                role: Role::System,
-                content: format!("You are a helpful assistant who is an expert in software development. \
+            Message::system(format!("You are a helpful assistant who is an expert in software development. \
                 You are helping a user who is a software developer. Your responses are short and concise. \
                 You include code snippets when appropriate. Code snippets are formatted using Markdown \
-                with a correct language tag. User's `uname`: {}", std::env::consts::OS),
-            },
-            Message {
-                role: Role::User,
-                content: "Your responses must be short and concise. Do not include explanations unless asked.".to_string(),
-            },
-            Message {
-                role: Role::Assistant,
-                content: "Understood.".to_string(),
-            },
+                with a correct language tag. User's `uname`: {}", std::env::consts::OS)),
+            Message::user("Your responses must be short and concise. Do not include explanations unless asked."),
+            Message::assistant("Understood."),
+        ]),
+        ..Default::default()
+    }
+}
+
+fn code_config() -> Config {
+    Config {
+        messages: Some(vec![
+            Message::system(format!("You are an expert software developer changing code in the user's working directory. \
+                Answer with unified diffs in ```diff fenced code blocks, with `--- a/<path>` and `+++ b/<path>` headers \
+                relative to the working directory and correct `@@` hunk headers with a few lines of context. \
+                Use `--- /dev/null` for new files. Keep any explanation short and outside the diffs. \
+                User's `uname`: {}", std::env::consts::OS)),
         ]),
         ..Default::default()
     }
@@ -140,23 +699,140 @@ pub fn general_config() -> Config {
 fn bash_config() -> Config {
     Config {
         messages: Some(vec![
-            Message {
-                role: Role::System,
-                content: format!("You output only valid and correct shell commands according to the user's prompt. \
+            Message::system(format!("You output only valid and correct shell commands according to the user's prompt. \
                 You don't provide any explanations or any other text that is not valid shell commands. \
                 If there is a lack of details, provide most logical solution.
                 Ensure the output is a valid shell command.
                 Never ever respond with something other than a shell command.
-                Provide only plain text without markdown formatting.
-                Do not provide formatting such as ```.
+                Put each command in its own fenced code block tagged with the shell, e.g. ```bash.
                 If multiple steps required, try to combine them together using &&.
-                If multiple options are possible, separate them with a newline.
-                If a command requires a newline, use a backslash at the end of the line.
+                If multiple options are possible, give each option its own code block.
+                A command that spans multiple lines, such as a loop, stays in a single code block.
                 User's `uname`: {}. User's `$SHELL`: {}.",
                 std::env::consts::OS,
-                std::env::var("SHELL").unwrap_or_else(|_| "Unknown".to_string())),
-            },
+                std::env::var("SHELL").unwrap_or_else(|_| "Unknown".to_string()))),
         ]),
         ..Default::default()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("config.toml");
+        std::fs::write(
+            &path,
+            r#"
+model = "claude-3-5-haiku-latest"
+stream = false
+skip_confirm = true
+
+[modes.bash]
+system = "Only output fish shell commands."
+"#,
+        )
+        .unwrap();
+        let config = Config::from_file(&path).unwrap();
+        assert_eq!(config.model.as_deref(), Some("claude-3-5-haiku-latest"));
+        assert!(!config.stream);
+        assert!(!config.skip_confirm);
+        assert_eq!(config.mode, Mode::General);
+
+        let built = Config::builder()
+            .modes(config.modes)
+            .mode(Mode::Bash)
+            .build();
+        let messages = built.messages.unwrap();
+        assert_eq!(messages[0].role, Role::System);
+        assert_eq!(messages[0].content, "Only output fish shell commands.");
+    }
+
+    #[test]
+    fn test_mode_from_str() {
+        assert_eq!("bash".parse::<Mode>(), Ok(Mode::Bash));
+        assert_eq!("general".parse::<Mode>(), Ok(Mode::General));
+        let error = "bsh".parse::<Mode>().unwrap_err();
+        assert!(error.contains("general, dev, bash, code"));
+    }
+
+    #[test]
+    fn test_init_messages() {
+        let example = Message::user("list files");
+        let config = Config::builder()
+            .mode(Mode::Bash)
+            .system_prompt(Some("Answer in plain English.".to_string()))
+            .extra_messages(vec![example.clone()])
+            .build();
+        let messages = config.init_messages();
+        assert_eq!(messages.len(), 2);
+        assert_eq!(messages[0].role, Role::System);
+        assert_eq!(messages[0].content, "Answer in plain English.");
+        assert_eq!(messages[1], example);
+
+        let config = Config::builder()
+            .extra_messages(vec![example.clone()])
+            .build();
+        assert_eq!(config.init_messages(), vec![example]);
+    }
+
+    #[test]
+    fn test_append_system() {
+        let config = Config::builder()
+            .mode(Mode::Bash)
+            .append_system("Review the diff.".to_string())
+            .build();
+        let messages = config.init_messages();
+        let system = messages
+            .iter()
+            .filter(|message| message.role == Role::System)
+            .collect::<Vec<_>>();
+        assert_eq!(system.len(), 1);
+        assert!(
+            system[0].content.starts_with("You output only valid"),
+            "{}",
+            system[0].content
+        );
+        assert!(system[0].content.ends_with("\n\nReview the diff."));
+
+        let config = Config::builder()
+            .system_prompt(Some("Be brief.".to_string()))
+            .append_system("Review the diff.".to_string())
+            .build();
+        assert_eq!(
+            config.init_messages()[0].content,
+            "Be brief.\n\nReview the diff."
+        );
+    }
+
+    #[test]
+    fn test_overridden() {
+        let config = Config::builder()
+            .model("claude-3-5-sonnet-latest".to_string())
+            .temperature(Some(0.2))
+            .max_tokens(Some(1024))
+            .stream(true)
+            .build();
+        let overrides = RequestOverrides {
+            model: Some("claude-3-5-haiku-latest".to_string()),
+            stream: Some(false),
+            ..Default::default()
+        };
+        let request = config
+            .overridden(overrides)
+            .request(vec![Message::user("hi")]);
+        assert_eq!(request.model.as_deref(), Some("claude-3-5-haiku-latest"));
+        assert!(!request.stream);
+        assert_eq!(request.temperature, Some(0.2));
+        assert_eq!(request.max_tokens, 1024);
+
+        let request = config
+            .overridden(RequestOverrides::default())
+            .request(vec![Message::user("hi")]);
+        assert_eq!(request.model.as_deref(), Some("claude-3-5-sonnet-latest"));
+        assert!(request.stream);
+    }
+}
@@ -1,10 +1,15 @@
+pub use rgpt_provider::error::ApiErrorKind;
+
 #[derive(thiserror::Error, Debug)]
 pub enum Error {
-    #[error("No api key")]
-    NoApiKey,
+    #[error("No api key, tried: {0}")]
+    NoApiKey(String),
+
+    #[error("Your {key} was rejected ({status})")]
+    Authentication { key: &'static str, status: u16 },
 
     #[error("Provider error: {0}")]
-    Provider(#[from] rgpt_provider::error::Error),
+    Provider(rgpt_provider::error::Error),
 
     #[error("IO error: {0}")]
     Io(#[from] std::io::Error),
@@ -30,6 +35,52 @@ pub enum Error {
     #[error("Dialoguer error")]
     Dialoguer(#[from] dialoguer::Error),
 
+    #[error("JSON error: {0}")]
+    Json(#[from] serde_json::Error),
+
+    #[error("Config error: {0}")]
+    Config(String),
+
+    #[error("Template error: {0}")]
+    Template(String),
+
+    #[error("Post-processing error: {0}")]
+    PostProcess(String),
+
+    #[error("Can't write the answer to {path}: {source}")]
+    Output {
+        path: std::path::PathBuf,
+        source: std::io::Error,
+    },
+
+    #[error("Patch error: {0}")]
+    Patch(String),
+
+    #[error("The answer ended before it was complete")]
+    Incomplete,
+
+    #[error("Tool calls still going after {0} rounds")]
+    ToolLoopLimit(usize),
+
+    #[error("Clipboard error: {0}")]
+    Clipboard(String),
+
     #[error("Generic {0}")]
     Generic(String),
 }
+
+impl From<rgpt_provider::error::Error> for Error {
+    fn from(e: rgpt_provider::error::Error) -> Self {
+        match e {
+            rgpt_provider::error::Error::Authentication {
+                key,
+                status,
+                message,
+            } => {
+                tracing::debug!("{} rejected: {}", key, message);
+                Error::Authentication { key, status }
+            }
+            e => Error::Provider(e),
+        }
+    }
+}
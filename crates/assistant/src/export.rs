@@ -0,0 +1,98 @@
+use rgpt_types::message::{Message, Role};
+
+use crate::pagetree::{MessageMeta, Turn};
+
+fn heading(role: Role) -> &'static str {
+    match role {
+        Role::System => "System",
+        Role::User => "User",
+        Role::Assistant => "Assistant",
+    }
+}
+
+/// `YYYY-MM-DDTHH:MM:SSZ` for a Unix timestamp.
+/// Ref: https://howardhinnant.github.io/date_algorithms.html#civil_from_days
+pub(crate) fn rfc3339(secs: u64) -> String {
+    let (days, secs) = ((secs / 86400) as i64, secs % 86400);
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        secs / 3600,
+        secs / 60 % 60,
+        secs % 60
+    )
+}
+
+/// Render a conversation as Markdown, one section per message.
+/// Messages without content are skipped.
+pub fn markdown(messages: &[Message]) -> String {
+    let turns = messages
+        .iter()
+        .map(|message| (message.clone(), None))
+        .collect::<Vec<_>>();
+    markdown_turns(&turns)
+}
+
+/// Same as [`markdown`], with the time of each message that has one in its heading.
+pub fn markdown_turns(turns: &[Turn]) -> String {
+    turns
+        .iter()
+        .filter(|(message, _)| !message.content.trim().is_empty())
+        .map(|(message, meta)| {
+            let time = meta
+                .map(|MessageMeta { created_at }| format!(" ({})", rfc3339(created_at)))
+                .unwrap_or_default();
+            format!(
+                "## {}{}\n\n{}\n",
+                heading(message.role),
+                time,
+                message.content.trim()
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_markdown() {
+        let messages = vec![
+            Message::system(""),
+            Message::user("How can I list the files in this directory?"),
+            Message::assistant("```bash\nls\n```"),
+        ];
+        assert_eq!(
+            markdown(&messages),
+            "## User\n\nHow can I list the files in this directory?\n\n## Assistant\n\n```bash\nls\n```\n"
+        );
+    }
+
+    #[test]
+    fn test_markdown_turns() {
+        let turns = vec![(
+            Message::user("ls"),
+            Some(MessageMeta {
+                created_at: 1_700_000_000,
+            }),
+        )];
+        assert_eq!(
+            markdown_turns(&turns),
+            "## User (2023-11-14T22:13:20Z)\n\nls\n"
+        );
+        assert_eq!(rfc3339(0), "1970-01-01T00:00:00Z");
+    }
+}
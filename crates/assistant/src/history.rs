@@ -0,0 +1,201 @@
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use rgpt_utils::fs::write_atomic;
+use serde::{Deserialize, Serialize};
+
+use crate::{error::Error, export::rfc3339, pagetree::SerializedTree};
+
+/// How many sessions are kept when no limit is configured.
+pub const DEFAULT_LIMIT: usize = 20;
+
+/// Longest title shown in the list, in chars.
+const TITLE_LENGTH: usize = 60;
+
+/// Archive of finished sessions in the state dir, with an index of the most recent ones.
+#[derive(Debug, Clone)]
+pub struct History {
+    dir: PathBuf,
+    limit: usize,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct HistoryEntry {
+    /// Name of the session, which is also its file name without the extension.
+    pub id: String,
+    pub title: String,
+    /// Seconds since the Unix epoch.
+    pub saved_at: u64,
+    pub turns: usize,
+}
+
+impl std::fmt::Display for HistoryEntry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} · {} · {} turns",
+            self.title,
+            rfc3339(self.saved_at),
+            self.turns
+        )
+    }
+}
+
+impl History {
+    pub fn new(dir: PathBuf, limit: usize) -> Self {
+        Self { dir, limit }
+    }
+
+    /// `history/` in the state dir.
+    pub fn open(limit: usize) -> Option<Self> {
+        rgpt_utils::paths::state_dir().map(|dir| Self::new(dir.join("history"), limit))
+    }
+
+    /// Name for a new session, after the start time and pid so concurrent sessions don't
+    /// clobber each other.
+    pub fn new_id() -> String {
+        format!("{}-{}", now(), std::process::id())
+    }
+
+    fn index_path(&self) -> PathBuf {
+        self.dir.join("index.json")
+    }
+
+    fn session_path(&self, id: &str) -> PathBuf {
+        self.dir.join(format!("{}.json", id))
+    }
+
+    /// Saved sessions, most recent first.
+    pub fn entries(&self) -> Result<Vec<HistoryEntry>, Error> {
+        match std::fs::read(self.index_path()) {
+            Ok(index) => Ok(serde_json::from_slice(&index)?),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(vec![]),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn write_entries(&self, entries: &[HistoryEntry]) -> Result<(), Error> {
+        Ok(write_atomic(
+            &self.index_path(),
+            &serde_json::to_vec(entries)?,
+        )?)
+    }
+
+    /// Save the session under `id`, replacing an earlier save of it, and drop the oldest
+    /// sessions past the limit.
+    pub fn save(&self, id: &str, tree: &SerializedTree) -> Result<(), Error> {
+        std::fs::create_dir_all(&self.dir)?;
+        write_atomic(&self.session_path(id), &serde_json::to_vec(tree)?)?;
+
+        let mut entries = self.entries()?;
+        entries.retain(|entry| entry.id != id);
+        entries.insert(
+            0,
+            HistoryEntry {
+                id: id.to_string(),
+                title: title(tree),
+                saved_at: now(),
+                turns: tree.nodes.iter().filter(|node| node.locked).count(),
+            },
+        );
+        let pruned = entries.split_off(entries.len().min(self.limit));
+        self.write_entries(&entries)?;
+        for entry in pruned {
+            self.remove_file(&entry.id)?;
+        }
+        tracing::debug!("saved session {} to history", id);
+        Ok(())
+    }
+
+    pub fn load(&self, entry: &HistoryEntry) -> Result<SerializedTree, Error> {
+        Ok(serde_json::from_slice(&std::fs::read(
+            self.session_path(&entry.id),
+        )?)?)
+    }
+
+    /// Delete the session and its entry.
+    pub fn remove(&self, entry: &HistoryEntry) -> Result<(), Error> {
+        let mut entries = self.entries()?;
+        entries.retain(|e| e.id != entry.id);
+        self.write_entries(&entries)?;
+        self.remove_file(&entry.id)
+    }
+
+    fn remove_file(&self, id: &str) -> Result<(), Error> {
+        match std::fs::remove_file(self.session_path(id)) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+            _ => Ok(()),
+        }
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+/// First line of the first prompt.
+fn title(tree: &SerializedTree) -> String {
+    let first = tree
+        .nodes
+        .iter()
+        .flat_map(|node| node.user.iter())
+        .map(|line| line.trim())
+        .find(|line| !line.is_empty())
+        .unwrap_or("(empty)");
+    match first.char_indices().nth(TITLE_LENGTH) {
+        Some((end, _)) => format!("{}…", &first[..end]),
+        None => first.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pagetree::{NodeId, SerializedNode};
+
+    fn tree(prompt: &str) -> SerializedTree {
+        SerializedTree {
+            system: vec![],
+            nodes: vec![SerializedNode {
+                id: NodeId::Node(0),
+                parent: NodeId::Root,
+                user: vec![prompt.to_string()],
+                assistant: vec!["answer".to_string()],
+                locked: true,
+                user_meta: None,
+                assistant_meta: None,
+                model: None,
+            }],
+            current: NodeId::Node(0),
+        }
+    }
+
+    #[test]
+    fn test_save_prune_remove() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        let history = History::new(dir.to_path_buf(), 2);
+
+        history.save("a", &tree("first")).unwrap();
+        history.save("b", &tree("second")).unwrap();
+        history.save("a", &tree("first, again")).unwrap();
+        let entries = history.entries().unwrap();
+        assert_eq!(
+            entries.iter().map(|e| e.id.as_str()).collect::<Vec<_>>(),
+            ["a", "b"]
+        );
+        assert_eq!(entries[0].title, "first, again");
+        assert_eq!(entries[0].turns, 1);
+        assert_eq!(history.load(&entries[0]).unwrap(), tree("first, again"));
+
+        history.save("c", &tree("third")).unwrap();
+        assert!(!dir.join("b.json").exists());
+
+        history.remove(&history.entries().unwrap()[0]).unwrap();
+        assert!(!dir.join("c.json").exists());
+        assert_eq!(history.entries().unwrap().len(), 1);
+    }
+}
@@ -0,0 +1,352 @@
+use serde::{Deserialize, Serialize};
+use tui_textarea::{Input, Key};
+
+use crate::error::Error;
+
+/// What a key does in a session.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    Quit,
+    /// Stop the answers streaming in, or quit if there are none.
+    Stop,
+    SwitchPane,
+    NewBranch,
+    NextBranch,
+    PreviousBranch,
+    Up,
+    Down,
+    YankConversation,
+    YankAnswer,
+    Undo,
+    Redo,
+    /// Fork the sent node on screen to edit its prompt.
+    EditPrompt,
+    Continue,
+    ToggleHscroll,
+    Save,
+    ToggleDiff,
+    Retry,
+    Prefill,
+    Submit,
+    CompareModels,
+}
+
+/// A key with its modifiers, written like `"ctrl-shift-y"`, `"alt-enter"` or `"esc"`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeyBinding {
+    pub key: Key,
+    pub ctrl: bool,
+    pub alt: bool,
+    pub shift: bool,
+}
+
+impl KeyBinding {
+    const fn ctrl(c: char) -> Self {
+        KeyBinding {
+            key: Key::Char(c),
+            ctrl: true,
+            alt: false,
+            shift: false,
+        }
+    }
+
+    const fn alt(c: char) -> Self {
+        KeyBinding {
+            key: Key::Char(c),
+            ctrl: false,
+            alt: true,
+            shift: false,
+        }
+    }
+
+    const fn plain(key: Key) -> Self {
+        KeyBinding {
+            key,
+            ctrl: false,
+            alt: false,
+            shift: false,
+        }
+    }
+
+    /// Without keyboard enhancement, most terminals report a shifted letter as uppercase, with
+    /// or without the shift, so letters are compared that way.
+    fn normalized(mut self) -> Self {
+        if let Key::Char(c) = self.key {
+            if self.shift || c.is_ascii_uppercase() {
+                self.key = Key::Char(c.to_ascii_uppercase());
+                self.shift = false;
+            }
+        }
+        self
+    }
+
+    pub fn matches(&self, input: &Input) -> bool {
+        let input = KeyBinding {
+            key: input.key,
+            ctrl: input.ctrl,
+            alt: input.alt,
+            shift: input.shift,
+        };
+        self.normalized() == input.normalized()
+    }
+}
+
+impl std::str::FromStr for KeyBinding {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || Error::Generic(format!("invalid key binding '{}'", s));
+        let mut binding = KeyBinding::plain(Key::Null);
+        let mut parts = s.split('-').peekable();
+        while let Some(part) = parts.next() {
+            // A trailing '-' is the key itself, as in "ctrl--"
+            let part = match (part, parts.peek()) {
+                ("", Some(&"")) => {
+                    parts.next();
+                    "-"
+                }
+                (part, _) => part,
+            };
+            if parts.peek().is_some() {
+                match part.to_lowercase().as_str() {
+                    "ctrl" => binding.ctrl = true,
+                    "alt" => binding.alt = true,
+                    "shift" => binding.shift = true,
+                    _ => return Err(invalid()),
+                }
+                continue;
+            }
+            binding.key = match part.to_lowercase().as_str() {
+                "esc" => Key::Esc,
+                "tab" => Key::Tab,
+                "enter" => Key::Enter,
+                "backspace" => Key::Backspace,
+                "delete" => Key::Delete,
+                "up" => Key::Up,
+                "down" => Key::Down,
+                "left" => Key::Left,
+                "right" => Key::Right,
+                "home" => Key::Home,
+                "end" => Key::End,
+                "pageup" => Key::PageUp,
+                "pagedown" => Key::PageDown,
+                "space" => Key::Char(' '),
+                f if f.len() > 1 && f.starts_with('f') => {
+                    Key::F(f[1..].parse().map_err(|_| invalid())?)
+                }
+                _ => {
+                    let mut chars = part.chars();
+                    match (chars.next(), chars.next()) {
+                        (Some(c), None) => Key::Char(c),
+                        _ => return Err(invalid()),
+                    }
+                }
+            };
+        }
+        match binding.key {
+            Key::Null => Err(invalid()),
+            _ => Ok(binding),
+        }
+    }
+}
+
+impl std::fmt::Display for KeyBinding {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (on, modifier) in [
+            (self.ctrl, "ctrl-"),
+            (self.alt, "alt-"),
+            (self.shift, "shift-"),
+        ] {
+            if on {
+                write!(f, "{}", modifier)?;
+            }
+        }
+        match self.key {
+            Key::Char(' ') => write!(f, "space"),
+            Key::Char(c) => write!(f, "{}", c),
+            Key::F(n) => write!(f, "f{}", n),
+            Key::Esc => write!(f, "esc"),
+            Key::Tab => write!(f, "tab"),
+            Key::Enter => write!(f, "enter"),
+            Key::Backspace => write!(f, "backspace"),
+            Key::Delete => write!(f, "delete"),
+            Key::Up => write!(f, "up"),
+            Key::Down => write!(f, "down"),
+            Key::Left => write!(f, "left"),
+            Key::Right => write!(f, "right"),
+            Key::Home => write!(f, "home"),
+            Key::End => write!(f, "end"),
+            Key::PageUp => write!(f, "pageup"),
+            Key::PageDown => write!(f, "pagedown"),
+            key => write!(f, "{:?}", key),
+        }
+    }
+}
+
+impl Serialize for KeyBinding {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
+impl<'de> Deserialize<'de> for KeyBinding {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(serde::de::Error::custom)
+    }
+}
+
+/// Keys of the session's actions, e.g. `submit = "alt-enter"` under `[keymap]` in a config
+/// file. Branches are still jumped to with Alt-1 to Alt-9, and answers paged with
+/// PageUp/PageDown. When two actions share a key, the one listed first here wins.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(default)]
+pub struct Keymap {
+    pub quit: KeyBinding,
+    pub stop: KeyBinding,
+    pub submit: KeyBinding,
+    pub prefill: KeyBinding,
+    pub compare_models: KeyBinding,
+    pub switch_pane: KeyBinding,
+    pub new_branch: KeyBinding,
+    pub next_branch: KeyBinding,
+    pub previous_branch: KeyBinding,
+    pub up: KeyBinding,
+    pub down: KeyBinding,
+    pub yank_conversation: KeyBinding,
+    pub yank_answer: KeyBinding,
+    pub undo: KeyBinding,
+    pub redo: KeyBinding,
+    pub edit_prompt: KeyBinding,
+    pub continue_answer: KeyBinding,
+    pub toggle_hscroll: KeyBinding,
+    pub save: KeyBinding,
+    pub toggle_diff: KeyBinding,
+    pub retry: KeyBinding,
+}
+
+impl Default for Keymap {
+    fn default() -> Self {
+        Keymap {
+            quit: KeyBinding::plain(Key::Esc),
+            stop: KeyBinding::ctrl('c'),
+            submit: KeyBinding::ctrl('j'),
+            prefill: KeyBinding::ctrl('g'),
+            compare_models: KeyBinding::ctrl('o'),
+            switch_pane: KeyBinding::plain(Key::Tab),
+            new_branch: KeyBinding::ctrl('b'),
+            next_branch: KeyBinding::ctrl('n'),
+            previous_branch: KeyBinding::ctrl('p'),
+            up: KeyBinding::ctrl('u'),
+            down: KeyBinding::ctrl('d'),
+            yank_conversation: KeyBinding::ctrl('Y'),
+            yank_answer: KeyBinding::ctrl('y'),
+            undo: KeyBinding::ctrl('z'),
+            // Ctrl-Shift-Z is Ctrl-Z to most terminals
+            redo: KeyBinding::alt('z'),
+            edit_prompt: KeyBinding::ctrl('e'),
+            continue_answer: KeyBinding::ctrl('t'),
+            toggle_hscroll: KeyBinding::ctrl('l'),
+            save: KeyBinding::ctrl('s'),
+            toggle_diff: KeyBinding::ctrl('x'),
+            retry: KeyBinding::ctrl('r'),
+        }
+    }
+}
+
+impl Keymap {
+    fn bindings(&self) -> [(KeyBinding, Action); 21] {
+        [
+            (self.quit, Action::Quit),
+            (self.stop, Action::Stop),
+            (self.submit, Action::Submit),
+            (self.prefill, Action::Prefill),
+            (self.compare_models, Action::CompareModels),
+            (self.switch_pane, Action::SwitchPane),
+            (self.new_branch, Action::NewBranch),
+            (self.next_branch, Action::NextBranch),
+            (self.previous_branch, Action::PreviousBranch),
+            (self.up, Action::Up),
+            (self.down, Action::Down),
+            (self.yank_conversation, Action::YankConversation),
+            (self.yank_answer, Action::YankAnswer),
+            (self.undo, Action::Undo),
+            (self.redo, Action::Redo),
+            (self.edit_prompt, Action::EditPrompt),
+            (self.continue_answer, Action::Continue),
+            (self.toggle_hscroll, Action::ToggleHscroll),
+            (self.save, Action::Save),
+            (self.toggle_diff, Action::ToggleDiff),
+            (self.retry, Action::Retry),
+        ]
+    }
+
+    /// The action bound to `input`, if any.
+    pub fn action(&self, input: &Input) -> Option<Action> {
+        self.bindings()
+            .into_iter()
+            .find(|(binding, _)| binding.matches(input))
+            .map(|(_, action)| action)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn input(key: Key, ctrl: bool, shift: bool) -> Input {
+        Input {
+            key,
+            ctrl,
+            shift,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_parse() {
+        for s in ["ctrl-shift-y", "alt-enter", "esc", "f5", "ctrl--", "space"] {
+            let binding = s.parse::<KeyBinding>().unwrap();
+            assert_eq!(binding.to_string(), s);
+        }
+        assert_eq!(
+            "Ctrl-j".parse::<KeyBinding>().unwrap(),
+            KeyBinding::ctrl('j')
+        );
+        for s in ["", "ctrl-", "hyper-j", "ctrl-jk"] {
+            assert!(s.parse::<KeyBinding>().is_err(), "{}", s);
+        }
+    }
+
+    #[test]
+    fn test_action() {
+        let keymap = Keymap::default();
+        let ctrl = |c| input(Key::Char(c), true, false);
+        assert_eq!(keymap.action(&ctrl('j')), Some(Action::Submit));
+        assert_eq!(keymap.action(&ctrl('y')), Some(Action::YankAnswer));
+        assert_eq!(keymap.action(&ctrl('Y')), Some(Action::YankConversation));
+        assert_eq!(
+            keymap.action(&input(Key::Char('y'), true, true)),
+            Some(Action::YankConversation)
+        );
+        assert_eq!(keymap.action(&input(Key::Char('j'), false, false)), None);
+        let alt_z = Input {
+            key: Key::Char('z'),
+            alt: true,
+            ..Default::default()
+        };
+        assert_eq!(keymap.action(&alt_z), Some(Action::Redo));
+        assert_eq!(keymap.action(&ctrl('z')), Some(Action::Undo));
+
+        let keymap: Keymap = toml::from_str(r#"submit = "alt-enter""#).unwrap();
+        let alt_enter = Input {
+            key: Key::Enter,
+            alt: true,
+            ..Default::default()
+        };
+        assert_eq!(keymap.action(&alt_enter), Some(Action::Submit));
+        assert_eq!(keymap.action(&ctrl('j')), None);
+        assert_eq!(keymap.action(&ctrl('g')), Some(Action::Prefill));
+    }
+}
@@ -1,24 +1,97 @@
+pub mod audit;
+pub mod cache;
+pub mod checkpoint;
+pub mod clipboard;
 pub mod config;
 pub mod error;
+pub mod export;
+pub mod history;
+pub mod keymap;
+pub mod markdown;
 pub mod pagetree;
+pub mod palette;
+pub mod patch;
+pub mod postprocess;
 pub mod query;
+pub mod resume;
+pub mod saved;
 pub mod session;
+pub mod template;
 pub mod textarea;
 
-use std::sync::Arc;
+use std::{future::Future, pin::Pin, sync::Arc, time::Duration};
 
-use config::{Config, Mode};
-use query::Query;
-use rgpt_provider::{api_key::ApiKey, Provider};
+use cache::{Cache, Recorder};
+use config::{Config, Format, Mode, RequestOverrides};
+use history::History;
+use postprocess::PostProcessor;
+use query::{JsonAnswer, JsonItem, Query};
+use resume::{continuation, Resume};
+use rgpt_provider::{api_key::ApiKey, tokenizer::Tokenizer, Api, ModelInfo, Provider};
 use rgpt_types::{
-    completion::{Request, TextEvent},
-    message::Message,
+    completion::{Content, ContentDelta, Request, Response, TextEvent},
+    message::{Message, RichMessage, ToolResult, ToolUse},
 };
 
 use error::Error;
 use session::Session;
-use tokio_stream::StreamExt as _;
+use tokio::task::JoinHandle;
+use tokio_stream::{Stream, StreamExt as _};
 
+/// Restarts of a dropped stream before giving up, with `resume_on_drop`.
+const MAX_RESUMES: usize = 3;
+
+/// Wait before the first retry of a failed request, doubling after each.
+const RETRY_DELAY: Duration = Duration::from_millis(500);
+
+/// Call `send` until it succeeds, fails other than by timing out or losing its connection, or has
+/// been retried `attempts` times. Errors the API answers with are left to the client's backoff,
+/// which already retries those that are transient.
+async fn with_retries<T, F, Fut>(
+    attempts: usize,
+    mut delay: Duration,
+    mut send: F,
+) -> Result<T, rgpt_provider::error::Error>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, rgpt_provider::error::Error>>,
+{
+    let mut attempt = 0;
+    loop {
+        match send().await {
+            Err(e) if e.is_connection() && attempt < attempts => {
+                attempt += 1;
+                tracing::warn!("request failed, retrying ({}/{}): {}", attempt, attempts, e);
+                tokio::time::sleep(delay).await;
+                delay *= 2;
+            }
+            result => return result,
+        }
+    }
+}
+
+/// The cached answer to `request`, or else the API's, which is then cached.
+async fn complete_cached(
+    provider: &Provider,
+    request: Request,
+    attempts: usize,
+    cached: Option<(Cache, String)>,
+) -> Result<Response, rgpt_provider::error::Error> {
+    if let Some(response) = cached.as_ref().and_then(|(cache, key)| cache.get(key)) {
+        tracing::debug!("answered from the cache");
+        return Ok(response);
+    }
+    let response =
+        with_retries(attempts, RETRY_DELAY, || provider.complete(request.clone())).await?;
+    if let Some((cache, key)) = cached {
+        if let Err(e) = cache.put(&key, &response) {
+            tracing::warn!("error caching answer: {}", e);
+        }
+    }
+    Ok(response)
+}
+
+#[derive(Clone)]
 pub struct Assistant {
     config: Config,
     provider: Arc<Provider>,
@@ -34,7 +107,15 @@ impl std::fmt::Debug for Assistant {
 
 impl Assistant {
     pub fn new(config: Config) -> Result<Self, Error> {
-        let provider = Arc::new(ApiKey::get().ok_or(Error::NoApiKey)?.get_provider());
+        let Some((key, source)) = ApiKey::find() else {
+            let tried = ApiKey::sources()
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>();
+            return Err(Error::NoApiKey(tried.join(", ")));
+        };
+        tracing::debug!("using api key from {}", source);
+        let provider = Arc::new(key.get_provider()?);
         Ok(Self { config, provider })
     }
 
@@ -43,32 +124,68 @@ impl Assistant {
     }
 
     fn init_messages(&self) -> Vec<Message> {
-        self.config.messages.clone().unwrap_or_default()
+        self.config.init_messages()
+    }
+
+    fn build_request<M: Into<RichMessage>>(&self, messages: Vec<M>) -> Request {
+        self.config.request(messages)
+    }
+
+    /// The request a query with `messages` sends, as indented JSON for the API of the key that
+    /// would be used. Needs no key and sends nothing.
+    pub fn dry_run(config: &Config, messages: &[Message]) -> Result<String, Error> {
+        let request = config.request([config.init_messages(), messages.to_vec()].concat());
+        let body = Api::current().request_body(request, true)?;
+        Ok(String::from_utf8_lossy(&body).into_owned())
     }
 
-    fn build_request(&self, messages: Vec<Message>) -> Request {
-        let mut builder = Request::builder()
-            .messages(messages)
-            .temperature(self.config.temperature)
-            .stream(self.config.stream);
-        if let Some(model) = &self.config.model {
-            builder = builder.model(model.clone());
+    /// With `cache` in the config, the cache and the key `request`'s answer is cached under,
+    /// which is the same whether it is streamed or not.
+    fn cached(&self, request: &Request) -> Option<(Cache, String)> {
+        if !self.config.cache {
+            return None;
+        }
+        let ttl = self
+            .config
+            .cache_ttl_minutes
+            .map(|minutes| Duration::from_secs(minutes * 60));
+        let request = Request {
+            stream: false,
+            ..request.clone()
+        };
+        match self.provider.request_hash(request) {
+            Ok(key) => Some((Cache::open(ttl)?, key)),
+            Err(e) => {
+                tracing::warn!("not caching, can't hash the request: {}", e);
+                None
+            }
         }
-        builder.build()
     }
 
-    fn complete(&self, messages: Vec<Message>, tx: tokio::sync::mpsc::Sender<TextEvent>) {
+    fn complete(
+        &self,
+        messages: Vec<Message>,
+        tx: tokio::sync::mpsc::Sender<TextEvent>,
+    ) -> JoinHandle<()> {
         tracing::trace!("not streaming");
         let request = self.build_request(messages);
+        let cached = self.cached(&request);
         let provider = self.provider.clone();
+        let attempts = self.config.retry_attempts;
         tokio::spawn(async move {
-            let response = match provider.complete(request).await {
+            let response = match complete_cached(&provider, request, attempts, cached).await {
                 Ok(response) => {
                     tracing::trace!("response: {:?}", response);
                     response
                 }
+                // Tell the caller there won't be an answer, rather than just closing the channel
                 Err(e) => {
                     tracing::error!("error: {}", e);
+                    let _ = tx
+                        .send(TextEvent::Error {
+                            message: e.to_string(),
+                        })
+                        .await;
                     return;
                 }
             };
@@ -77,93 +194,693 @@ impl Assistant {
                     tracing::error!("error: send output");
                 }
             }
-        });
+        })
     }
 
-    fn complete_stream(&self, messages: Vec<Message>, tx: tokio::sync::mpsc::Sender<TextEvent>) {
+    fn complete_stream(
+        &self,
+        messages: Vec<Message>,
+        tx: tokio::sync::mpsc::Sender<TextEvent>,
+    ) -> JoinHandle<()> {
         tracing::trace!("streaming");
-        let request = self.build_request(messages);
-        let provider = self.provider.clone();
+        let assistant = self.clone();
         tokio::spawn(async move {
-            let mut stream = provider.complete_stream(request).await?;
-            while let Some(event) = stream.next().await {
-                match event {
-                    Ok(event) => {
-                        tracing::trace!("event: {:?}", event);
-                        if (tx.send(event).await).is_err() {
-                            tracing::error!("error: send output");
-                        }
+            let cached = assistant.cached(&assistant.build_request(messages.clone()));
+            if let Some(response) = cached.as_ref().and_then(|(cache, key)| cache.get(key)) {
+                tracing::debug!("answered from the cache");
+                for event in cache::replay(response) {
+                    if (tx.send(event).await).is_err() {
+                        tracing::error!("error: send output");
                     }
+                }
+                return;
+            }
+            let mut recorder = cached.as_ref().map(|_| Recorder::default());
+            let mut received = String::new();
+            let mut resume: Option<Resume> = None;
+            // Only reported once resuming is given up on
+            let mut error = None;
+            // An answer that never started wasn't interrupted, it failed
+            let mut started = false;
+            // Some stop reasons end an answer before it has any content, which is said rather
+            // than showing as nothing at all
+            let mut content = false;
+            let mut stop_reason = None;
+            // A rejected key or request fails the same way however often it is resumed
+            let mut permanent = false;
+            for attempt in 0..=MAX_RESUMES {
+                let request_messages = match resume.as_ref().and_then(Resume::prefill) {
+                    Some(prefill) => continuation(messages.clone(), &prefill),
+                    None => messages.clone(),
+                };
+                let request = assistant.build_request(request_messages);
+                let mut stream = match assistant.provider.complete_stream(request).await {
+                    Ok(stream) => stream,
                     Err(e) => {
                         tracing::error!("error: {}", e);
+                        error = Some(e.to_string());
                         break;
                     }
+                };
+                while let Some(event) = stream.next().await {
+                    match event {
+                        Ok(event) => {
+                            tracing::trace!("event: {:?}", event);
+                            started = true;
+                            let stop = matches!(event, TextEvent::MessageStop);
+                            match &event {
+                                TextEvent::MessageStart { message } => {
+                                    content |= !message.content.is_empty()
+                                }
+                                TextEvent::ContentBlockStart { .. } => content = true,
+                                _ => {}
+                            }
+                            if let Some(reason) = event.stop_reason() {
+                                stop_reason = Some(reason.clone());
+                            }
+                            if stop && !content {
+                                let _ = tx.send(TextEvent::no_content(stop_reason.as_ref())).await;
+                            }
+                            for event in resumed_events(resume.as_mut(), event) {
+                                if let Some(text) = streamed_text(&event) {
+                                    received.push_str(&text);
+                                }
+                                if let Some(recorder) = recorder.as_mut() {
+                                    recorder.add(&event);
+                                }
+                                if (tx.send(event).await).is_err() {
+                                    tracing::error!("error: send output");
+                                }
+                            }
+                            if stop {
+                                let answer = recorder.take().and_then(Recorder::finish);
+                                if let (Some((cache, key)), Some(answer)) = (&cached, answer) {
+                                    if let Err(e) = cache.put(key, &answer) {
+                                        tracing::warn!("error caching answer: {}", e);
+                                    }
+                                }
+                                return;
+                            }
+                        }
+                        Err(e) => {
+                            tracing::error!("error: {}", e);
+                            permanent = !e.is_transient();
+                            error = Some(e.to_string());
+                            break;
+                        }
+                    }
+                }
+                if let Some(text) = resume.as_mut().and_then(Resume::flush) {
+                    received.push_str(&text);
+                    let _ = tx.send(text_delta(text)).await;
                 }
+                if permanent || !assistant.config.resume_on_drop || attempt == MAX_RESUMES {
+                    break;
+                }
+                tracing::warn!("stream dropped, resuming ({}/{})", attempt + 1, MAX_RESUMES);
+                resume = Some(Resume::new(received.clone()));
+            }
+            if let Some(message) = error {
+                let _ = tx.send(TextEvent::Error { message }).await;
             }
-            Ok::<(), Error>(())
+            if started {
+                let _ = tx.send(TextEvent::Interrupted).await;
+            }
+        })
+    }
+
+    /// Complete `messages`, calling `handler` for the tools the model uses and sending back what
+    /// they return, until it answers without using any. Returns `messages` with the answers and
+    /// tool results appended.
+    pub async fn run_tools<F, Fut>(
+        &self,
+        mut messages: Vec<RichMessage>,
+        mut handler: F,
+    ) -> Result<Vec<RichMessage>, Error>
+    where
+        F: FnMut(ToolUse) -> Fut,
+        Fut: Future<Output = ToolResult>,
+    {
+        for round in 0.. {
+            let init_messages = self.init_messages().into_iter().map(RichMessage::from);
+            let mut request = self.build_request(init_messages.chain(messages.clone()).collect());
+            request.stream = false;
+            let answer = RichMessage::from(self.provider.complete(request).await?);
+            let tool_uses = answer.tool_uses.clone();
+            messages.push(answer);
+            if tool_uses.is_empty() {
+                break;
+            }
+            if round == self.config.max_tool_rounds {
+                return Err(Error::ToolLoopLimit(round));
+            }
+            let mut results = Vec::with_capacity(tool_uses.len());
+            for tool_use in tool_uses {
+                results.push(handler(tool_use).await);
+            }
+            messages.push(Message::with_tool_results(results));
+        }
+        Ok(messages)
+    }
+
+    /// The whole response to `messages`, without streaming.
+    pub async fn answer(&self, messages: &[Message]) -> Result<Response, Error> {
+        let mut request = self.build_request([self.init_messages(), messages.to_vec()].concat());
+        request.stream = false;
+        let cached = self.cached(&request);
+        Ok(complete_cached(&self.provider, request, self.config.retry_attempts, cached).await?)
+    }
+
+    /// The events of the answer to `messages` as they arrive, ending after `MessageStop`. Without
+    /// `stream` in the config the whole answer is requested at once and its events follow it.
+    pub fn stream(
+        &self,
+        messages: &[Message],
+    ) -> impl Stream<Item = Result<TextEvent, Error>> + Send + 'static {
+        let assistant = self.clone();
+        let messages = messages.to_vec();
+        let events = futures::stream::once(async move {
+            let events: Pin<Box<dyn Stream<Item = Result<TextEvent, Error>> + Send>> =
+                match assistant.config.stream {
+                    true => {
+                        let request =
+                            assistant.build_request([assistant.init_messages(), messages].concat());
+                        let events = assistant.provider.complete_stream(request).await?;
+                        Box::pin(futures::StreamExt::map(events, |event| Ok(event?)))
+                    }
+                    false => {
+                        let events = Vec::<TextEvent>::from(assistant.answer(&messages).await?);
+                        Box::pin(futures::stream::iter(events.into_iter().map(Ok)))
+                    }
+                };
+            Ok::<_, Error>(events)
         });
+        until_stop(futures::TryStreamExt::try_flatten(events))
+    }
+
+    /// Just the text of the answer to `messages`, its content blocks on separate lines. Thinking
+    /// is left out. Fails if the answer stops before `MessageStop`.
+    pub async fn complete_text(&self, messages: &[Message]) -> Result<String, Error> {
+        let mut blocks = TextBlocks::default();
+        let mut events = std::pin::pin!(self.stream(messages));
+        while let Some(event) = events.next().await {
+            match event? {
+                TextEvent::MessageStop => return Ok(blocks.join()),
+                event => blocks.add(event),
+            }
+        }
+        Err(Error::Incomplete)
+    }
+
+    /// Input tokens a query with `messages` would use.
+    pub async fn count_tokens(&self, messages: &[Message]) -> Result<usize, Error> {
+        let request = self.build_request([self.init_messages(), messages.to_vec()].concat());
+        Ok(self.provider.count_tokens(request).await?)
+    }
+
+    /// Send the answer to `messages` to `tx`, from a task that can be aborted to stop it.
+    pub fn handle_input(
+        &self,
+        messages: Vec<Message>,
+        tx: tokio::sync::mpsc::Sender<TextEvent>,
+    ) -> JoinHandle<()> {
+        self.handle_input_with(messages, RequestOverrides::default(), tx)
     }
 
-    pub fn handle_input(&self, messages: Vec<Message>, tx: tokio::sync::mpsc::Sender<TextEvent>) {
-        if self.config.stream {
-            self.complete_stream(messages, tx);
+    /// Like [`Assistant::handle_input`], with `overrides` taking the place of the config's
+    /// settings for this request only.
+    pub fn handle_input_with(
+        &self,
+        messages: Vec<Message>,
+        overrides: RequestOverrides,
+        tx: tokio::sync::mpsc::Sender<TextEvent>,
+    ) -> JoinHandle<()> {
+        let assistant = Assistant {
+            config: self.config.overridden(overrides),
+            provider: self.provider.clone(),
+        };
+        if assistant.config.stream {
+            assistant.complete_stream(messages, tx)
         } else {
-            self.complete(messages, tx);
+            assistant.complete(messages, tx)
         }
     }
 
+    /// The same assistant, answering with `model` instead of the configured one.
+    pub fn with_model(&self, model: String) -> Assistant {
+        let mut assistant = self.clone();
+        assistant.config.model = Some(model);
+        assistant
+    }
+
+    /// Local token counter for the configured model.
+    pub fn tokenizer(&self) -> Box<dyn Tokenizer> {
+        self.provider.tokenizer(self.config.model.as_deref())
+    }
+
+    /// Stable hash of the request `messages` would make, see `Config::deterministic`.
+    pub fn request_hash(&self, messages: Vec<Message>) -> Result<String, Error> {
+        Ok(self.provider.request_hash(self.build_request(messages))?)
+    }
+
+    pub async fn health_check(&self) -> Result<(), Error> {
+        Ok(self.provider.health_check().await?)
+    }
+
+    /// The models the key's API offers, as it lists them.
+    pub async fn list_models(&self) -> Result<Vec<ModelInfo>, Error> {
+        Ok(self.provider.list_models().await?)
+    }
+
     pub async fn session(self, messages: &[Message]) -> Result<(), Error> {
         Session::setup(self)?.start(messages).await
     }
 
+    /// Continue the session saved under `name`, Ctrl-S saves it back.
+    pub async fn load_session(self, name: &str) -> Result<(), Error> {
+        Session::setup(self)?
+            .load(saved::SavedSession::new(name)?)
+            .await
+    }
+
+    /// Pick one of the archived sessions to open in a session, or to delete.
+    pub async fn history(self) -> Result<(), Error> {
+        let limit = self.config.keep_sessions.unwrap_or(history::DEFAULT_LIMIT);
+        let history =
+            History::open(limit).ok_or_else(|| Error::Generic("no state directory".to_string()))?;
+        loop {
+            let entries = history.entries()?;
+            if entries.is_empty() {
+                println!("No saved sessions");
+                return Ok(());
+            }
+            let Some(selection) = dialoguer::Select::new()
+                .with_prompt("Session")
+                .items(&entries)
+                .default(0)
+                .interact_opt()?
+            else {
+                return Ok(());
+            };
+            let entry = &entries[selection];
+            match dialoguer::Select::new()
+                .with_prompt(entry.title.as_str())
+                .items(&["Open", "Delete", "Back"])
+                .default(0)
+                .interact_opt()?
+            {
+                Some(0) => return Session::setup(self)?.open(history, entry).await,
+                Some(1) => history.remove(entry)?,
+                _ => {}
+            }
+        }
+    }
+
     pub async fn query(self, messages: &[Message]) -> Result<(), Error> {
+        if self.config.format == Format::Json {
+            return self.query_json(messages).await;
+        }
+        self.into_query().start(messages).await
+    }
+
+    /// Print the answer as a [`JsonAnswer`] once it is complete.
+    async fn query_json(self, messages: &[Message]) -> Result<(), Error> {
+        let messages = match messages.is_empty() {
+            true => Query::prompt_user_input().await?,
+            false => messages.to_vec(),
+        };
+        let answer = self.json_answer(&messages).await?;
+        println!("{}", serde_json::to_string(&answer)?);
+        Ok(())
+    }
+
+    /// The answer to `messages`, with the request hash if `deterministic` is set.
+    async fn json_answer(&self, messages: &[Message]) -> Result<JsonAnswer, Error> {
+        let request_hash = match self.config.deterministic {
+            true => Some(self.request_hash([self.init_messages(), messages.to_vec()].concat())?),
+            false => None,
+        };
+        Ok(JsonAnswer {
+            request_hash,
+            ..self.answer(messages).await?.into()
+        })
+    }
+
+    /// Query each prompt on its own. A failed query is reported in place of its answer and
+    /// does not stop the others. The answers are separated by `delimiter`, or with
+    /// `--format json` printed as one array, with an `error` in place of a failed answer.
+    pub async fn query_each(self, prompts: &[Message], delimiter: &str) -> Result<(), Error> {
+        if self.config.format == Format::Json {
+            let mut items = Vec::with_capacity(prompts.len());
+            for prompt in prompts {
+                items.push(match self.json_answer(std::slice::from_ref(prompt)).await {
+                    Ok(answer) => JsonItem::Answer(answer),
+                    Err(e) => JsonItem::Error {
+                        error: e.to_string(),
+                    },
+                });
+            }
+            println!("{}", serde_json::to_string(&items)?);
+            return Ok(());
+        }
+        for (i, prompt) in prompts.iter().enumerate() {
+            if i > 0 {
+                println!("{}", delimiter);
+            }
+            if let Err(e) = self.clone().query(std::slice::from_ref(prompt)).await {
+                println!("error: {}", e);
+                continue;
+            }
+            println!();
+        }
+        Ok(())
+    }
+
+    /// Query turn after turn, each with the conversation so far, until the end of input or
+    /// `/quit`. `/reset` forgets the conversation.
+    pub async fn repl(self) -> Result<(), Error> {
+        let mut conversation = Vec::new();
+        while let Some(line) = Query::read_prompt().await? {
+            match ReplLine::from(line.as_str()) {
+                ReplLine::Empty => continue,
+                ReplLine::Quit => break,
+                ReplLine::Reset => {
+                    conversation.clear();
+                    eprintln!("conversation cleared");
+                    continue;
+                }
+                ReplLine::Prompt(prompt) => conversation.push(Message::from(prompt)),
+            }
+            let mut query = self.clone().into_query();
+            // A turn that failed is left out, so the prompt can be sent again
+            if let Err(e) = query.start(&conversation).await {
+                eprintln!("error: {}", e);
+                conversation.pop();
+                continue;
+            }
+            println!();
+            match query.answer() {
+                answer if answer.is_empty() => {
+                    conversation.pop();
+                }
+                answer => conversation.push(Message::assistant(answer)),
+            }
+        }
+        Ok(())
+    }
+
+    fn into_query(self) -> Query {
         let execute = self.mode() == Mode::Bash;
+        let show_thinking = self.config.show_thinking;
+        let skip_confirm = self.config.skip_confirm;
+        let confirm_execute = self.config.confirm_execute;
+        let show_usage = self.config.show_usage;
+        let apply = self.mode() == Mode::Code && self.config.apply;
+        let post_processor = self.config.post_process.clone().map(PostProcessor::Command);
+        let output = self.config.output.clone();
+        let spinner = !self.config.stream && !self.config.quiet;
         Query::builder(self)
             .execute(execute)
+            .show_thinking(show_thinking)
+            .skip_confirm(skip_confirm)
+            .execute_confirm(confirm_execute)
+            .show_usage(show_usage)
+            .apply(apply)
+            .post_processor(post_processor)
+            .output(output)
+            .spinner(spinner)
             .build()
-            .start(messages)
-            .await
+    }
+}
+
+/// A line typed at the `--repl` prompt.
+#[derive(Debug, PartialEq, Eq)]
+enum ReplLine {
+    Empty,
+    Quit,
+    Reset,
+    Prompt(String),
+}
+
+impl From<&str> for ReplLine {
+    fn from(line: &str) -> Self {
+        match line.trim() {
+            "" => ReplLine::Empty,
+            "/quit" => ReplLine::Quit,
+            "/reset" => ReplLine::Reset,
+            prompt => ReplLine::Prompt(prompt.to_string()),
+        }
+    }
+}
+
+/// Text of an answer by content block, as its events come in.
+#[derive(Debug, Default)]
+struct TextBlocks(Vec<String>);
+
+impl TextBlocks {
+    fn push(&mut self, index: usize, text: &str) {
+        if self.0.len() <= index {
+            self.0.resize(index + 1, String::new());
+        }
+        self.0[index].push_str(text);
+    }
+
+    fn add(&mut self, event: TextEvent) {
+        match event {
+            TextEvent::MessageStart { message } => {
+                for (index, content) in message.content.iter().enumerate() {
+                    if let Content::Text { text } = content {
+                        self.push(index, text);
+                    }
+                }
+            }
+            TextEvent::ContentBlockStart {
+                index,
+                content_block,
+            } => self.push(index, &content_block.text().unwrap_or_default()),
+            TextEvent::ContentBlockDelta { index, delta } => {
+                self.push(index, &delta.text().unwrap_or_default())
+            }
+            _ => {}
+        }
+    }
+
+    /// The text blocks, one per line. Blocks without text, e.g. tool calls, are skipped.
+    fn join(self) -> String {
+        self.0
+            .into_iter()
+            .filter(|block| !block.is_empty())
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+}
+
+/// `events` up to and including `MessageStop`.
+fn until_stop<S>(events: S) -> impl Stream<Item = Result<TextEvent, Error>>
+where
+    S: Stream<Item = Result<TextEvent, Error>>,
+{
+    futures::StreamExt::scan(events, false, |stopped, event| {
+        let next = match *stopped {
+            true => None,
+            false => {
+                *stopped = matches!(event, Ok(TextEvent::MessageStop));
+                Some(event)
+            }
+        };
+        std::future::ready(next)
+    })
+}
+
+fn text_delta(text: String) -> TextEvent {
+    TextEvent::ContentBlockDelta {
+        index: 0,
+        delta: ContentDelta::TextDelta { text },
+    }
+}
+
+fn streamed_text(event: &TextEvent) -> Option<String> {
+    match event {
+        TextEvent::ContentBlockStart { content_block, .. } => content_block.text(),
+        TextEvent::ContentBlockDelta { delta, .. } => delta.text(),
+        _ => None,
+    }
+}
+
+/// A restarted stream continues the earlier message, so its start events are dropped and its
+/// text is deduplicated against what was already sent.
+fn resumed_events(resume: Option<&mut Resume>, event: TextEvent) -> Vec<TextEvent> {
+    let Some(resume) = resume else {
+        return vec![event];
+    };
+    match event {
+        TextEvent::MessageStart { .. } | TextEvent::Null => vec![],
+        TextEvent::ContentBlockStart { content_block, .. } => content_block
+            .text()
+            .and_then(|text| resume.push(&text))
+            .map(text_delta)
+            .into_iter()
+            .collect(),
+        TextEvent::ContentBlockDelta {
+            delta: ContentDelta::TextDelta { text },
+            ..
+        } => resume.push(&text).map(text_delta).into_iter().collect(),
+        event => resume
+            .flush()
+            .map(text_delta)
+            .into_iter()
+            .chain(std::iter::once(event))
+            .collect(),
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use rgpt_types::message::Role;
-
     use super::*;
 
     fn get_config() -> Config {
         Config {
             messages: Some(vec![
-                Message {
-                    role: Role::System,
-                    content: "You are my testing assistant. Whatever you say, start with 'Testing: '".to_string(),
-                },
-                Message {
-                    role: Role::User,
-                    content: "Your responses must be short and concise. Do not include explanations unless asked.".to_string(),
-                },
-                Message {
-                    role: Role::Assistant,
-                    content: "Understood.".to_string(),
-                },
+                Message::system("You are my testing assistant. Whatever you say, start with 'Testing: '"),
+                Message::user("Your responses must be short and concise. Do not include explanations unless asked."),
+                Message::assistant("Understood."),
             ]),
             ..Default::default()
         }
     }
 
+    #[test]
+    fn test_dry_run() {
+        let config = Config {
+            model: Some("test-model".to_string()),
+            ..get_config()
+        };
+        let json = Assistant::dry_run(&config, &[Message::user("Hello")]).unwrap();
+        let body: serde_json::Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(body["model"], "test-model");
+        let messages = body["messages"].as_array().unwrap();
+        assert!(messages.last().unwrap().to_string().contains("Hello"));
+        assert!(json.contains("Understood."));
+    }
+
+    #[test]
+    fn test_repl_line() {
+        assert_eq!(ReplLine::from(" /quit "), ReplLine::Quit);
+        assert_eq!(ReplLine::from("/reset"), ReplLine::Reset);
+        assert_eq!(ReplLine::from(""), ReplLine::Empty);
+        assert_eq!(
+            ReplLine::from("list files"),
+            ReplLine::Prompt("list files".to_string())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_until_stop() {
+        let events = tokio_stream::iter(vec![
+            Ok(text_delta("Hi".to_string())),
+            Ok(TextEvent::MessageStop),
+            Ok(TextEvent::Interrupted),
+        ]);
+        let events = until_stop(events).collect::<Vec<_>>().await;
+        assert_eq!(events.len(), 2);
+        assert!(matches!(events[1], Ok(TextEvent::MessageStop)));
+    }
+
+    #[test]
+    fn test_text_blocks() {
+        let events: Vec<TextEvent> = serde_json::from_str(
+            r#"[
+                {"type":"content_block_start","index":0,"content_block":{"type":"thinking","thinking":"Hmm"}},
+                {"type":"content_block_delta","index":0,"delta":{"type":"thinking_delta","thinking":", files"}},
+                {"type":"content_block_start","index":1,"content_block":{"type":"text","text":""}},
+                {"type":"content_block_delta","index":1,"delta":{"type":"text_delta","text":"Use"}},
+                {"type":"content_block_delta","index":1,"delta":{"type":"text_delta","text":" ls."}},
+                {"type":"content_block_start","index":2,"content_block":{"type":"text","text":"Or find."}}
+            ]"#,
+        )
+        .unwrap();
+        let mut blocks = TextBlocks::default();
+        for event in events {
+            blocks.add(event);
+        }
+        assert_eq!(blocks.join(), "Use ls.\nOr find.");
+    }
+
+    #[tokio::test]
+    async fn test_rejected_key_not_retried() {
+        let mut calls = 0;
+        let result: Result<(), _> = with_retries(2, Duration::ZERO, || {
+            calls += 1;
+            async {
+                Err(rgpt_provider::error::Error::Authentication {
+                    key: "ANTHROPIC_API_KEY",
+                    status: 401,
+                    message: "invalid x-api-key".to_string(),
+                })
+            }
+        })
+        .await;
+        assert!(result.is_err());
+        assert_eq!(calls, 1);
+    }
+
     #[tokio::test]
     #[tracing_test::traced_test]
+    #[ignore = "requires ANTHROPIC_API_KEY and network access"]
     async fn test_assistant() -> Result<(), Error> {
         let cfg = get_config();
         let assistant = Assistant::new(cfg).unwrap();
-        let test_messages = vec![Message {
-            role: Role::User,
-            content: "Testing: Hello, world!".to_string(),
-        }];
+        let test_messages = vec![Message::user("Testing: Hello, world!")];
         let (tx, mut rx) = tokio::sync::mpsc::channel(100);
         assistant.complete(test_messages, tx);
         println!("response: {:?}", rx.recv().await.unwrap());
         Ok(())
     }
+
+    #[tokio::test]
+    #[tracing_test::traced_test]
+    #[ignore = "requires ANTHROPIC_API_KEY and network access"]
+    async fn test_stream() -> Result<(), Error> {
+        let assistant = Assistant::new(get_config()).unwrap();
+        let events = assistant
+            .stream(&[Message::user("Testing: Hello, world!")])
+            .collect::<Vec<_>>()
+            .await;
+        assert!(matches!(events.last(), Some(Ok(TextEvent::MessageStop))));
+        Ok(())
+    }
+
+    #[tokio::test]
+    #[tracing_test::traced_test]
+    #[ignore = "requires ANTHROPIC_API_KEY and network access"]
+    async fn test_run_tools() -> Result<(), Error> {
+        let cfg = Config {
+            tools: vec![rgpt_types::completion::Tool {
+                name: "get_weather".to_string(),
+                description: Some("Current weather in a city".to_string()),
+                input_schema: serde_json::json!({
+                    "type": "object",
+                    "properties": {"city": {"type": "string"}},
+                    "required": ["city"]
+                }),
+            }],
+            ..get_config()
+        };
+        let assistant = Assistant::new(cfg).unwrap();
+        let messages = assistant
+            .run_tools(
+                vec![Message::user("What's the weather in Paris?").into()],
+                |tool_use| async move {
+                    ToolResult {
+                        tool_use_id: tool_use.id,
+                        content: "Sunny, 22°C".to_string(),
+                        is_error: false,
+                    }
+                },
+            )
+            .await?;
+        assert!(messages.len() >= 3);
+        assert!(messages.last().unwrap().tool_uses.is_empty());
+        Ok(())
+    }
 }
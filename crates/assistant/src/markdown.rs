@@ -0,0 +1,208 @@
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::text::{Line, Span, Text};
+
+const CODE: Style = Style::new().fg(Color::Cyan);
+const CODE_STRING: Style = Style::new().fg(Color::Green);
+const CODE_COMMENT: Style = Style::new().fg(Color::DarkGray);
+const FENCE: Style = Style::new().fg(Color::DarkGray);
+
+/// Markdown styled for the terminal: headers, emphasis, inline code and fenced code blocks, with
+/// strings and comments in code picked out. Markup this doesn't understand, like an unclosed `**`,
+/// is shown as it is.
+pub fn render(lines: &[String]) -> Text<'static> {
+    let mut in_code = false;
+    lines
+        .iter()
+        .map(|line| {
+            if line.trim_start().starts_with("```") {
+                in_code = !in_code;
+                return Line::styled(line.clone(), FENCE);
+            }
+            match in_code {
+                true => code_line(line),
+                false => prose_line(line),
+            }
+        })
+        .collect::<Vec<_>>()
+        .into()
+}
+
+fn prose_line(line: &str) -> Line<'static> {
+    let level = line.chars().take_while(|&c| c == '#').count();
+    if (1..=6).contains(&level) && line[level..].starts_with(' ') {
+        let style = Style::new().add_modifier(Modifier::BOLD);
+        let style = match level {
+            1 => style.add_modifier(Modifier::UNDERLINED),
+            _ => style,
+        };
+        return Line::styled(line[level + 1..].to_string(), style);
+    }
+    let indent = line.len() - line.trim_start().len();
+    match line
+        .trim_start()
+        .strip_prefix("- ")
+        .or(line.trim_start().strip_prefix("* "))
+    {
+        Some(item) => {
+            let mut spans = vec![Span::raw(format!("{}• ", &line[..indent]))];
+            spans.extend(inline(item));
+            Line::from(spans)
+        }
+        None => Line::from(inline(line)),
+    }
+}
+
+/// Spans for `**bold**`, `*italic*`/`_italic_` and `` `code` ``.
+fn inline(text: &str) -> Vec<Span<'static>> {
+    let mut spans = Vec::new();
+    let mut plain = String::new();
+    let mut rest = text;
+    while let Some(c) = rest.chars().next() {
+        let styled = match c {
+            '`' => delimited(rest, "`").map(|(inner, len)| (Span::styled(inner, CODE), len)),
+            '*' if rest.starts_with("**") => delimited(rest, "**").map(|(inner, len)| {
+                (
+                    Span::styled(inner, Style::new().add_modifier(Modifier::BOLD)),
+                    len,
+                )
+            }),
+            '*' | '_' if !plain.ends_with(char::is_alphanumeric) => delimited(rest, &rest[..1])
+                .map(|(inner, len)| {
+                    (
+                        Span::styled(inner, Style::new().add_modifier(Modifier::ITALIC)),
+                        len,
+                    )
+                }),
+            _ => None,
+        };
+        match styled {
+            Some((span, len)) => {
+                if !plain.is_empty() {
+                    spans.push(Span::raw(std::mem::take(&mut plain)));
+                }
+                spans.push(span);
+                rest = &rest[len..];
+            }
+            None => {
+                plain.push(c);
+                rest = &rest[c.len_utf8()..];
+            }
+        }
+    }
+    if !plain.is_empty() {
+        spans.push(Span::raw(plain));
+    }
+    spans
+}
+
+/// The text between `delimiter` at the start of `text` and its next occurrence, and the length
+/// of the whole. Like in CommonMark, the text can't be empty or start or end with whitespace.
+fn delimited(text: &str, delimiter: &str) -> Option<(String, usize)> {
+    let inner = &text[delimiter.len()..];
+    let end = inner.find(delimiter)?;
+    let inner = &inner[..end];
+    if inner.is_empty()
+        || inner.starts_with(char::is_whitespace)
+        || inner.ends_with(char::is_whitespace)
+    {
+        return None;
+    }
+    Some((inner.to_string(), delimiter.len() * 2 + end))
+}
+
+/// A line of code, with string literals and a trailing `#` or `//` comment picked out. A comment
+/// starts a line or follows whitespace, so `$#`, `a#b` and `https://` aren't taken for one.
+fn code_line(line: &str) -> Line<'static> {
+    let mut spans = Vec::new();
+    let mut current = String::new();
+    let mut quote = None;
+    let mut chars = line.char_indices();
+    while let Some((i, c)) = chars.next() {
+        match quote {
+            Some(q) => {
+                current.push(c);
+                if c == '\\' {
+                    current.extend(chars.next().map(|(_, c)| c));
+                } else if c == q {
+                    spans.push(Span::styled(std::mem::take(&mut current), CODE_STRING));
+                    quote = None;
+                }
+            }
+            None if c == '"' || c == '\'' => {
+                if !current.is_empty() {
+                    spans.push(Span::styled(std::mem::take(&mut current), CODE));
+                }
+                current.push(c);
+                quote = Some(c);
+            }
+            None if (c == '#' || line[i..].starts_with("//"))
+                && line[..i]
+                    .chars()
+                    .next_back()
+                    .is_none_or(char::is_whitespace) =>
+            {
+                if !current.is_empty() {
+                    spans.push(Span::styled(std::mem::take(&mut current), CODE));
+                }
+                spans.push(Span::styled(line[i..].to_string(), CODE_COMMENT));
+                return Line::from(spans);
+            }
+            None => current.push(c),
+        }
+    }
+    if !current.is_empty() {
+        let style = match quote {
+            Some(_) => CODE_STRING,
+            None => CODE,
+        };
+        spans.push(Span::styled(current, style));
+    }
+    Line::from(spans)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lines(text: &str) -> Vec<String> {
+        text.lines().map(String::from).collect()
+    }
+
+    #[test]
+    fn test_render() {
+        let text = render(&lines(
+            "# Title\nUse **ls** or `ls -la`.\n```bash\nls \"my dir\" # list\n```\n2 * 3 **",
+        ));
+        assert_eq!(text.lines[0].spans[0].content, "Title");
+        assert!(text.lines[0].style.add_modifier.contains(Modifier::BOLD));
+
+        let spans = &text.lines[1].spans;
+        assert_eq!(spans[1].content, "ls");
+        assert!(spans[1].style.add_modifier.contains(Modifier::BOLD));
+        assert_eq!(spans[3].content, "ls -la");
+        assert_eq!(spans[3].style, CODE);
+
+        let code = &text.lines[3].spans;
+        assert_eq!(code[1].content, "\"my dir\"");
+        assert_eq!(code[1].style, CODE_STRING);
+        assert_eq!(code[3].content, "# list");
+
+        // Unmatched markers are left alone
+        assert_eq!(text.lines[5].spans.len(), 1);
+        assert_eq!(text.lines[5].spans[0].content, "2 * 3 **");
+    }
+
+    #[test]
+    fn test_code_comment() {
+        let spans = code_line("curl https://example.com // fetch").spans;
+        assert_eq!(spans[0].content, "curl https://example.com ");
+        assert_eq!(spans[1].content, "// fetch");
+        assert_eq!(spans[1].style, CODE_COMMENT);
+
+        let spans = code_line("echo $# a#b").spans;
+        assert_eq!(spans.len(), 1);
+        assert_eq!(spans[0].style, CODE);
+
+        assert_eq!(code_line("#!/bin/sh").spans[0].style, CODE_COMMENT);
+    }
+}
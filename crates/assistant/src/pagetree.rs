@@ -2,9 +2,10 @@ use crate::{
     error::Error,
     textarea::{SessionAreaId, SessionTextArea},
 };
-use rgpt_types::message::Message;
+use rgpt_types::message::{Message, Role};
+use serde::{Deserialize, Serialize};
 
-#[derive(Default, Copy, Clone, Debug, PartialEq, Eq)]
+#[derive(Default, Copy, Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum NodeId {
     #[default]
     Root,
@@ -20,20 +21,83 @@ impl From<NodeId> for String {
     }
 }
 
+/// When a message was written. Kept next to the text areas rather than in [`Message`], which
+/// is sent to the API as is.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MessageMeta {
+    /// Seconds since the Unix epoch.
+    pub created_at: u64,
+}
+
+impl MessageMeta {
+    pub fn now() -> Self {
+        Self {
+            created_at: std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or_default(),
+        }
+    }
+}
+
+/// A message with its metadata, if it has any.
+pub type Turn = (Message, Option<MessageMeta>);
+
+/// Flat, serializable form of a [`Root`] and the node that was current.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct SerializedTree {
+    pub system: Vec<String>,
+    pub nodes: Vec<SerializedNode>,
+    pub current: NodeId,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct SerializedNode {
+    pub id: NodeId,
+    pub parent: NodeId,
+    pub user: Vec<String>,
+    pub assistant: Vec<String>,
+    pub locked: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub user_meta: Option<MessageMeta>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub assistant_meta: Option<MessageMeta>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub model: Option<String>,
+}
+
+fn set_area_lines(area: &mut SessionTextArea, lines: &[String]) {
+    area.set_message(Message {
+        role: area.id.into(),
+        content: lines.join("\n"),
+    });
+}
+
 pub struct Root<'a> {
     pub nodes: Vec<Node<'a>>,
     pub active: NodeId,
     pub system_area: SessionTextArea<'a>,
     pub children: Vec<NodeId>,
+    /// Label of the assistant areas in their titles.
+    pub assistant_label: String,
 }
 
 impl<'a> Root<'a> {
-    pub fn new(max_line_length: usize) -> Self {
+    pub fn new() -> Self {
         Root {
             nodes: vec![],
             active: NodeId::default(),
-            system_area: SessionTextArea::new(SessionAreaId::System, &[], max_line_length),
+            system_area: SessionTextArea::new(SessionAreaId::System, &[]),
             children: vec![],
+            assistant_label: crate::config::DEFAULT_PERSONA_LABEL.to_string(),
+        }
+    }
+
+    pub fn set_assistant_label(&mut self, label: String) {
+        self.assistant_label = label;
+        for id in 0..self.nodes.len() {
+            let path_str = self.node_path_string(NodeId::Node(id as u16));
+            self.nodes[id].set_titles(path_str, &self.assistant_label);
         }
     }
 
@@ -77,54 +141,47 @@ impl<'a> Root<'a> {
         }
     }
 
+    /// Insert `messages` under `parent` as a chain of nodes, one per user message with the answer
+    /// that follows it, if any, and return the last. The first system message, wherever it is,
+    /// becomes the system prompt. Nothing is inserted if an answer doesn't follow a user message.
     pub fn insert_messages(
         &mut self,
         parent: Option<NodeId>,
         messages: Vec<Message>,
     ) -> Result<NodeId, Error> {
-        fn inner(
-            tree: &mut Root,
-            parent: Option<NodeId>,
-            mut stack: Vec<Message>,
-        ) -> Result<NodeId, Error> {
-            if stack.is_empty() {
-                return Ok(parent.unwrap_or(NodeId::Root));
-            }
-            let parent = parent.unwrap_or(NodeId::Root);
-            let child = tree.insert_child_with_parent(parent);
-            let child_node = tree.get_mut(child).unwrap();
-            let message = stack.pop().unwrap();
-
-            if SessionAreaId::from(message.role) != SessionAreaId::User {
-                return Err(Error::Generic("invalid message role".to_string()));
-            }
-            child_node.user_area.set_message(message);
-
-            let message = if stack.is_empty() {
-                return Ok(child);
-            } else {
-                stack.pop().unwrap()
-            };
-
-            if SessionAreaId::from(message.role) != SessionAreaId::Assistant {
-                return Err(Error::Generic("invalid message role".to_string()));
+        let mut system = None;
+        let mut turns: Vec<(Message, Option<Message>)> = vec![];
+        for (i, message) in messages.into_iter().enumerate() {
+            match message.role {
+                Role::System => {
+                    system.get_or_insert(message);
+                }
+                Role::User => turns.push((message, None)),
+                Role::Assistant => match turns.last_mut() {
+                    Some((_, answer @ None)) => *answer = Some(message),
+                    _ => {
+                        return Err(Error::Generic(format!(
+                            "message {} is an answer that doesn't follow a user message",
+                            i + 1
+                        )))
+                    }
+                },
             }
-
-            child_node.assistant_area.set_message(message);
-
-            inner(tree, Some(child), stack)
         }
 
-        let mut messages = messages;
-        let system_message = messages
-            .iter()
-            .find(|message| message.role == rgpt_types::message::Role::System);
-        if let Some(system_message) = system_message {
-            self.system_area.set_message(system_message.clone());
-            messages.retain(|m| m.role != rgpt_types::message::Role::System);
+        if let Some(system) = system {
+            self.system_area.set_message(system);
+        }
+        let mut id = parent.unwrap_or(NodeId::Root);
+        for (user, answer) in turns {
+            id = self.insert_child_with_parent(id);
+            let node = self.get_mut(id).unwrap();
+            node.user_area.set_message(user);
+            if let Some(answer) = answer {
+                node.assistant_area.set_message(answer);
+            }
         }
-        messages.reverse();
-        inner(self, parent, messages)
+        Ok(id)
     }
 
     pub fn walk_up(&self, id: NodeId) -> Vec<NodeId> {
@@ -153,23 +210,29 @@ impl<'a> Root<'a> {
 
     pub fn insert_child_with_parent(&mut self, parent: NodeId) -> NodeId {
         let id = self.next_id();
-        let node = Node::new(
-            id,
-            parent,
-            self.height(parent) + 1,
-            self.system_area.max_line_length,
-        );
+        let node = Node::new(id, parent, self.height(parent) + 1);
         self.nodes.push(node);
         let path_str = self.node_path_string(id);
         match parent {
             NodeId::Root => self.children.push(id),
             NodeId::Node(parent) => self.nodes[parent as usize].children.push(id),
         }
+        let label = self.assistant_label.clone();
         let node = self.get_mut(id).unwrap();
-        node.set_titles(path_str);
+        node.set_titles(path_str, &label);
         id
     }
 
+    /// Label the node's answer with the model that wrote it, when it isn't the configured one.
+    pub fn set_model(&mut self, id: NodeId, model: String) {
+        let path_str = self.node_path_string(id);
+        let label = self.assistant_label.clone();
+        if let Some(node) = self.get_mut(id) {
+            node.model = Some(model);
+            node.set_titles(path_str, &label);
+        }
+    }
+
     pub fn fork_node(&mut self, id: NodeId) -> NodeId {
         tracing::trace!("forking node {:?}", id);
         let node = self.get(id).unwrap();
@@ -182,8 +245,9 @@ impl<'a> Root<'a> {
             NodeId::Root => self.children.push(next_id),
             NodeId::Node(parent) => self.nodes[parent as usize].children.push(next_id),
         }
+        let label = self.assistant_label.clone();
         let fork = self.get_mut(next_id).unwrap();
-        fork.set_titles(path_str);
+        fork.set_titles(path_str, &label);
         next_id
     }
 
@@ -269,6 +333,19 @@ impl<'a> Root<'a> {
         }
     }
 
+    /// Position of `id` among its siblings, from 1, and how many there are.
+    pub fn sibling_index(&self, id: NodeId) -> Option<(usize, usize)> {
+        let siblings = self.siblings(id);
+        let index = siblings.iter().position(|&sibling| sibling == id)?;
+        Some((index + 1, siblings.len()))
+    }
+
+    /// The `n`th sibling of `id`, from 1 like [`Root::sibling_index`].
+    pub fn goto_sibling(&self, id: NodeId, n: usize) -> Option<&Node<'a>> {
+        let &sibling = self.siblings(id).get(n.checked_sub(1)?)?;
+        self.get(sibling)
+    }
+
     pub fn children(&self, id: NodeId) -> Vec<&Node<'a>> {
         self.get(id)
             .map(|node| {
@@ -289,38 +366,118 @@ impl<'a> Root<'a> {
     }
 
     pub fn get_node_messages(&self, id: NodeId) -> Vec<Message> {
+        self.get_node_turns(id)
+            .into_iter()
+            .map(|(message, _)| message)
+            .collect()
+    }
+
+    pub fn get_node_turns(&self, id: NodeId) -> Vec<Turn> {
         match id {
-            NodeId::Root => self.system_area.message().into_iter().collect(),
-            id @ NodeId::Node(_) => self.get(id).map(|node| node.messages()).unwrap_or_default(),
+            NodeId::Root => self
+                .system_area
+                .message()
+                .map(|message| (message, None))
+                .into_iter()
+                .collect(),
+            id @ NodeId::Node(_) => self.get(id).map(|node| node.turns()).unwrap_or_default(),
         }
     }
 
+    pub fn to_serialized(&self, current: NodeId) -> SerializedTree {
+        SerializedTree {
+            system: self.system_area.lines().to_vec(),
+            nodes: self
+                .nodes
+                .iter()
+                .map(|node| SerializedNode {
+                    id: node.id,
+                    parent: node.parent,
+                    user: node.user_area.lines().to_vec(),
+                    assistant: node.assistant_area.lines().to_vec(),
+                    locked: node.is_locked(),
+                    user_meta: node.user_meta,
+                    assistant_meta: node.assistant_meta,
+                    model: node.model.clone(),
+                })
+                .collect(),
+            current,
+        }
+    }
+
+    /// Rebuild a tree from [`Root::to_serialized`], returning it with the node that was current.
+    /// Nodes are re-inserted in id order, which also restores the order of siblings.
+    pub fn from_serialized(tree: SerializedTree) -> Result<(Self, NodeId), Error> {
+        let mut root = Root::new();
+        set_area_lines(&mut root.system_area, &tree.system);
+        for node in tree.nodes {
+            if root.next_id() != node.id {
+                return Err(Error::Generic(format!("unexpected node id {:?}", node.id)));
+            }
+            if node.parent != NodeId::Root && root.get(node.parent).is_none() {
+                return Err(Error::Generic(format!(
+                    "node {:?} has unknown parent {:?}",
+                    node.id, node.parent
+                )));
+            }
+            let id = root.insert_child_with_parent(node.parent);
+            let inserted = root.get_mut(id).unwrap();
+            set_area_lines(&mut inserted.user_area, &node.user);
+            set_area_lines(&mut inserted.assistant_area, &node.assistant);
+            inserted.user_meta = node.user_meta;
+            inserted.assistant_meta = node.assistant_meta;
+            if node.locked {
+                inserted.lock();
+            }
+            if let Some(model) = node.model {
+                root.set_model(id, model);
+            }
+        }
+        if tree.current != NodeId::Root && root.get(tree.current).is_none() {
+            return Err(Error::Generic(format!(
+                "unknown current node {:?}",
+                tree.current
+            )));
+        }
+        Ok((root, tree.current))
+    }
+
     pub fn collect_messages(&self, id: NodeId, down_to: Option<u16>) -> Vec<Message> {
+        self.collect_turns(id, down_to)
+            .into_iter()
+            .map(|(message, _)| message)
+            .collect()
+    }
+
+    /// Same as [`Root::collect_messages`], with each message's metadata.
+    pub fn collect_turns(&self, id: NodeId, down_to: Option<u16>) -> Vec<Turn> {
         tracing::trace!(
             "collecting messages from node {:?} down to {:?}",
             id,
             down_to
         );
-        let mut messages = vec![];
+        let mut turns = vec![];
         let down_to = down_to.unwrap_or(0);
+        let drafting = self.get(id).is_some_and(Node::is_drafting_answer);
         let mut height = self.height(id);
         let mut id = id;
         while height > down_to {
-            messages.extend(self.get_node_messages(id));
+            turns.extend(self.get_node_turns(id));
             id = self.get(id).map(|node| node.parent).unwrap_or(NodeId::Root);
             height -= 1;
         }
-        messages.reverse();
-        if messages.last().map(|m| m.role) == Some(rgpt_types::message::Role::Assistant) {
-            messages.pop();
+        turns.reverse();
+        // An answer still being written is what's asked for, not part of the history
+        if drafting {
+            turns.pop();
         }
-        messages
+        turns
     }
 }
 
 impl<'a> Default for Root<'a> {
     fn default() -> Self {
-        Self::new(70)
+        Self::new()
     }
 }
 
@@ -333,6 +490,9 @@ pub struct Node<'a> {
     pub height: u16,
     pub active: Option<SessionAreaId>,
     pub locked: bool,
+    pub user_meta: Option<MessageMeta>,
+    pub assistant_meta: Option<MessageMeta>,
+    pub model: Option<String>,
 }
 
 impl std::fmt::Debug for Node<'_> {
@@ -348,16 +508,19 @@ impl std::fmt::Debug for Node<'_> {
 }
 
 impl<'a> Node<'a> {
-    pub fn new(id: NodeId, parent: NodeId, height: u16, max_line_length: usize) -> Self {
+    pub fn new(id: NodeId, parent: NodeId, height: u16) -> Self {
         Node {
             id,
-            user_area: SessionTextArea::new(SessionAreaId::User, &[], max_line_length),
-            assistant_area: SessionTextArea::new(SessionAreaId::Assistant, &[], max_line_length),
+            user_area: SessionTextArea::new(SessionAreaId::User, &[]),
+            assistant_area: SessionTextArea::new(SessionAreaId::Assistant, &[]),
             children: vec![],
             parent,
             height,
             active: None,
             locked: false,
+            user_meta: None,
+            assistant_meta: None,
+            model: None,
         }
     }
 
@@ -375,14 +538,20 @@ impl<'a> Node<'a> {
             height: self.height,
             active: None,
             locked: false,
+            user_meta: self.user_meta,
+            assistant_meta: self.assistant_meta,
+            model: self.model.clone(),
         }
     }
 
-    pub fn set_titles(&mut self, path_str: String) {
+    pub fn set_titles(&mut self, path_str: String, assistant_label: &str) {
         tracing::trace!("setting titles for node {:?}", self.id);
         self.user_area.set_title(format!("{} : user", path_str));
-        self.assistant_area
-            .set_title(format!("{} : assistant", path_str));
+        let title = match &self.model {
+            Some(model) => format!("{} : {} ({})", path_str, assistant_label, model),
+            None => format!("{} : {}", path_str, assistant_label),
+        };
+        self.assistant_area.set_title(title);
     }
 
     pub fn area(&self, id: SessionAreaId) -> &SessionTextArea<'a> {
@@ -403,6 +572,11 @@ impl<'a> Node<'a> {
         self.locked
     }
 
+    /// Whether the node hasn't been sent yet and has the start of an answer after its prompt.
+    pub fn is_drafting_answer(&self) -> bool {
+        !self.locked && self.turns().len() == 2
+    }
+
     pub fn area_mut(&mut self, id: SessionAreaId) -> &mut SessionTextArea<'a> {
         match id {
             SessionAreaId::User => &mut self.user_area,
@@ -430,9 +604,18 @@ impl<'a> Node<'a> {
     }
 
     pub fn messages(&self) -> Vec<Message> {
+        self.turns()
+            .into_iter()
+            .map(|(message, _)| message)
+            .collect()
+    }
+
+    pub fn turns(&self) -> Vec<Turn> {
         match (self.user_area.message(), self.assistant_area.message()) {
-            (Some(user), Some(assistant)) => vec![assistant, user],
-            (Some(user), None) => vec![user],
+            (Some(user), Some(assistant)) => {
+                vec![(assistant, self.assistant_meta), (user, self.user_meta)]
+            }
+            (Some(user), None) => vec![(user, self.user_meta)],
             _ => vec![],
         }
     }
@@ -445,7 +628,8 @@ mod tests {
     #[test]
     fn test_new_page_tree() {
         let tree = Root::default();
-        assert_eq!(tree.nodes.len(), 1);
+        // The root is no node of its own, it only holds the system prompt and its children
+        assert!(tree.nodes.is_empty());
         assert_eq!(tree.active, NodeId::Root);
     }
 
@@ -469,4 +653,155 @@ mod tests {
             Some(SessionAreaId::User)
         );
     }
+
+    #[test]
+    fn test_insert_messages() {
+        let mut tree = Root::default();
+        let messages = vec![
+            Message::system("Be brief."),
+            Message::user("hi"),
+            Message::assistant("hello"),
+            Message::user("bye"),
+        ];
+        let leaf = tree.insert_messages(None, messages.clone()).unwrap();
+        assert_eq!(leaf, NodeId::Node(1));
+        assert_eq!(tree.get(leaf).unwrap().parent, NodeId::Node(0));
+        assert_eq!(tree.get_system_area().lines(), ["Be brief."]);
+        assert_eq!(tree.collect_messages(leaf, None), messages[1..]);
+
+        let answer_first = vec![Message::assistant("hello")];
+        assert!(tree.insert_messages(Some(leaf), answer_first).is_err());
+    }
+
+    #[test]
+    fn test_insert_messages_any_order() {
+        let mut tree = Root::default();
+        let messages = vec![
+            Message::user("hi"),
+            Message::user("anyone?"),
+            Message::system("Be brief."),
+            Message::assistant("hello"),
+        ];
+        let leaf = tree.insert_messages(None, messages).unwrap();
+        assert_eq!(tree.get_system_area().lines(), ["Be brief."]);
+        assert_eq!(
+            tree.collect_messages(leaf, None),
+            vec![Message::user("hi"), Message::user("anyone?")]
+        );
+        tree.get_mut(leaf).unwrap().lock();
+        assert_eq!(
+            tree.collect_messages(leaf, None).last(),
+            Some(&Message::assistant("hello"))
+        );
+
+        // Two answers in a row can't both answer the same message
+        let nodes = tree.nodes.len();
+        let answers = vec![
+            Message::user("hi"),
+            Message::assistant("hello"),
+            Message::assistant("hello again"),
+        ];
+        assert!(tree.insert_messages(None, answers).is_err());
+        assert_eq!(tree.nodes.len(), nodes);
+    }
+
+    #[test]
+    fn test_fork_node() {
+        let mut tree = Root::default();
+        let messages = vec![
+            Message::user("hi"),
+            Message::assistant("hello"),
+            Message::user("bye"),
+        ];
+        let leaf = tree.insert_messages(None, messages).unwrap();
+        tree.get_mut(leaf).unwrap().lock();
+        let fork = tree.fork_node(leaf);
+
+        assert_eq!(tree.siblings(fork), tree.siblings(leaf));
+        assert_eq!(tree.sibling_index(fork), Some((2, 2)));
+        let node = tree.get(fork).unwrap();
+        assert_eq!(node.parent, NodeId::Node(0));
+        assert!(!node.is_locked());
+        assert_eq!(node.user_area.lines(), ["bye"]);
+        assert_eq!(
+            tree.collect_messages(fork, None),
+            tree.collect_messages(leaf, None)
+        );
+    }
+
+    #[test]
+    fn test_collect_answered() {
+        let mut tree = Root::default();
+        let messages = vec![Message::user("hi"), Message::assistant("hello")];
+        let node = tree.insert_messages(None, messages.clone()).unwrap();
+        // A draft answer on a node that wasn't sent is left out
+        assert_eq!(tree.collect_messages(node, None), vec![Message::user("hi")]);
+
+        tree.get_mut(node).unwrap().lock();
+        assert_eq!(tree.collect_messages(node, None), messages);
+        let next = tree.insert_child_with_parent(node);
+        assert_eq!(tree.collect_messages(next, None), messages);
+    }
+
+    #[test]
+    fn test_sibling_index() {
+        let mut tree = Root::default();
+        let first = tree.insert_child_with_parent(NodeId::Root);
+        assert_eq!(tree.sibling_index(first), Some((1, 1)));
+        let second = tree.insert_child_with_parent(NodeId::Root);
+        let third = tree.insert_child_with_parent(NodeId::Root);
+        assert_eq!(tree.sibling_index(second), Some((2, 3)));
+
+        assert_eq!(tree.next_sibling(third).unwrap().id, first);
+        assert_eq!(tree.previous_sibling(first).unwrap().id, third);
+        assert_eq!(tree.goto_sibling(first, 3).unwrap().id, third);
+        assert!(tree.goto_sibling(first, 0).is_none());
+        assert!(tree.goto_sibling(first, 4).is_none());
+        assert_eq!(tree.sibling_index(NodeId::Node(9)), None);
+    }
+
+    #[test]
+    fn test_serialized_round_trip() {
+        let mut tree = Root::default();
+        let messages = vec![
+            Message {
+                role: rgpt_types::message::Role::System,
+                content: "You are a bartender.".to_string(),
+            },
+            Message {
+                role: rgpt_types::message::Role::User,
+                content: "A human walks into a bar".to_string(),
+            },
+            Message {
+                role: rgpt_types::message::Role::Assistant,
+                content: "What can I get you?".to_string(),
+            },
+            Message {
+                role: rgpt_types::message::Role::User,
+                content: "A beer\nplease".to_string(),
+            },
+        ];
+        let leaf = tree.insert_messages(None, messages).unwrap();
+        tree.get_mut(NodeId::Node(0)).unwrap().lock();
+        tree.get_mut(NodeId::Node(0)).unwrap().user_meta = Some(MessageMeta {
+            created_at: 1_700_000_000,
+        });
+        let fork = tree.fork_node(leaf);
+
+        let serialized = tree.to_serialized(fork);
+        let json = serde_json::to_string(&serialized).unwrap();
+        let (restored, current) =
+            Root::from_serialized(serde_json::from_str(&json).unwrap()).unwrap();
+
+        assert_eq!(current, fork);
+        assert_eq!(restored.siblings(fork), tree.siblings(fork));
+        assert!(restored.is_locked(NodeId::Node(0)));
+        assert_eq!(
+            restored.collect_turns(current, None)[0].1,
+            Some(MessageMeta {
+                created_at: 1_700_000_000
+            })
+        );
+        assert_eq!(restored.to_serialized(current), serialized);
+    }
 }
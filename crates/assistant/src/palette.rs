@@ -0,0 +1,125 @@
+use serde::{Deserialize, Serialize};
+
+const ANSI_RESET: &[u8] = b"\x1b[0m";
+
+/// One of the 16 standard terminal colors.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum NamedColor {
+    Black,
+    Red,
+    Green,
+    Yellow,
+    Blue,
+    Magenta,
+    Cyan,
+    White,
+    BrightBlack,
+    BrightRed,
+    BrightGreen,
+    BrightYellow,
+    BrightBlue,
+    BrightMagenta,
+    BrightCyan,
+    BrightWhite,
+}
+
+impl NamedColor {
+    fn code(&self) -> u8 {
+        match self {
+            NamedColor::Black => 30,
+            NamedColor::Red => 31,
+            NamedColor::Green => 32,
+            NamedColor::Yellow => 33,
+            NamedColor::Blue => 34,
+            NamedColor::Magenta => 35,
+            NamedColor::Cyan => 36,
+            NamedColor::White => 37,
+            NamedColor::BrightBlack => 90,
+            NamedColor::BrightRed => 91,
+            NamedColor::BrightGreen => 92,
+            NamedColor::BrightYellow => 93,
+            NamedColor::BrightBlue => 94,
+            NamedColor::BrightMagenta => 95,
+            NamedColor::BrightCyan => 96,
+            NamedColor::BrightWhite => 97,
+        }
+    }
+}
+
+/// A foreground color, either named (`"bright_blue"`) or a 256-color code (`208`).
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(untagged)]
+pub enum AnsiColor {
+    Named(NamedColor),
+    Fixed(u8),
+}
+
+impl AnsiColor {
+    pub fn start(&self) -> Vec<u8> {
+        match self {
+            AnsiColor::Named(color) => format!("\x1b[{}m", color.code()),
+            AnsiColor::Fixed(code) => format!("\x1b[38;5;{}m", code),
+        }
+        .into_bytes()
+    }
+}
+
+/// Colors used by query mode.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Palette {
+    /// Streamed assistant text.
+    pub assistant: AnsiColor,
+    /// The selector's own entries, e.g. `exit`.
+    pub selector: AnsiColor,
+    /// Code blocks offered by the selector.
+    pub code_block: AnsiColor,
+    /// Extended thinking, when shown.
+    pub thinking: AnsiColor,
+    /// Warnings about the answer, e.g. that it was cut off.
+    pub warning: AnsiColor,
+    /// Token usage shown after the answer.
+    pub usage: AnsiColor,
+}
+
+impl Default for Palette {
+    fn default() -> Self {
+        Palette {
+            assistant: AnsiColor::Named(NamedColor::BrightMagenta),
+            selector: AnsiColor::Named(NamedColor::BrightBlue),
+            code_block: AnsiColor::Named(NamedColor::BrightMagenta),
+            thinking: AnsiColor::Named(NamedColor::BrightBlack),
+            warning: AnsiColor::Named(NamedColor::Yellow),
+            usage: AnsiColor::Named(NamedColor::BrightBlack),
+        }
+    }
+}
+
+impl Palette {
+    /// Colors are disabled when `NO_COLOR` is set to a non-empty value.
+    /// Ref: https://no-color.org
+    pub fn enabled() -> bool {
+        std::env::var_os("NO_COLOR").is_none_or(|v| v.is_empty())
+    }
+
+    pub fn paint(&self, color: AnsiColor, text: &[u8]) -> Vec<u8> {
+        if !Self::enabled() {
+            return text.to_vec();
+        }
+        [color.start().as_slice(), text, ANSI_RESET].concat()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_color_start() {
+        assert_eq!(
+            AnsiColor::Named(NamedColor::BrightBlue).start(),
+            b"\x1b[94m".to_vec()
+        );
+        assert_eq!(AnsiColor::Fixed(208).start(), b"\x1b[38;5;208m".to_vec());
+    }
+}
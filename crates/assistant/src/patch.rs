@@ -0,0 +1,253 @@
+use std::path::{Component, Path};
+
+use rgpt_utils::diff::{DiffLine, DiffTag};
+
+use crate::error::Error;
+
+/// One hunk of a unified diff, with the file it applies to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Hunk {
+    /// Relative to the working directory, without the `a/`/`b/` prefix.
+    pub path: String,
+    /// The `@@ -1,2 +1,3 @@` line.
+    pub header: String,
+    /// Line the hunk starts at in the old file, from 1, or 0 for a new file.
+    pub old_start: usize,
+    pub lines: Vec<DiffLine>,
+}
+
+impl std::fmt::Display for Hunk {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "{}", self.header)?;
+        for line in &self.lines {
+            writeln!(f, "{}", line)?;
+        }
+        Ok(())
+    }
+}
+
+/// `-1,2 +1,3` of a hunk header, as the old start and line counts.
+fn parse_header(header: &str) -> Option<(usize, usize, usize)> {
+    let ranges = header.strip_prefix("@@ ")?.split(" @@").next()?;
+    let (old, new) = ranges.split_once(' ')?;
+    let range = |range: &str| -> Option<(usize, usize)> {
+        match range.split_once(',') {
+            Some((start, count)) => Some((start.parse().ok()?, count.parse().ok()?)),
+            None => Some((range.parse().ok()?, 1)),
+        }
+    };
+    let (old_start, old_count) = range(old.strip_prefix('-')?)?;
+    let (_, new_count) = range(new.strip_prefix('+')?)?;
+    Some((old_start, old_count, new_count))
+}
+
+fn strip_path(path: &str) -> &str {
+    let path = path.split('\t').next().unwrap_or(path).trim();
+    path.strip_prefix("a/")
+        .or_else(|| path.strip_prefix("b/"))
+        .unwrap_or(path)
+}
+
+/// Hunks of the unified diffs in `text`, which may be surrounded by prose or code fences. Other
+/// text is ignored. A hunk ends once it has as many lines as its header says.
+pub fn parse(text: &str) -> Vec<Hunk> {
+    let mut hunks: Vec<Hunk> = Vec::new();
+    let mut path: Option<String> = None;
+    // Old and new lines left in the current hunk
+    let mut remaining: (usize, usize) = (0, 0);
+    let mut lines = text.lines().peekable();
+    while let Some(line) = lines.next() {
+        if remaining != (0, 0) {
+            let (tag, text) = match line.chars().next() {
+                Some('+') => (DiffTag::Insert, &line[1..]),
+                Some('-') => (DiffTag::Delete, &line[1..]),
+                Some(' ') => (DiffTag::Equal, &line[1..]),
+                // Trailing whitespace of an empty context line is often lost
+                None => (DiffTag::Equal, ""),
+                Some('\\') => continue,
+                Some(_) => {
+                    remaining = (0, 0);
+                    continue;
+                }
+            };
+            match tag {
+                DiffTag::Insert => remaining.1 = remaining.1.saturating_sub(1),
+                DiffTag::Delete => remaining.0 = remaining.0.saturating_sub(1),
+                DiffTag::Equal => {
+                    remaining = (remaining.0.saturating_sub(1), remaining.1.saturating_sub(1))
+                }
+            }
+            if let Some(hunk) = hunks.last_mut() {
+                hunk.lines.push(DiffLine {
+                    tag,
+                    text: text.to_string(),
+                });
+            }
+            continue;
+        }
+        if let Some(old) = line.strip_prefix("--- ") {
+            let Some(new) = lines.peek().and_then(|line| line.strip_prefix("+++ ")) else {
+                continue;
+            };
+            // Deleting a file isn't a hunk that can be applied
+            path = match strip_path(new) {
+                "/dev/null" => None,
+                _ if strip_path(old) == "/dev/null" => Some(strip_path(new).to_string()),
+                new => Some(new.to_string()),
+            };
+            lines.next();
+        } else if line.starts_with("@@ ") {
+            let (Some(path), Some((old_start, old_count, new_count))) = (&path, parse_header(line))
+            else {
+                continue;
+            };
+            remaining = (old_count, new_count);
+            hunks.push(Hunk {
+                path: path.clone(),
+                header: line.to_string(),
+                old_start,
+                lines: vec![],
+            });
+        }
+    }
+    hunks
+}
+
+impl Hunk {
+    fn old_lines(&self) -> Vec<&str> {
+        self.lines
+            .iter()
+            .filter(|line| line.tag != DiffTag::Insert)
+            .map(|line| line.text.as_str())
+            .collect()
+    }
+
+    fn new_lines(&self) -> Vec<&str> {
+        self.lines
+            .iter()
+            .filter(|line| line.tag != DiffTag::Delete)
+            .map(|line| line.text.as_str())
+            .collect()
+    }
+
+    /// `content` with the hunk applied. The hunk's lines are looked for nearest to where its
+    /// header says they are, since models often get the line numbers wrong. Trailing whitespace
+    /// is ignored when matching.
+    pub fn apply_to(&self, content: &str) -> Result<String, Error> {
+        let lines = content.lines().collect::<Vec<_>>();
+        let old = self.old_lines();
+        let expected = self.old_start.saturating_sub(1);
+        let at = match old.is_empty() {
+            true => Some(expected.min(lines.len())),
+            false => (0..=lines.len().saturating_sub(old.len()))
+                .filter(|&i| {
+                    lines[i..].len() >= old.len()
+                        && lines[i..i + old.len()]
+                            .iter()
+                            .zip(&old)
+                            .all(|(a, b)| a.trim_end() == b.trim_end())
+                })
+                .min_by_key(|&i| i.abs_diff(expected)),
+        };
+        let at =
+            at.ok_or_else(|| Error::Patch(format!("{} doesn't match {}", self.header, self.path)))?;
+        let patched = lines[..at]
+            .iter()
+            .chain(&self.new_lines())
+            .chain(&lines[at + old.len()..])
+            .map(|line| format!("{}\n", line))
+            .collect::<String>();
+        Ok(match content.is_empty() || content.ends_with('\n') {
+            true => patched,
+            false => patched.trim_end_matches('\n').to_string(),
+        })
+    }
+
+    /// Apply the hunk to its file under `root`, creating the file if it is new.
+    pub fn apply(&self, root: &Path) -> Result<(), Error> {
+        let relative = Path::new(&self.path);
+        if !relative
+            .components()
+            .all(|component| matches!(component, Component::Normal(_)))
+        {
+            return Err(Error::Patch(format!(
+                "{} is outside the working directory",
+                self.path
+            )));
+        }
+        let path = root.join(relative);
+        let content = match std::fs::read_to_string(&path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound && self.old_start == 0 => {
+                String::new()
+            }
+            Err(e) => return Err(e.into()),
+        };
+        let patched = self.apply_to(&content)?;
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        std::fs::write(path, patched)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ANSWER: &str = "Rename the variable:
+
+```diff
+--- a/src/main.rs
++++ b/src/main.rs
+@@ -1,4 +1,4 @@
+ fn main() {
+-    let x = 1;
+-    println!(\"{}\", x);
++    let count = 1;
++    println!(\"{}\", count);
+ }
+--- /dev/null
++++ b/NOTES.md
+@@ -0,0 +1 @@
++Renamed x.
+```
+
+That's all.";
+
+    #[test]
+    fn test_parse_and_apply() {
+        let hunks = parse(ANSWER);
+        assert_eq!(hunks.len(), 2);
+        assert_eq!(hunks[0].path, "src/main.rs");
+        assert_eq!(hunks[0].lines.len(), 6);
+        assert_eq!(hunks[1].path, "NOTES.md");
+        assert_eq!(hunks[1].old_start, 0);
+
+        // Shifted down by a line, as if the header's line numbers were off
+        let content = "// entry point\nfn main() {\n    let x = 1;\n    println!(\"{}\", x);\n}\n";
+        assert_eq!(
+            hunks[0].apply_to(content).unwrap(),
+            "// entry point\nfn main() {\n    let count = 1;\n    println!(\"{}\", count);\n}\n"
+        );
+        assert_eq!(hunks[1].apply_to("").unwrap(), "Renamed x.\n");
+        assert!(hunks[0].apply_to("fn other() {}\n").is_err());
+    }
+
+    #[test]
+    fn test_no_diff() {
+        assert!(parse("ls -la\n```bash\nls -- -f\n```").is_empty());
+    }
+
+    #[test]
+    fn test_outside_working_directory() {
+        let hunk = Hunk {
+            path: "../etc/hosts".to_string(),
+            header: "@@ -0,0 +1 @@".to_string(),
+            old_start: 0,
+            lines: vec![],
+        };
+        assert!(hunk.apply(&std::env::temp_dir()).is_err());
+    }
+}
@@ -0,0 +1,87 @@
+use std::io::Write as _;
+use std::process::{Command, Stdio};
+use std::sync::Arc;
+
+use crate::error::Error;
+
+type Function = dyn Fn(&str) -> Result<String, Error> + Send + Sync;
+
+/// Transforms the complete answer before it is printed, e.g. a formatter or a redaction step.
+#[derive(Clone)]
+pub enum PostProcessor {
+    /// Shell command the answer is piped through, stdin to stdout.
+    Command(String),
+    Function(Arc<Function>),
+}
+
+impl std::fmt::Debug for PostProcessor {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PostProcessor::Command(command) => f.debug_tuple("Command").field(command).finish(),
+            PostProcessor::Function(_) => f.debug_tuple("Function").finish(),
+        }
+    }
+}
+
+impl PostProcessor {
+    pub fn function(f: impl Fn(&str) -> Result<String, Error> + Send + Sync + 'static) -> Self {
+        PostProcessor::Function(Arc::new(f))
+    }
+
+    pub fn apply(&self, text: &str) -> Result<String, Error> {
+        match self {
+            PostProcessor::Command(command) => run(command, text),
+            PostProcessor::Function(f) => f(text),
+        }
+    }
+}
+
+fn run(command: &str, text: &str) -> Result<String, Error> {
+    let mut child = Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+
+    // Write from another thread, the command may fill its stdout before it has read everything.
+    let mut stdin = child.stdin.take().unwrap();
+    let input = text.to_string();
+    let writer = std::thread::spawn(move || stdin.write_all(input.as_bytes()));
+
+    // Reads stdout and stderr together, a command may fill either pipe before closing the other
+    let output = child.wait_with_output()?;
+    // A command that exits without reading its input breaks the pipe, which is fine.
+    let _ = writer.join();
+
+    if !output.status.success() {
+        return Err(Error::PostProcess(format!(
+            "'{}' failed with {}: {}",
+            command,
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_post_process() {
+        let upper = PostProcessor::Command("tr a-z A-Z".to_string());
+        assert_eq!(upper.apply("ls -la\n").unwrap(), "LS -LA\n");
+
+        let failing = PostProcessor::Command("exit 3".to_string());
+        assert!(matches!(failing.apply(""), Err(Error::PostProcess(_))));
+
+        let noisy = PostProcessor::Command("head -c 200000 /dev/zero >&2; cat".to_string());
+        assert_eq!(noisy.apply("ls\n").unwrap(), "ls\n");
+
+        let redact = PostProcessor::function(|text| Ok(text.replace("hunter2", "***")));
+        assert_eq!(redact.apply("pw: hunter2").unwrap(), "pw: ***");
+    }
+}
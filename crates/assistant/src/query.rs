@@ -1,43 +1,199 @@
-use std::{io::Write as _, process::Command};
+use std::{
+    io::{IsTerminal as _, Write as _},
+    path::{Path, PathBuf},
+    process::Command,
+};
 
-use crate::{error::Error, Assistant};
+use crate::{
+    audit::AuditLog,
+    error::Error,
+    export::rfc3339,
+    pagetree::MessageMeta,
+    palette::Palette,
+    patch,
+    postprocess::PostProcessor,
+    session::{SPINNER, SPINNER_INTERVAL},
+    Assistant,
+};
 use rgpt_types::{
-    completion::{Content, ContentBlock, ContentDelta, TextEvent},
+    completion::{Content, ContentBlock, ContentDelta, Response, StopReason, TextEvent, Usage},
     message::Message,
 };
+use serde::Serialize;
+use unicode_width::UnicodeWidthChar;
 
 pub struct Query {
     assistant: Assistant,
     state: QueryState,
     execute: bool,
+    show_thinking: bool,
+    skip_confirm: bool,
+    execute_confirm: bool,
+    show_usage: bool,
+    apply: bool,
+    audit_log: Option<AuditLog>,
+    post_processor: Option<PostProcessor>,
+    output: Option<PathBuf>,
+    spinner: bool,
+    palette: Palette,
+}
+
+/// Spins on stderr after the cursor until stopped, which clears it again.
+struct Spinner(tokio::task::JoinHandle<()>);
+
+impl Spinner {
+    fn start() -> Self {
+        Spinner(tokio::spawn(async {
+            let mut interval = tokio::time::interval(SPINNER_INTERVAL);
+            for frame in SPINNER.iter().cycle() {
+                interval.tick().await;
+                // Saved and restored, so the answer is printed where the spinner is
+                eprint!("\x1b7{} waiting for the answer\x1b8", frame);
+            }
+        }))
+    }
+
+    async fn stop(self) {
+        self.0.abort();
+        let _ = self.0.await;
+        eprint!("\x1b[K");
+    }
 }
 
 #[derive(Default)]
 pub struct QueryState {
+    /// Terminal rows the output has moved down, including rows wrapped at `width`.
     line_no: usize,
+    /// Terminal width, or 0 if unknown, in which case only newlines count as rows.
+    width: usize,
+    /// Chars printed on the current row.
+    column: usize,
     messages: Vec<Vec<u8>>,
+    in_thinking: bool,
+    /// The answer hit `max_tokens` and is incomplete.
+    truncated: bool,
+    usage: Usage,
 }
 
 type CodeBlock = Vec<u8>;
 
+/// The answer as printed by `--format json`.
+#[derive(Debug, Serialize)]
+pub struct JsonAnswer {
+    pub model: String,
+    pub content: String,
+    pub stop_reason: Option<StopReason>,
+    pub usage: Usage,
+    /// When the answer was received, as `YYYY-MM-DDTHH:MM:SSZ`.
+    pub created_at: String,
+    /// Hash of the request, with `deterministic` in the config.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub request_hash: Option<String>,
+}
+
+/// An entry of the array `--format json` prints for several prompts.
+#[derive(Debug, Serialize)]
+#[serde(untagged)]
+pub enum JsonItem {
+    Answer(JsonAnswer),
+    Error { error: String },
+}
+
+impl From<Response> for JsonAnswer {
+    fn from(response: Response) -> Self {
+        let content = response
+            .content
+            .iter()
+            .filter_map(|content| match content {
+                Content::Text { text } => Some(text.as_str()),
+                _ => None,
+            })
+            .collect();
+        Self {
+            model: response.model,
+            content,
+            stop_reason: response.stop_reason,
+            usage: response.usage,
+            created_at: rfc3339(MessageMeta::now().created_at),
+            request_hash: None,
+        }
+    }
+}
+
 impl QueryState {
     pub fn new() -> Self {
         Default::default()
     }
 
+    pub fn with_width(width: usize) -> Self {
+        Self {
+            width,
+            ..Default::default()
+        }
+    }
+
+    /// Count the rows `printed` moves the cursor down. The terminal wraps only once a char is
+    /// printed past a full row, so a newline right after a full row moves down just once.
+    fn advance(&mut self, printed: &[u8]) {
+        for c in String::from_utf8_lossy(printed).chars() {
+            if c == '\n' {
+                self.line_no += 1;
+                self.column = 0;
+                continue;
+            }
+            let width = c.width().unwrap_or(0);
+            // A wide char that doesn't fit in what's left of the row moves to the next one
+            if self.width > 0 && self.column + width > self.width && self.column > 0 {
+                self.line_no += 1;
+                self.column = 0;
+            }
+            self.column += width;
+        }
+    }
+
     pub fn add_message(&mut self, index: usize, msg: Vec<u8>) {
         if self.messages.len() <= index {
             self.messages.resize(index + 1, vec![]);
         }
-        self.line_no += msg.iter().filter(|&&b| b == b'\n').count();
+        self.advance(&msg);
         self.messages
             .get_mut(index)
             .unwrap()
             .extend(msg.iter().copied());
     }
 
+    /// Replace the answer, e.g. with its post-processed version.
+    pub fn set_message(&mut self, msg: Vec<u8>) {
+        self.line_no = 0;
+        self.column = 0;
+        self.advance(&msg);
+        self.messages = vec![msg];
+    }
+
+    /// The start of a message has the input tokens, later deltas the output tokens so far.
+    pub fn add_usage(&mut self, event: &TextEvent) {
+        match event {
+            TextEvent::MessageStart { message } => self.usage = message.usage,
+            TextEvent::MessageDelta {
+                usage: Some(usage), ..
+            } => self.usage.output_tokens = usage.output_tokens,
+            _ => {}
+        }
+    }
+
+    /// Thinking is printed but kept out of `messages`, so it never ends up in a code block.
+    pub fn add_thinking(&mut self, msg: &[u8]) {
+        self.advance(msg);
+    }
+
+    /// Fenced code blocks if the answer has any, otherwise one block per line, joining lines that
+    /// end with `/`.
     fn get_code_blocks(&self) -> Vec<Vec<u8>> {
         let joined = self.messages.iter().flatten().copied().collect::<Vec<u8>>();
+        let fenced = fenced_code_blocks(&joined);
+        if !fenced.is_empty() {
+            return fenced;
+        }
         let mut blocks = Vec::new();
         let mut current_block = Vec::new();
 
@@ -62,16 +218,63 @@ impl QueryState {
     }
 }
 
+/// Contents of the Markdown code fences in `text`. An unterminated fence runs to the end.
+fn fenced_code_blocks(text: &[u8]) -> Vec<Vec<u8>> {
+    let mut blocks = Vec::new();
+    let mut current_block: Option<Vec<u8>> = None;
+
+    for line in text.split(|&b| b == b'\n') {
+        if line.trim_ascii_start().starts_with(b"```") {
+            match current_block.take() {
+                Some(block) => blocks.push(block),
+                None => current_block = Some(Vec::new()),
+            }
+        } else if let Some(block) = current_block.as_mut() {
+            block.extend_from_slice(line);
+            block.push(b'\n');
+        }
+    }
+
+    blocks.extend(current_block);
+    blocks.retain(|block| !block.trim_ascii().is_empty());
+    blocks
+}
+
+/// The file `--output` writes the answer to, created before the request is sent so a path that
+/// can't be written fails before the answer is paid for.
+struct OutputFile {
+    path: PathBuf,
+    file: std::fs::File,
+}
+
+impl OutputFile {
+    fn create(path: &Path) -> Result<Self, Error> {
+        let file = std::fs::File::create(path).map_err(|source| Error::Output {
+            path: path.to_path_buf(),
+            source,
+        })?;
+        Ok(Self {
+            path: path.to_path_buf(),
+            file,
+        })
+    }
+
+    /// Write the answer as it is, ending with a newline.
+    fn write(mut self, answer: &[u8]) -> Result<(), Error> {
+        let mut text = answer.to_vec();
+        if !text.ends_with(b"\n") {
+            text.push(b'\n');
+        }
+        self.file.write_all(&text).map_err(|source| Error::Output {
+            path: self.path,
+            source,
+        })
+    }
+}
+
 impl Query {
-    const ANSI_BLUE_START: &'static [u8] = b"\x1b[94m";
-    const ANSI_BLUE_END: &'static [u8] = b"\x1b[0m";
-    const ANSI_PURPLE_START: &'static [u8] = b"\x1b[95m";
-    const ANSI_PURPLE_END: &'static [u8] = b"\x1b[0m";
-
-    fn assistant_write(msg: Vec<u8>) -> Result<(), Error> {
-        std::io::stdout().write_all(Self::ANSI_PURPLE_START)?;
-        std::io::stdout().write_all(&msg)?;
-        std::io::stdout().write_all(Self::ANSI_PURPLE_END)?;
+    fn assistant_write(palette: &Palette, msg: Vec<u8>) -> Result<(), Error> {
+        std::io::stdout().write_all(&palette.paint(palette.assistant, &msg))?;
         std::io::stdout().flush()?;
         Ok(())
     }
@@ -87,22 +290,79 @@ impl Query {
         };
         let mut query_messages = self.assistant.init_messages();
         query_messages.extend(messages);
+        let output_file = self.output.as_deref().map(OutputFile::create).transpose()?;
+
+        let request_hash = match self.assistant.config.deterministic {
+            true => Some(self.assistant.request_hash(query_messages.clone())?),
+            false => None,
+        };
 
         let (resp_tx, mut resp_rx) = tokio::sync::mpsc::channel(10);
         self.assistant.handle_input(query_messages, resp_tx);
 
+        if let Some(label) = self.assistant.config.persona_label.as_deref() {
+            Self::assistant_write(&self.palette, format!("{}: ", label).into_bytes())?;
+        }
+
         let (out_tx, mut out_rx) = tokio::sync::mpsc::channel::<Vec<u8>>(10);
-        tokio::spawn(async move {
+        let palette = self.palette;
+        let output = tokio::spawn(async move {
             tracing::debug!("output task started");
             while let Some(msg) = out_rx.recv().await {
-                Self::assistant_write(msg)?;
+                Self::assistant_write(&palette, msg)?;
             }
             Ok::<(), Error>(())
         });
 
+        // Only for answers that aren't streamed, which otherwise show nothing for a while
+        let mut spinner = (self.spinner && std::io::stderr().is_terminal()).then(Spinner::start);
         while let Some(event) = resp_rx.recv().await {
+            if let Some(spinner) = spinner.take() {
+                spinner.stop().await;
+            }
             tracing::debug!("event: {:?}", event);
-            let _ = out_tx.send(self.handle_event(event)?).await;
+            let msg = self.handle_event(event)?;
+            let _ = out_tx.send(msg).await;
+        }
+        if let Some(spinner) = spinner {
+            spinner.stop().await;
+        }
+        if let Some(post_processor) = &self.post_processor {
+            let answer = self.state.messages.concat();
+            let processed = post_processor.apply(&String::from_utf8_lossy(&answer))?;
+            self.state.set_message(processed.clone().into_bytes());
+            let _ = out_tx.send(processed.into_bytes()).await;
+        }
+        // Everything must be printed before we return, callers may print right after.
+        drop(out_tx);
+        output.await??;
+
+        if let Some(output_file) = output_file {
+            output_file.write(&self.state.messages.concat())?;
+        }
+
+        if let Some(request_hash) = request_hash {
+            eprintln!("\nrequest hash: {}", request_hash);
+        }
+
+        if self.show_usage {
+            let usage = self.usage();
+            let line = format!(
+                "\ntokens: {} in / {} out\n",
+                usage.input_tokens, usage.output_tokens
+            );
+            std::io::stderr()
+                .write_all(&self.palette.paint(self.palette.usage, line.as_bytes()))?;
+        }
+
+        if self.state.truncated {
+            let warning = "\nwarning: the answer was cut off at the token limit, raise --max-tokens for all of it\n";
+            std::io::stderr()
+                .write_all(&self.palette.paint(self.palette.warning, warning.as_bytes()))?;
+        }
+
+        if self.apply {
+            self.apply_hunks()?;
         }
 
         if self.execute {
@@ -110,7 +370,14 @@ impl Query {
             print!("\r\x1b[K");
             std::io::stdout().flush()?;
 
-            match self.select(&self.state.get_code_blocks()) {
+            let code_blocks = self.state.get_code_blocks();
+            let code = match self.skip_confirm {
+                true => code_blocks.into_iter().next(),
+                false => self
+                    .select(&code_blocks)
+                    .filter(|code| !self.execute_confirm || self.confirm(code)),
+            };
+            match code {
                 None => {}
                 Some(code) => {
                     let mut cmd = Command::new("bash");
@@ -121,6 +388,13 @@ impl Query {
                     child.stdin.as_mut().unwrap().write_all(&code)?;
                     let output = child.wait_with_output()?;
 
+                    if let Some(audit_log) = &self.audit_log {
+                        let command = String::from_utf8_lossy(&code);
+                        if let Err(e) = audit_log.record(&command, output.status.code()) {
+                            tracing::error!("error writing audit log: {}", e);
+                        }
+                    }
+
                     // Print both stdout and stderr
                     std::io::stdout().write_all(&output.stdout)?;
                     std::io::stderr().write_all(&output.stderr)?;
@@ -145,7 +419,7 @@ impl Query {
         }
         std::io::stdout().flush().unwrap();
 
-        let exit = [Query::ANSI_BLUE_START, b"exit ", Query::ANSI_BLUE_END].concat();
+        let exit = self.palette.paint(self.palette.selector, b"exit ");
         if code_blocks.is_empty() {
             return None;
         }
@@ -153,12 +427,11 @@ impl Query {
         let selections = code_blocks
             .iter()
             .map(|block| {
-                format!(
-                    "{}{}{}",
-                    String::from_utf8_lossy(Self::ANSI_PURPLE_START),
-                    String::from_utf8_lossy(block).trim(),
-                    String::from_utf8_lossy(Self::ANSI_PURPLE_END),
-                )
+                let block = String::from_utf8_lossy(block);
+                let block = self
+                    .palette
+                    .paint(self.palette.code_block, block.trim().as_bytes());
+                String::from_utf8_lossy(&block).to_string()
             })
             .chain(std::iter::once(String::from_utf8_lossy(&exit).to_string()))
             .collect::<Vec<String>>();
@@ -174,17 +447,97 @@ impl Query {
         }
     }
 
+    /// Pick hunks of the diffs in the answer to apply to the working directory, one at a time,
+    /// until none are left or `exit` is picked.
+    fn apply_hunks(&self) -> Result<(), Error> {
+        let answer = String::from_utf8_lossy(&self.state.messages.concat()).to_string();
+        let mut hunks = patch::parse(&answer);
+        let root = std::env::current_dir()?;
+        while !hunks.is_empty() {
+            let exit = self.palette.paint(self.palette.selector, b"exit");
+            let items = hunks
+                .iter()
+                .map(|hunk| format!("{} {}", hunk.path, hunk.header))
+                .chain(std::iter::once(String::from_utf8_lossy(&exit).to_string()))
+                .collect::<Vec<_>>();
+            let selection = match dialoguer::Select::new()
+                .with_prompt("Apply")
+                .items(&items)
+                .default(0)
+                .interact()
+            {
+                Ok(selection) if selection < hunks.len() => selection,
+                _ => break,
+            };
+            let hunk = hunks.remove(selection);
+            std::io::stdout().write_all(
+                &self
+                    .palette
+                    .paint(self.palette.code_block, hunk.to_string().as_bytes()),
+            )?;
+            let apply = dialoguer::Confirm::new()
+                .with_prompt(format!("Apply to {}?", hunk.path))
+                .default(false)
+                .interact()
+                .unwrap_or(false);
+            if !apply {
+                continue;
+            }
+            match hunk.apply(&root) {
+                Ok(()) => println!("applied to {}", hunk.path),
+                Err(e) => eprintln!("error: {}", e),
+            }
+        }
+        Ok(())
+    }
+
+    /// Show the command and ask whether to run it, defaulting to no.
+    fn confirm(&self, code: &[u8]) -> bool {
+        let code = String::from_utf8_lossy(code);
+        let _ = std::io::stdout().write_all(&self.palette.paint(
+            self.palette.code_block,
+            format!("{}\n", code.trim()).as_bytes(),
+        ));
+        dialoguer::Confirm::new()
+            .with_prompt("Run this?")
+            .default(false)
+            .interact()
+            .unwrap_or(false)
+    }
+
     #[tracing::instrument]
     pub async fn prompt_user_input() -> Result<Vec<Message>, Error> {
+        let line = Self::read_prompt().await?.unwrap_or_default();
+        Ok(vec![Message::from(line)])
+    }
+
+    /// A trimmed line typed after a `> ` prompt, or `None` at the end of input.
+    pub async fn read_prompt() -> Result<Option<String>, Error> {
         std::io::stdout().write_all(b"> ")?;
         std::io::stdout().flush()?;
         let mut line = String::new();
-        std::io::stdin().read_line(&mut line)?;
-        Ok(vec![Message::from(line.trim().to_string())])
+        match std::io::stdin().read_line(&mut line)? {
+            0 => Ok(None),
+            _ => Ok(Some(line.trim().to_string())),
+        }
+    }
+
+    /// The text of the answer, as printed.
+    pub fn answer(&self) -> String {
+        String::from_utf8_lossy(&self.state.messages.concat()).to_string()
+    }
+
+    /// Tokens used by the query so far.
+    pub fn usage(&self) -> Usage {
+        self.state.usage
     }
 
     #[tracing::instrument(skip(self))]
     pub fn handle_event(&mut self, event: TextEvent) -> Result<Vec<u8>, Error> {
+        if event.stop_reason() == Some(&StopReason::MaxTokens) {
+            self.state.truncated = true;
+        }
+        self.state.add_usage(&event);
         match event {
             TextEvent::MessageStart { message } => message
                 .content
@@ -204,18 +557,44 @@ impl Query {
             TextEvent::ContentBlockDelta { index, delta } => {
                 self.handle_content_block_delta(index, delta)
             }
-            TextEvent::ContentBlockStop { .. } => Ok(vec![]),
+            TextEvent::ContentBlockStop { .. } => self.handle_content_block_stop(),
+            TextEvent::Interrupted => {
+                eprintln!("\nresponse interrupted");
+                Ok(vec![])
+            }
+            TextEvent::Error { message } => {
+                eprintln!("\nerror: {}", message);
+                Ok(vec![])
+            }
             _ => Ok(vec![]),
         }
     }
 
+    /// A post-processed answer is kept back to be printed once complete, thinking still shows as
+    /// it comes.
     pub fn handle_message_bytes(&mut self, index: usize, msg: Vec<u8>) -> Result<Vec<u8>, Error> {
         self.state.add_message(index, msg.clone());
-        Ok(msg)
+        match self.post_processor {
+            Some(_) => Ok(vec![]),
+            None => Ok(msg),
+        }
+    }
+
+    /// Thinking is hidden unless `show_thinking` is set, in which case it is printed in the
+    /// palette's thinking color.
+    pub fn handle_thinking_bytes(&mut self, msg: Vec<u8>) -> Result<Vec<u8>, Error> {
+        if !self.show_thinking {
+            return Ok(vec![]);
+        }
+        self.state.add_thinking(&msg);
+        Ok(self.palette.paint(self.palette.thinking, &msg))
     }
 
     pub fn handle_content(&mut self, index: usize, content: Content) -> Result<Vec<u8>, Error> {
-        self.handle_message_bytes(index, content.bytes())
+        match content.thinking() {
+            Some(thinking) => self.handle_thinking_bytes(format!("{}\n", thinking).into_bytes()),
+            None => self.handle_message_bytes(index, content.bytes()),
+        }
     }
 
     pub fn handle_content_block_start(
@@ -223,7 +602,13 @@ impl Query {
         index: usize,
         block: ContentBlock,
     ) -> Result<Vec<u8>, Error> {
-        self.handle_message_bytes(index, block.bytes())
+        match block.thinking() {
+            Some(thinking) => {
+                self.state.in_thinking = true;
+                self.handle_thinking_bytes(thinking.into_bytes())
+            }
+            None => self.handle_message_bytes(index, block.bytes()),
+        }
     }
 
     pub fn handle_content_block_delta(
@@ -231,7 +616,21 @@ impl Query {
         index: usize,
         delta: ContentDelta,
     ) -> Result<Vec<u8>, Error> {
-        self.handle_message_bytes(index, delta.bytes())
+        if let ContentDelta::SignatureDelta { .. } = delta {
+            return Ok(vec![]);
+        }
+        match delta.thinking() {
+            Some(thinking) => self.handle_thinking_bytes(thinking.into_bytes()),
+            None => self.handle_message_bytes(index, delta.bytes()),
+        }
+    }
+
+    /// Separates a finished thinking block from the answer that follows it.
+    pub fn handle_content_block_stop(&mut self) -> Result<Vec<u8>, Error> {
+        match std::mem::take(&mut self.state.in_thinking) {
+            true => self.handle_thinking_bytes(b"\n".to_vec()),
+            false => Ok(vec![]),
+        }
     }
 
     pub fn builder(assistant: Assistant) -> Builder {
@@ -242,12 +641,28 @@ impl Query {
 pub struct Builder {
     assistant: Assistant,
     execute: bool,
+    show_thinking: bool,
+    skip_confirm: bool,
+    execute_confirm: bool,
+    show_usage: bool,
+    apply: bool,
+    post_processor: Option<PostProcessor>,
+    output: Option<PathBuf>,
+    spinner: bool,
 }
 
 impl Builder {
     pub fn new(assistant: Assistant) -> Self {
         Self {
             execute: false,
+            show_thinking: false,
+            skip_confirm: false,
+            execute_confirm: true,
+            show_usage: false,
+            apply: false,
+            post_processor: None,
+            output: None,
+            spinner: false,
             assistant,
         }
     }
@@ -257,11 +672,77 @@ impl Builder {
         self
     }
 
+    pub fn show_thinking(mut self, show_thinking: bool) -> Self {
+        self.show_thinking = show_thinking;
+        self
+    }
+
+    /// Run the first code block without asking which one, if any.
+    pub fn skip_confirm(mut self, skip_confirm: bool) -> Self {
+        self.skip_confirm = skip_confirm;
+        self
+    }
+
+    /// Ask before running the picked code block.
+    pub fn execute_confirm(mut self, execute_confirm: bool) -> Self {
+        self.execute_confirm = execute_confirm;
+        self
+    }
+
+    /// Print the tokens used once the answer is complete.
+    pub fn show_usage(mut self, show_usage: bool) -> Self {
+        self.show_usage = show_usage;
+        self
+    }
+
+    /// Offer to apply the diffs in the answer to the working directory.
+    pub fn apply(mut self, apply: bool) -> Self {
+        self.apply = apply;
+        self
+    }
+
+    /// Transform the complete answer before printing it, instead of streaming it.
+    pub fn post_processor(mut self, post_processor: Option<PostProcessor>) -> Self {
+        self.post_processor = post_processor;
+        self
+    }
+
+    /// Also write the plain answer to this file once it is complete.
+    pub fn output(mut self, output: Option<PathBuf>) -> Self {
+        self.output = output;
+        self
+    }
+
+    /// Show a spinner on a terminal until the answer arrives.
+    pub fn spinner(mut self, spinner: bool) -> Self {
+        self.spinner = spinner;
+        self
+    }
+
     pub fn build(self) -> Query {
+        let audit_log = self
+            .assistant
+            .config
+            .audit_log
+            .clone()
+            .or_else(AuditLog::default_path)
+            .map(AuditLog::new);
         Query {
             execute: self.execute,
+            show_thinking: self.show_thinking,
+            skip_confirm: self.skip_confirm,
+            execute_confirm: self.execute_confirm,
+            show_usage: self.show_usage,
+            apply: self.apply,
+            audit_log,
+            post_processor: self.post_processor,
+            output: self.output,
+            spinner: self.spinner,
+            palette: self.assistant.config.palette,
             assistant: self.assistant,
-            state: Default::default(),
+            state: QueryState::with_width(
+                crossterm::terminal::size().map_or(0, |(width, _)| width as usize),
+            ),
         }
     }
 }
@@ -285,4 +766,140 @@ mod tests {
         let blocks = state.get_code_blocks();
         assert_eq!(blocks.len(), 5);
     }
+
+    #[test]
+    fn test_get_fenced_code_blocks() {
+        let mut state = QueryState::new();
+        state.add_message(
+            0,
+            b"```bash\nfor f in *.log; do\n  gzip \"$f\"\ndone\n```\n```sh\nls -la\n```\n".to_vec(),
+        );
+
+        assert_eq!(
+            state.get_code_blocks(),
+            vec![
+                b"for f in *.log; do\n  gzip \"$f\"\ndone\n".to_vec(),
+                b"ls -la\n".to_vec()
+            ]
+        );
+    }
+
+    #[test]
+    fn test_get_fenced_code_block_in_prose() {
+        let mut state = QueryState::new();
+        state.add_message(0, b"```bash\nls -la /tmp/\n```".to_vec());
+        assert_eq!(state.get_code_blocks(), vec![b"ls -la /tmp/\n".to_vec()]);
+
+        let mut state = QueryState::new();
+        state.add_message(
+            0,
+            b"To free up space, remove old logs:\n\n```bash\ngzip /var/log/*.log\n```\n\nCheck the result with `df -h`.\n".to_vec(),
+        );
+        assert_eq!(
+            state.get_code_blocks(),
+            vec![b"gzip /var/log/*.log\n".to_vec()]
+        );
+    }
+
+    #[test]
+    fn test_thinking_not_in_code_blocks() {
+        let mut state = QueryState::new();
+        state.add_thinking(b"The user wants to list files.\n");
+        state.add_message(0, b"ls -la\n".to_vec());
+
+        assert_eq!(state.get_code_blocks(), vec![b"ls -la\n".to_vec()]);
+        assert_eq!(state.line_no, 2);
+    }
+
+    #[test]
+    fn test_line_no_wraps() {
+        let mut state = QueryState::with_width(80);
+        state.add_message(0, "a".repeat(200).into_bytes());
+        assert_eq!(state.line_no, 2);
+        state.add_message(0, format!("{}\nls\n", "b".repeat(40)).into_bytes());
+        assert_eq!(state.line_no, 4);
+
+        let mut state = QueryState::with_width(80);
+        state.add_message(0, format!("{}\n", "a".repeat(80)).into_bytes());
+        assert_eq!(state.line_no, 1);
+
+        let mut state = QueryState::with_width(80);
+        state.add_message(0, "日".repeat(41).into_bytes());
+        assert_eq!(state.line_no, 1);
+    }
+
+    #[test]
+    fn test_usage() {
+        let events: Vec<TextEvent> = serde_json::from_str(
+            r#"[
+                {"type":"message_start","message":{"id":"msg_1","type":"message","role":"assistant","model":"claude","content":[],"stop_reason":null,"stop_sequence":null,"usage":{"input_tokens":45,"output_tokens":1}}},
+                {"type":"message_delta","delta":{"stop_reason":null,"stop_sequence":null},"usage":{"output_tokens":100}},
+                {"type":"message_delta","delta":{"stop_reason":"end_turn","stop_sequence":null},"usage":{"output_tokens":210}}
+            ]"#,
+        )
+        .unwrap();
+        let mut state = QueryState::new();
+        for event in &events {
+            state.add_usage(event);
+        }
+        assert_eq!(
+            state.usage,
+            Usage {
+                input_tokens: 45,
+                output_tokens: 210
+            }
+        );
+    }
+
+    #[test]
+    fn test_json_answer() {
+        let response: Response = serde_json::from_str(
+            r#"{"id":"msg_1","type":"message","role":"assistant","model":"claude","content":[{"type":"text","text":"ls"},{"type":"text","text":" -la"}],"stop_reason":"end_turn","stop_sequence":null,"usage":{"input_tokens":12,"output_tokens":3}}"#,
+        )
+        .unwrap();
+        let mut json = serde_json::to_value(JsonAnswer::from(response.clone())).unwrap();
+        let created_at = json.as_object_mut().unwrap().remove("created_at").unwrap();
+        assert!(created_at.as_str().is_some_and(|at| at.ends_with('Z')));
+        assert_eq!(
+            json,
+            serde_json::json!({
+                "model": "claude",
+                "content": "ls -la",
+                "stop_reason": "end_turn",
+                "usage": {"input_tokens": 12, "output_tokens": 3}
+            })
+        );
+        assert_eq!(
+            serde_json::to_value(JsonItem::Error {
+                error: "overloaded".to_string()
+            })
+            .unwrap(),
+            serde_json::json!({"error": "overloaded"})
+        );
+
+        let answer = JsonAnswer {
+            request_hash: Some("abc".to_string()),
+            ..JsonAnswer::from(response)
+        };
+        assert_eq!(serde_json::to_value(answer).unwrap()["request_hash"], "abc");
+    }
+
+    #[test]
+    fn test_write_output() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("out.txt");
+        OutputFile::create(&path).unwrap().write(b"ls -la").unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "ls -la\n");
+        OutputFile::create(&path)
+            .unwrap()
+            .write(b"ls -la\n")
+            .unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "ls -la\n");
+
+        let missing = dir.path().join("missing").join("out.txt");
+        assert!(matches!(
+            OutputFile::create(&missing),
+            Err(Error::Output { .. })
+        ));
+    }
 }
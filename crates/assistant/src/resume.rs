@@ -0,0 +1,137 @@
+//! Stitching a restarted stream onto the text received before the connection dropped.
+use rgpt_types::message::{Message, Role};
+
+/// Continuation text buffered before looking for a regenerated overlap.
+const LOOKAHEAD: usize = 64;
+/// Shortest non-whitespace overlap that is treated as regenerated rather than coincidental.
+const MIN_OVERLAP: usize = 8;
+
+/// The restarted request prefills `received` as the assistant's turn, so the model should carry on
+/// where it left off. It may still repeat the tail of what was already shown (at least the trailing
+/// whitespace, which the API does not accept in a prefill), so the head of the continuation is held
+/// back until the overlap can be removed.
+#[derive(Debug)]
+pub struct Resume {
+    received: String,
+    pending: String,
+    flushed: bool,
+}
+
+impl Resume {
+    pub fn new(received: String) -> Self {
+        Self {
+            received,
+            pending: String::new(),
+            flushed: false,
+        }
+    }
+
+    /// What to send as the assistant prefill, if anything.
+    pub fn prefill(&self) -> Option<String> {
+        let prefill = self.received.trim_end();
+        (!prefill.is_empty()).then(|| prefill.to_string())
+    }
+
+    /// Feed continuation text, returning what can be shown now.
+    pub fn push(&mut self, text: &str) -> Option<String> {
+        if self.flushed {
+            return (!text.is_empty()).then(|| text.to_string());
+        }
+        self.pending.push_str(text);
+        if self.pending.len() < LOOKAHEAD {
+            return None;
+        }
+        self.flush()
+    }
+
+    /// Release whatever is still held back, e.g. when the continuation ends early.
+    pub fn flush(&mut self) -> Option<String> {
+        if self.flushed {
+            return None;
+        }
+        self.flushed = true;
+        let pending = std::mem::take(&mut self.pending);
+        let skip = overlap(&self.received, &pending);
+        let text = &pending[skip..];
+        (!text.is_empty()).then(|| text.to_string())
+    }
+}
+
+/// `messages` with `partial` as the start of the answer, for the model to continue. A request
+/// that already ends with a partial answer has it extended.
+pub fn continuation(mut messages: Vec<Message>, partial: &str) -> Vec<Message> {
+    match messages.last_mut() {
+        Some(last) if last.role == Role::Assistant => last.content.push_str(partial),
+        _ => messages.push(Message::assistant(partial)),
+    }
+    // The API rejects a trailing answer that ends in whitespace
+    if let Some(last) = messages.last_mut() {
+        last.content.truncate(last.content.trim_end().len());
+    }
+    messages
+}
+
+/// Length of the longest head of `continuation` that `received` already ends with.
+fn overlap(received: &str, continuation: &str) -> usize {
+    continuation
+        .char_indices()
+        .map(|(i, c)| i + c.len_utf8())
+        .rfind(|&end| {
+            let head = &continuation[..end];
+            received.ends_with(head)
+                && (end >= MIN_OVERLAP || head.chars().all(char::is_whitespace))
+        })
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn resume(received: &str, chunks: &[&str]) -> String {
+        let mut resume = Resume::new(received.to_string());
+        let mut out: String = chunks.iter().filter_map(|c| resume.push(c)).collect();
+        out.extend(resume.flush());
+        out
+    }
+
+    #[test]
+    fn test_resume_dedup() {
+        // trailing whitespace dropped from the prefill comes back
+        assert_eq!(
+            resume("for f in *; do\n", &["\n  echo", " $f\ndone"]),
+            "  echo $f\ndone"
+        );
+        // regenerated tail
+        assert_eq!(
+            resume("The quick brown fox", &["brown fox", " jumps"]),
+            " jumps"
+        );
+        // short coincidental overlap is kept
+        assert_eq!(resume("I saw a", &["a cat"]), "a cat");
+        // past the lookahead everything streams through
+        let long = "x".repeat(LOOKAHEAD);
+        assert_eq!(resume("y ", &[" ", &long, "z"]), format!("{}z", long));
+    }
+
+    #[test]
+    fn test_continuation() {
+        let question = Message::from("count to 3".to_string());
+        let messages = continuation(vec![question.clone()], "1, 2, ");
+        assert_eq!(messages[1].role, Role::Assistant);
+        assert_eq!(messages[1].content, "1, 2,");
+
+        let messages = continuation(messages, " 3");
+        assert_eq!(messages.len(), 2);
+        assert_eq!(messages[1].content, "1, 2, 3");
+    }
+
+    #[test]
+    fn test_prefill() {
+        assert_eq!(
+            Resume::new("ls -l \n".into()).prefill().as_deref(),
+            Some("ls -l")
+        );
+        assert_eq!(Resume::new(" \n".into()).prefill(), None);
+    }
+}
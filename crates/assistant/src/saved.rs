@@ -0,0 +1,93 @@
+use std::path::PathBuf;
+
+use crate::{error::Error, pagetree::SerializedTree};
+
+/// A session saved under a name, in `sessions/` in the config dir.
+#[derive(Debug, Clone)]
+pub struct SavedSession {
+    name: String,
+    path: PathBuf,
+}
+
+impl SavedSession {
+    fn dir() -> Option<PathBuf> {
+        rgpt_utils::paths::config_dir().map(|dir| dir.join("sessions"))
+    }
+
+    pub fn new(name: &str) -> Result<Self, Error> {
+        let dir = Self::dir().ok_or_else(|| Error::Generic("no config directory".to_string()))?;
+        Self::in_dir(dir, name)
+    }
+
+    /// The name becomes the file name, so it can't contain a path.
+    pub fn in_dir(dir: PathBuf, name: &str) -> Result<Self, Error> {
+        if name.is_empty() || name.starts_with('.') || name.contains(['/', '\\']) {
+            return Err(Error::Generic(format!("invalid session name '{}'", name)));
+        }
+        Ok(Self {
+            name: name.to_string(),
+            path: dir.join(format!("{}.json", name)),
+        })
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn save(&self, tree: &SerializedTree) -> Result<(), Error> {
+        if let Some(dir) = self.path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        rgpt_utils::fs::write_atomic(&self.path, &serde_json::to_vec(tree)?)?;
+        tracing::debug!("saved session {}", self.path.display());
+        Ok(())
+    }
+
+    pub fn load(&self) -> Result<SerializedTree, Error> {
+        match std::fs::read(&self.path) {
+            Ok(tree) => Ok(serde_json::from_slice(&tree)?),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Err(Error::Generic(format!(
+                "no saved session named '{}'",
+                self.name
+            ))),
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pagetree::{NodeId, SerializedNode};
+
+    #[test]
+    fn test_save_load() {
+        let dir = tempfile::tempdir().unwrap();
+        let saved = SavedSession::in_dir(dir.path().to_path_buf(), "review").unwrap();
+        assert!(saved.load().is_err());
+
+        let node = |id, parent, user: &str| SerializedNode {
+            id: NodeId::Node(id),
+            parent,
+            user: vec![user.to_string()],
+            assistant: vec![],
+            locked: false,
+            user_meta: None,
+            assistant_meta: None,
+            model: None,
+        };
+        let tree = SerializedTree {
+            system: vec!["Be brief.".to_string()],
+            nodes: vec![
+                node(0, NodeId::Root, "first"),
+                node(1, NodeId::Node(0), "branch a"),
+                node(2, NodeId::Node(0), "branch b"),
+            ],
+            current: NodeId::Node(2),
+        };
+        saved.save(&tree).unwrap();
+        assert_eq!(saved.load().unwrap(), tree);
+
+        assert!(SavedSession::in_dir(dir.path().to_path_buf(), "../escape").is_err());
+    }
+}
@@ -1,26 +1,54 @@
 use crate::textarea::SessionAreaId;
 use crate::textarea::SessionTextArea;
 use crossterm::{
-    event::{DisableMouseCapture, EnableMouseCapture},
-    terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen},
+    event::{
+        DisableMouseCapture, EnableMouseCapture, KeyboardEnhancementFlags, MouseButton, MouseEvent,
+        MouseEventKind, PopKeyboardEnhancementFlags, PushKeyboardEnhancementFlags,
+    },
+    terminal::{
+        disable_raw_mode, enable_raw_mode, supports_keyboard_enhancement, EnterAlternateScreen,
+        LeaveAlternateScreen,
+    },
 };
 use futures::stream::StreamExt;
 use ratatui::Terminal;
-use ratatui::{backend::CrosstermBackend, layout::Rect};
+use ratatui::{
+    backend::CrosstermBackend,
+    layout::{Position, Rect},
+};
 use ratatui::{
     layout::{Constraint, Direction, Layout},
+    style::{Color, Style},
+    text::Line,
+    widgets::{Block, Borders, Paragraph},
     Frame,
 };
-use std::{io::stdout, rc::Rc};
-use tui_textarea::{Input, Key, TextArea};
+use rgpt_utils::diff::{diff_lines, DiffLine, DiffTag};
+use std::{
+    collections::{HashMap, HashSet},
+    io::stdout,
+    rc::Rc,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use tokio::task::AbortHandle;
+use tui_textarea::{Input, Key};
 
 use crate::{
+    checkpoint::Checkpoint,
+    clipboard::Clipboard,
+    config::Autosave,
     error::Error,
-    pagetree::{NodeId, Root},
+    export,
+    history::{History, HistoryEntry},
+    keymap::Action,
+    pagetree::{MessageMeta, NodeId, Root, SerializedTree, Turn},
+    resume::continuation,
+    saved::SavedSession,
     Assistant,
 };
 use rgpt_types::{
-    completion::TextEvent,
+    completion::{StopReason, TextEvent},
     message::{Message, Role},
 };
 
@@ -36,9 +64,118 @@ impl Session {
     }
 
     pub async fn start(&mut self, messages: &[Message]) -> Result<(), Error> {
+        if self.inner.autosave().is_some() {
+            self.inner.offer_restore()?;
+        }
         self.inner.run(messages).await?;
         Ok(())
     }
+
+    /// Continue an archived session, saving it back to the same entry.
+    pub async fn open(&mut self, history: History, entry: &HistoryEntry) -> Result<(), Error> {
+        self.inner.layout.restore(history.load(entry)?)?;
+        self.inner.history = Some((history, entry.id.clone()));
+        self.inner.run(&[]).await?;
+        Ok(())
+    }
+
+    /// Continue a session saved with Ctrl-S, which then saves back to it.
+    pub async fn load(&mut self, saved: SavedSession) -> Result<(), Error> {
+        self.inner.layout.restore(saved.load()?)?;
+        self.inner.saved = Some(saved);
+        self.inner.run(&[]).await?;
+        Ok(())
+    }
+}
+
+/// Frames of the status line's spinner, one per [`SPINNER_INTERVAL`].
+pub(crate) const SPINNER: [char; 10] = ['⠋', '⠙', '⠹', '⠸', '⠼', '⠴', '⠦', '⠧', '⠇', '⠏'];
+pub(crate) const SPINNER_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Lines a turn of the mouse wheel scrolls.
+const MOUSE_SCROLL_ROWS: i16 = 3;
+
+/// Events of an answer stream, tagged with the node the answer goes to.
+type EventSender = tokio::sync::mpsc::Sender<(NodeId, TextEvent)>;
+
+/// How the answer on a compared branch went, shown while it is focused.
+#[derive(Debug, Clone)]
+pub struct BranchStats {
+    pub model: String,
+    pub submitted: Instant,
+    /// Time to the start of the answer.
+    pub latency: Option<Duration>,
+    pub input_tokens: Option<usize>,
+    pub output_tokens: Option<usize>,
+}
+
+impl BranchStats {
+    fn new(model: String) -> Self {
+        BranchStats {
+            model,
+            submitted: Instant::now(),
+            latency: None,
+            input_tokens: None,
+            output_tokens: None,
+        }
+    }
+}
+
+impl std::fmt::Display for BranchStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.model)?;
+        if let Some(latency) = self.latency {
+            write!(f, " · {}ms", latency.as_millis())?;
+        }
+        let tokens = |count: Option<usize>| count.map_or("?".to_string(), |n| n.to_string());
+        if self.input_tokens.is_some() || self.output_tokens.is_some() {
+            write!(
+                f,
+                " · {} in / {} out tokens",
+                tokens(self.input_tokens),
+                tokens(self.output_tokens)
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// The tasks of an answer on its way, the request and the one passing its events on.
+struct InFlight {
+    request: AbortHandle,
+    forward: AbortHandle,
+}
+
+impl InFlight {
+    fn is_finished(&self) -> bool {
+        self.forward.is_finished()
+    }
+
+    fn abort(&self) {
+        self.request.abort();
+        self.forward.abort();
+    }
+}
+
+/// Send `messages` to `assistant`, with its answer going to `node`.
+fn submit(
+    assistant: &Assistant,
+    node: NodeId,
+    messages: Vec<Message>,
+    tx: &EventSender,
+) -> InFlight {
+    let (event_tx, mut event_rx) = tokio::sync::mpsc::channel(100);
+    let request = assistant.handle_input(messages, event_tx).abort_handle();
+    let tx = tx.clone();
+    let forward = tokio::spawn(async move {
+        while let Some(event) = event_rx.recv().await {
+            if tx.send((node, event)).await.is_err() {
+                break;
+            }
+        }
+    })
+    .abort_handle();
+    InFlight { request, forward }
 }
 
 pub struct SessionLayout<'a> {
@@ -46,10 +183,49 @@ pub struct SessionLayout<'a> {
     pub current_node: NodeId,
     pub active: SessionAreaId,
 
-    pub assistant_stream_node: Option<NodeId>,
+    /// Diff of the displayed answer against its next sibling, shown in place of the assistant
+    /// pane while set.
+    pub diff: Option<(String, Vec<DiffLine>)>,
+
+    /// Transient message shown on the bottom line until the next key press.
+    pub status: Option<String>,
+
+    /// Node whose answer stream dropped before completing, until it is retried.
+    pub interrupted: Option<NodeId>,
 
-    // FIXME: patch until tui-textarea implements wrapping.
-    pub max_line_length: usize,
+    /// Why the last answer failed, shown in red until another one starts.
+    pub error: Option<String>,
+
+    /// Node whose answer was cut off at the token limit, until it is continued.
+    pub truncated: Option<NodeId>,
+
+    /// Nodes whose streaming answer continues the text already in their assistant pane.
+    pub stream_prefilled: HashSet<NodeId>,
+
+    /// Nodes with an answer on its way, from when it is requested until it stops streaming in.
+    pub streaming: HashSet<NodeId>,
+
+    /// Model, latency and token counts of the branches of a model comparison.
+    pub branch_stats: HashMap<NodeId, BranchStats>,
+
+    /// Draw long assistant lines in full, scrolling sideways with the cursor, instead of
+    /// truncating them.
+    pub hscroll: bool,
+    /// Draw answers that aren't being edited as Markdown.
+    pub render_markdown: bool,
+    /// Draw the status line under the panes.
+    pub status_line: bool,
+    /// Model answers go to unless their branch has its own, `None` for the provider's default.
+    pub model: Option<String>,
+    /// Frame of the spinner shown while answers stream in.
+    spinner: usize,
+
+    /// Rows the drawn answer is scrolled down, pinned there by PageUp/PageDown or the wheel
+    /// instead of following its cursor, e.g. as it streams in.
+    pub answer_scroll: Option<u16>,
+
+    /// Where each pane was last drawn, to find the one under the mouse.
+    pane_rects: Vec<(SessionAreaId, Rect)>,
 }
 
 impl std::fmt::Debug for SessionLayout<'_> {
@@ -60,31 +236,19 @@ impl std::fmt::Debug for SessionLayout<'_> {
                 self.page_tree.get(self.current_node).unwrap(),
             )
             .field("active", &self.active)
-            .field("max_line_length", &self.max_line_length)
             .finish()
     }
 }
 
 impl<'a> SessionLayout<'a> {
-    fn new(messages: &[Message]) -> Self {
+    fn new(messages: &[Message], assistant_label: &str) -> Self {
         tracing::trace!("messages: {:?}", messages);
-        // FIXME: patch until tui-textarea implements wrapping.
-        let max_line_length = crossterm::terminal::size()
-            .map(|(w, _)| (w.saturating_sub(10)) as usize / 2)
-            .unwrap_or(70);
-        tracing::trace!("max_line_length: {}", max_line_length);
-
         let mut messages = messages.to_vec();
-        messages.push(Message {
-            role: Role::User,
-            content: "".to_string(),
-        });
-        messages.push(Message {
-            role: Role::Assistant,
-            content: "".to_string(),
-        });
+        messages.push(Message::user(""));
+        messages.push(Message::assistant(""));
 
-        let mut page_tree = Root::new(max_line_length);
+        let mut page_tree = Root::new();
+        page_tree.set_assistant_label(assistant_label.to_string());
         let current_node = match page_tree.insert_messages(None, messages) {
             Ok(id) => id,
             Err(e) => {
@@ -98,8 +262,21 @@ impl<'a> SessionLayout<'a> {
             page_tree,
             current_node,
             active,
-            max_line_length,
-            assistant_stream_node: None,
+            diff: None,
+            status: None,
+            interrupted: None,
+            error: None,
+            truncated: None,
+            stream_prefilled: HashSet::new(),
+            streaming: HashSet::new(),
+            branch_stats: HashMap::new(),
+            hscroll: false,
+            render_markdown: false,
+            status_line: false,
+            model: None,
+            spinner: 0,
+            answer_scroll: None,
+            pane_rects: Vec::new(),
         };
         layout.activate(active);
         layout.switch_node(current_node);
@@ -162,6 +339,91 @@ impl<'a> SessionLayout<'a> {
         });
     }
 
+    fn pane_at(&self, column: u16, row: u16) -> Option<SessionAreaId> {
+        self.pane_rects
+            .iter()
+            .find(|(_, rect)| rect.contains(Position { x: column, y: row }))
+            .map(|&(id, _)| id)
+    }
+
+    /// A click focuses the pane under the mouse, the wheel scrolls it.
+    fn mouse(&mut self, mouse: MouseEvent) {
+        let Some(id) = self.pane_at(mouse.column, mouse.row) else {
+            return;
+        };
+        match mouse.kind {
+            MouseEventKind::Down(MouseButton::Left) => self.activate(id),
+            MouseEventKind::ScrollUp => self.scroll_pane(id, -MOUSE_SCROLL_ROWS),
+            MouseEventKind::ScrollDown => self.scroll_pane(id, MOUSE_SCROLL_ROWS),
+            _ => {}
+        }
+    }
+
+    /// Whether the drawn answer scrolls by itself rather than with its cursor, which it does
+    /// unless it is being edited.
+    fn answer_scrolls(&self) -> bool {
+        self.active != SessionAreaId::Assistant || self.assistant_area_to_draw().is_locked()
+    }
+
+    /// Rows of text the assistant pane last had room for.
+    fn answer_height(&self) -> Option<usize> {
+        self.pane_rects
+            .iter()
+            .find(|(id, _)| *id == SessionAreaId::Assistant)
+            .map(|(_, rect)| rect.height.saturating_sub(2) as usize)
+    }
+
+    /// Scroll the drawn answer `rows` down, or up if negative. Back at the bottom it follows the
+    /// cursor again, so a streaming answer keeps scrolling.
+    fn scroll_answer(&mut self, rows: i16) {
+        let Some(height) = self.answer_height() else {
+            return;
+        };
+        let area = self.assistant_area_to_draw();
+        let follow = area.cursor_scroll(height);
+        let bottom = area.bottom_scroll(height);
+        let scroll = self.answer_scroll.unwrap_or(follow);
+        let scroll = scroll.saturating_add_signed(rows).min(bottom);
+        self.answer_scroll = match scroll == bottom && follow == bottom {
+            true => None,
+            false => Some(scroll),
+        };
+    }
+
+    /// Page the drawn answer up or down, if it isn't being edited.
+    fn page_answer(&mut self, up: bool) -> bool {
+        if !self.answer_scrolls() {
+            return false;
+        }
+        let page = self.answer_height().unwrap_or(1).max(1) as i16;
+        self.scroll_answer(if up { -page } else { page });
+        true
+    }
+
+    /// Scroll the pane as drawn, which for the assistant may be the parent's answer.
+    fn scroll_pane(&mut self, id: SessionAreaId, rows: i16) {
+        if id == SessionAreaId::Assistant && self.answer_scrolls() {
+            return self.scroll_answer(rows);
+        }
+        let node = match id {
+            SessionAreaId::Assistant if self.current_node_area(id).is_empty() => {
+                self.page_tree.parent_id(self.current_node)
+            }
+            _ => self.current_node,
+        };
+        let area = match (id, node) {
+            (SessionAreaId::Assistant, NodeId::Node(_)) => self
+                .page_tree
+                .get_mut(node)
+                .map(|node| node.area_mut(SessionAreaId::Assistant)),
+            _ => None,
+        };
+        match area {
+            Some(area) => area.scroll_cursor(rows),
+            None => self.current_node_area_mut(id).scroll_cursor(rows),
+        }
+    }
+
     fn input(&mut self, input: Input) {
         if !self.current_node_area_mut(self.active).input(input.clone()) {
             self.fork_current_node();
@@ -169,45 +431,263 @@ impl<'a> SessionLayout<'a> {
         }
     }
 
+    fn undo(&mut self) {
+        if !self.current_node_area_mut(self.active).undo() {
+            self.set_status("nothing to undo".to_string());
+        }
+    }
+
+    fn redo(&mut self) {
+        if !self.current_node_area_mut(self.active).redo() {
+            self.set_status("nothing to redo".to_string());
+        }
+    }
+
     fn fork_current_node(&mut self) {
         let fork_id = self.page_tree.fork_node(self.current_node);
         self.switch_node(fork_id);
     }
 
-    fn user_text_area_to_draw(&self) -> &TextArea {
-        self.current_node_area(SessionAreaId::User).text_area()
+    /// Fork the sent node on screen without its answer, to edit its prompt and submit it as a
+    /// new branch. The node and the branch after it are kept as they are.
+    fn edit_prompt(&mut self) -> Option<NodeId> {
+        if !self.page_tree.is_locked(self.current_node) {
+            return None;
+        }
+        let fork = self.page_tree.fork_node(self.current_node);
+        let node = self.page_tree.get_mut(fork)?;
+        node.area_mut(SessionAreaId::Assistant).clear();
+        node.area_mut(SessionAreaId::User).move_cursor_to_end();
+        self.switch_node(fork);
+        self.activate(SessionAreaId::User);
+        self.set_status("editing the prompt on a new branch".to_string());
+        Some(fork)
+    }
+
+    fn user_text_area_to_draw(&self) -> &SessionTextArea<'_> {
+        self.current_node_area(SessionAreaId::User)
     }
 
-    fn assistant_text_area_to_draw(&self) -> &TextArea {
+    fn assistant_area_to_draw(&self) -> &SessionTextArea<'_> {
         match self.current_node_area(SessionAreaId::Assistant) {
-            node if node.is_empty() => self.parent_node_area(SessionAreaId::Assistant).text_area(),
-            node => node.text_area(),
+            node if node.is_empty() => self.parent_node_area(SessionAreaId::Assistant),
+            node => node,
         }
     }
 
-    fn system_text_area_to_draw(&self) -> &TextArea {
-        self.current_node_area(SessionAreaId::System).text_area()
+    /// Only a locked answer is drawn as Markdown, one being edited shows the raw text.
+    fn markdown_to_draw(&self) -> bool {
+        self.render_markdown
+            && self.active != SessionAreaId::Assistant
+            && self.assistant_area_to_draw().is_locked()
+    }
+
+    fn toggle_hscroll(&mut self) {
+        self.hscroll = !self.hscroll;
+        let state = if self.hscroll { "on" } else { "off" };
+        self.set_status(format!("horizontal scroll {}", state));
+    }
+
+    fn system_text_area_to_draw(&self) -> &SessionTextArea<'_> {
+        self.current_node_area(SessionAreaId::System)
+    }
+
+    /// The node whose assistant answer is displayed, which is the parent of the current node
+    /// while the current node's assistant area is still empty.
+    fn answer_node(&self) -> NodeId {
+        match self.page_tree.get(self.current_node) {
+            Some(node) if !node.assistant_area.is_empty() => self.current_node,
+            _ => self.page_tree.parent_id(self.current_node),
+        }
+    }
+
+    fn toggle_diff(&mut self) {
+        if self.diff.take().is_some() {
+            return;
+        }
+        let id = self.answer_node();
+        let (Some(node), Some(sibling)) = (self.page_tree.get(id), self.page_tree.next_sibling(id))
+        else {
+            return;
+        };
+        if sibling.id == id {
+            tracing::debug!("no sibling to diff {:?} against", id);
+            return;
+        }
+        let answer = |area: &SessionTextArea| area.message().map(|m| m.content).unwrap_or_default();
+        let title = format!(
+            "diff {} > {}",
+            String::from(node.id),
+            String::from(sibling.id)
+        );
+        let lines = diff_lines(
+            &answer(&node.assistant_area),
+            &answer(&sibling.assistant_area),
+        );
+        self.diff = Some((title, lines));
+    }
+
+    fn diff_to_draw(&self) -> Option<Paragraph<'_>> {
+        let (title, lines) = self.diff.as_ref()?;
+        let lines = lines
+            .iter()
+            .map(|line| {
+                let style = match line.tag {
+                    DiffTag::Equal => Style::default(),
+                    DiffTag::Insert => Style::default().fg(Color::Green),
+                    DiffTag::Delete => Style::default().fg(Color::Red),
+                };
+                Line::styled(line.to_string(), style)
+            })
+            .collect::<Vec<_>>();
+        Some(
+            Paragraph::new(lines)
+                .block(Block::default().borders(Borders::ALL).title(title.clone())),
+        )
+    }
+
+    fn set_status(&mut self, status: String) {
+        tracing::debug!("status: {}", status);
+        self.status = Some(status);
+    }
+
+    /// The status message, or the stats of the focused branch of a model comparison, after which
+    /// branch this is when there are several.
+    fn status_to_draw(&self) -> Option<String> {
+        let status = self.status.clone().or_else(|| {
+            self.branch_stats
+                .get(&self.answer_node())
+                .map(BranchStats::to_string)
+        });
+        let branch = self
+            .page_tree
+            .sibling_index(self.current_node)
+            .filter(|&(_, total)| total > 1)
+            .map(|(current, total)| format!("branch {}/{}", current, total));
+        match (branch, status) {
+            (Some(branch), Some(status)) => Some(format!("{}  {}", branch, status)),
+            (branch, status) => branch.or(status),
+        }
+    }
+
+    /// The error of the last failed answer in red, then the status, then the status line if it's
+    /// on, each on a line.
+    fn footer_to_draw(&self) -> Vec<Line<'static>> {
+        let error = self.error.as_ref().map(|error| {
+            Line::styled(format!("error: {}", error), Style::default().fg(Color::Red))
+        });
+        error
+            .into_iter()
+            .chain(self.status_to_draw().map(Line::from))
+            .chain(self.status_line.then(|| self.status_line_to_draw()))
+            .collect()
+    }
+
+    /// The focused pane and the model of the answer shown, then a spinner while answers are on
+    /// their way. Which branch this is shows in the status above.
+    fn status_line_to_draw(&self) -> Line<'static> {
+        let model = self
+            .page_tree
+            .get(self.answer_node())
+            .and_then(|node| node.model.clone())
+            .or_else(|| self.model.clone())
+            .unwrap_or_else(|| "default model".to_string());
+        let mut line = format!(" {} │ {}", self.active, model);
+        if !self.streaming.is_empty() {
+            line.push_str(&format!(
+                " │ {} streaming",
+                SPINNER[self.spinner % SPINNER.len()]
+            ));
+        }
+        Line::styled(line, Style::default().fg(Color::DarkGray))
+    }
+
+    /// Advance the spinner, returning whether it is shown.
+    fn tick_spinner(&mut self) -> bool {
+        self.spinner = self.spinner.wrapping_add(1);
+        self.status_line && !self.streaming.is_empty()
+    }
+
+    /// Split off the bottom `height` lines for the footer, if it has any.
+    fn status_chunks(&self, chunk: Rect, height: u16) -> (Rect, Option<Rect>) {
+        if height == 0 {
+            return (chunk, None);
+        }
+        let layout = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints([Constraint::Min(0), Constraint::Length(height)].as_ref())
+            .split(chunk);
+        (layout[0], Some(layout[1]))
     }
 
     fn draw(&mut self, f: &mut Frame) {
         tracing::debug!("layout: {:?}", self);
-        let (outer_layout, user_layout) = self.chunks(f.area());
+        let footer = self.footer_to_draw();
+        let (main, footer_area) = self.status_chunks(f.area(), footer.len() as u16);
+        if let Some(area) = footer_area {
+            f.render_widget(Paragraph::new(footer), area);
+        }
+        let (outer_layout, user_layout) = self.chunks(main);
+        self.pane_rects = vec![
+            (SessionAreaId::System, user_layout[0]),
+            (SessionAreaId::User, user_layout[1]),
+            (SessionAreaId::Assistant, outer_layout[1]),
+        ];
         let user_area = self.user_text_area_to_draw();
-        let assistant_area = self.assistant_text_area_to_draw();
         let system_area = self.system_text_area_to_draw();
-        f.render_widget(user_area, user_layout[1]);
-        f.render_widget(assistant_area, outer_layout[1]);
-        f.render_widget(system_area, user_layout[0]);
+        // Wrapped to the current size on every draw, so it follows terminal resizes
+        f.render_widget(user_area.wrapped(user_layout[1]), user_layout[1]);
+        match self.diff_to_draw() {
+            Some(diff) => f.render_widget(diff, outer_layout[1]),
+            None if self.markdown_to_draw() => f.render_widget(
+                self.assistant_area_to_draw()
+                    .markdown(outer_layout[1], self.answer_scroll),
+                outer_layout[1],
+            ),
+            None if self.hscroll => {
+                f.render_widget(self.assistant_area_to_draw().text_area(), outer_layout[1])
+            }
+            None => f.render_widget(
+                self.assistant_area_to_draw()
+                    .truncated(outer_layout[1], self.answer_scroll),
+                outer_layout[1],
+            ),
+        }
+        f.render_widget(system_area.wrapped(user_layout[0]), user_layout[0]);
+    }
+
+    /// Same as [`SessionLayout::messages`], with each message's metadata.
+    fn turns(&self) -> Vec<Turn> {
+        self.turns_at(self.current_node)
+    }
+
+    /// The system prompt, if there is one, followed by the history up to `node`.
+    fn turns_at(&self, node: NodeId) -> Vec<Turn> {
+        let system = self.current_node_area(SessionAreaId::System).message();
+        let mut history = self.page_tree.collect_turns(node, None);
+        if let Some(system) = &system {
+            history.retain(|(message, _)| {
+                message.role != Role::System || message.content != system.content
+            });
+        }
+        system
+            .map(|system| (system, None))
+            .into_iter()
+            .chain(history)
+            .collect()
     }
 
     fn messages(&self) -> Vec<Message> {
-        let mut messages = vec![Message::from(self.current_node_area(SessionAreaId::System))];
-        messages.extend(self.page_tree.collect_messages(self.current_node, None));
-        messages
+        self.turns()
+            .into_iter()
+            .map(|(message, _)| message)
+            .collect()
     }
 
     fn switch_node(&mut self, node: NodeId) -> Option<NodeId> {
         self.current_node = node;
+        self.diff = None;
+        self.answer_scroll = None;
         self.activate(self.active);
         Some(node)
     }
@@ -228,6 +708,10 @@ impl<'a> SessionLayout<'a> {
         self.switch_node(self.page_tree.previous_sibling(self.current_node)?.id)
     }
 
+    fn goto_branch(&mut self, n: usize) -> Option<NodeId> {
+        self.switch_node(self.page_tree.goto_sibling(self.current_node, n)?.id)
+    }
+
     fn new_branch(&mut self, node_id: NodeId) {
         let id = self.page_tree.insert_child_with_parent(
             self.page_tree
@@ -242,23 +726,113 @@ impl<'a> SessionLayout<'a> {
         self.new_branch(self.current_node);
     }
 
+    fn snapshot(&self) -> SerializedTree {
+        self.page_tree.to_serialized(self.current_node)
+    }
+
+    fn restore(&mut self, tree: SerializedTree) -> Result<(), Error> {
+        let (mut page_tree, current_node) = Root::from_serialized(tree)?;
+        page_tree.set_assistant_label(self.page_tree.assistant_label.clone());
+        self.page_tree = page_tree;
+        self.stream_prefilled.clear();
+        self.streaming.clear();
+        self.branch_stats.clear();
+        self.interrupted = None;
+        self.truncated = None;
+        self.switch_node(current_node);
+        Ok(())
+    }
+
     fn update(&mut self, messages: &[Message], node: Option<NodeId>) -> Result<(), Error> {
         let id = self.page_tree.insert_messages(node, messages.to_vec())?;
         self.switch_node(id);
         Ok(())
     }
 
-    fn lock_current_node(&mut self) {
-        self.page_tree.get_mut(self.current_node).unwrap().lock();
-        self.assistant_stream_node = Some(self.current_node);
+    /// The current assistant pane as a partial answer for the model to continue. The API
+    /// rejects trailing whitespace there.
+    fn prefill(&self) -> Option<Message> {
+        let mut message = self.current_node_area(SessionAreaId::Assistant).message()?;
+        message.content.truncate(message.content.trim_end().len());
+        (!message.content.is_empty()).then_some(message)
+    }
+
+    fn lock_node(&mut self, id: NodeId) {
+        let node = self.page_tree.get_mut(id).unwrap();
+        node.user_meta = Some(MessageMeta::now());
+        node.lock();
+    }
+
+    /// Lock the current node and fork a locked sibling of it for every model after the first,
+    /// returning each node with the model that answers there.
+    fn fork_for_models(&mut self, models: &[String]) -> Vec<(NodeId, String)> {
+        let mut nodes = vec![self.current_node];
+        for _ in 1..models.len() {
+            nodes.push(self.page_tree.fork_node(self.current_node));
+        }
+        nodes
+            .into_iter()
+            .zip(models.iter().cloned())
+            .map(|(node, model)| {
+                self.lock_node(node);
+                self.page_tree.set_model(node, model.clone());
+                self.branch_stats
+                    .insert(node, BranchStats::new(model.clone()));
+                (node, model)
+            })
+            .collect()
+    }
+
+    /// Ask the model to carry on with the truncated answer, which the stream is appended to.
+    fn continue_truncated(&mut self) -> Option<(NodeId, Vec<Message>)> {
+        let node = self.truncated.take()?;
+        let partial = self.page_tree.get(node)?.assistant_area.message()?;
+        let mut messages: Vec<Message> = self
+            .turns_at(node)
+            .into_iter()
+            .map(|(message, _)| message)
+            .collect();
+        // The partial answer, which the continuation starts with
+        messages.pop();
+        self.stream_prefilled.insert(node);
+        Some((node, continuation(messages, &partial.content)))
     }
 
-    fn get_assistant_stream_node(&self) -> Option<NodeId> {
-        self.assistant_stream_node
+    /// Clear the interrupted answer and stream it again, returning the messages to resend.
+    fn retry_interrupted(&mut self) -> Option<(NodeId, Vec<Message>)> {
+        let node = self.interrupted.take()?;
+        self.page_tree
+            .get_mut(node)?
+            .area_mut(SessionAreaId::Assistant)
+            .clear();
+        let messages = self
+            .turns_at(node)
+            .into_iter()
+            .map(|(message, _)| message)
+            .collect();
+        Some((node, messages))
     }
 
-    fn reset_assistant_stream_node(&mut self) {
-        self.assistant_stream_node = None;
+    /// Fork the node of the shown answer, which keeps that answer as a sibling branch, and
+    /// return the fork with the messages to answer it again. Nothing while the answer streams.
+    fn regenerate(&mut self) -> Option<(NodeId, Vec<Message>)> {
+        let node = self.answer_node();
+        if self.streaming.contains(&node) || self.page_tree.get(node)?.assistant_area.is_empty() {
+            return None;
+        }
+        let fork = self.page_tree.fork_node(node);
+        self.page_tree
+            .get_mut(fork)?
+            .area_mut(SessionAreaId::Assistant)
+            .clear();
+        self.lock_node(fork);
+        self.new_child(fork);
+        let messages = self
+            .turns_at(fork)
+            .into_iter()
+            .map(|(message, _)| message)
+            .collect();
+        Some((fork, messages))
     }
 
     fn new_child(&mut self, node: NodeId) {
@@ -270,7 +844,7 @@ impl<'a> SessionLayout<'a> {
         self.new_child(self.current_node);
     }
 
-    async fn handle_assistant_event(&mut self, event: TextEvent) {
+    async fn handle_assistant_event(&mut self, node: NodeId, event: TextEvent) {
         tracing::trace!("handling assistant stream");
         fn char_to_input(c: char) -> Input {
             fn enter() -> Input {
@@ -295,19 +869,57 @@ impl<'a> SessionLayout<'a> {
             s.chars().map(char_to_input).collect()
         }
         tracing::trace!("assistant event: {:?}", event);
-        let area = if let Some(node) = self.get_assistant_stream_node() {
-            self.page_tree
-                .get_mut(node)
-                .unwrap()
-                .area_mut(SessionAreaId::Assistant)
-        } else {
-            self.current_node_area_mut(SessionAreaId::Assistant)
+        let prefilled =
+            matches!(event, TextEvent::MessageStart { .. }) && self.stream_prefilled.remove(&node);
+        match &event {
+            TextEvent::MessageStart { .. } => {
+                self.streaming.insert(node);
+                self.error = None;
+            }
+            TextEvent::MessageStop | TextEvent::Interrupted => {
+                self.streaming.remove(&node);
+            }
+            // A request that failed before its answer started ends here
+            TextEvent::Error { message } => {
+                self.streaming.remove(&node);
+                self.error = Some(message.clone());
+            }
+            _ => {}
+        }
+        if event.stop_reason() == Some(&StopReason::MaxTokens) {
+            self.truncated = Some(node);
+            self.set_status("answer cut off at the token limit, Ctrl-T to continue".to_string());
+        }
+        if let Some(stats) = self.branch_stats.get_mut(&node) {
+            match &event {
+                TextEvent::MessageStart { message } => {
+                    stats.latency = Some(stats.submitted.elapsed());
+                    stats.input_tokens = Some(message.usage.input_tokens);
+                }
+                TextEvent::MessageDelta {
+                    usage: Some(usage), ..
+                } => stats.output_tokens = Some(usage.output_tokens),
+                _ => {}
+            }
+        }
+        let Some(node) = self.page_tree.get_mut(node) else {
+            tracing::debug!("no node {:?} for assistant event", node);
+            return;
         };
+        if let TextEvent::MessageStart { .. } = event {
+            node.assistant_meta = Some(MessageMeta::now());
+        }
+        let id = node.id;
+        let area = node.area_mut(SessionAreaId::Assistant);
         match event {
             TextEvent::Null => {}
             TextEvent::MessageStart { .. } => {
-                // clear the assistant buffer
-                area.clear();
+                if prefilled {
+                    area.move_cursor_to_end();
+                } else {
+                    // clear the assistant buffer
+                    area.clear();
+                }
             }
             TextEvent::ContentBlockStart { content_block, .. } => {
                 for input in string_to_inputs(content_block.text().unwrap_or_default().as_str()) {
@@ -323,8 +935,13 @@ impl<'a> SessionLayout<'a> {
             TextEvent::MessageDelta { .. } => {}
             TextEvent::MessageStop => {
                 tracing::trace!("message stop");
-                self.reset_assistant_stream_node();
             }
+            TextEvent::Interrupted => {
+                tracing::trace!("stream interrupted");
+                self.interrupted = Some(id);
+                self.set_status("response interrupted, Ctrl-R to retry".to_string());
+            }
+            TextEvent::Error { .. } => {}
         }
         tracing::trace!("finished")
     }
@@ -333,21 +950,298 @@ impl<'a> SessionLayout<'a> {
 pub struct SessionInner {
     assistant: Assistant,
     layout: SessionLayout<'static>,
+    clipboard: Clipboard,
+    checkpoint: Option<Checkpoint>,
+    turns_since_checkpoint: usize,
+    /// Archive this session is saved to on exit, under the id.
+    history: Option<(History, String)>,
+    /// Where Ctrl-S saves to, named on the first save if the session wasn't loaded.
+    saved: Option<SavedSession>,
+    /// Answers on their way, by the node they go to.
+    in_flight: HashMap<NodeId, InFlight>,
 }
 
 impl SessionInner {
     fn new(assistant: Assistant) -> Self {
         let messages = assistant.init_messages();
-        let layout = SessionLayout::new(&messages);
-        SessionInner { assistant, layout }
+        let mut layout = SessionLayout::new(&messages, assistant.config.persona_label());
+        layout.render_markdown = assistant.config.render_markdown;
+        layout.status_line = assistant.config.status_line;
+        layout.model = assistant.config.model.clone();
+        let checkpoint = assistant.config.autosave.and_then(|_| Checkpoint::new());
+        let history = assistant
+            .config
+            .keep_sessions
+            .and_then(History::open)
+            .map(|history| (history, History::new_id()));
+        SessionInner {
+            assistant,
+            layout,
+            clipboard: Clipboard::new(),
+            checkpoint,
+            turns_since_checkpoint: 0,
+            history,
+            saved: None,
+            in_flight: HashMap::new(),
+        }
+    }
+
+    fn autosave(&self) -> Option<Autosave> {
+        self.assistant.config.autosave
+    }
+
+    /// Offer to continue from the most recent checkpoint, which this session then takes over.
+    fn offer_restore(&mut self) -> Result<(), Error> {
+        let Some(latest) = Checkpoint::latest() else {
+            return Ok(());
+        };
+        let restore = dialoguer::Confirm::new()
+            .with_prompt(format!("Restore checkpoint {}?", latest.path().display()))
+            .default(false)
+            .interact()?;
+        if restore {
+            self.layout.restore(latest.load()?)?;
+            // This session's own checkpoint hasn't been written yet, only locked
+            if let Some(own) = self.checkpoint.replace(latest) {
+                if let Err(e) = own.remove() {
+                    tracing::error!("error removing checkpoint: {}", e);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn save_checkpoint(&mut self) {
+        let Some(checkpoint) = self.checkpoint.as_ref() else {
+            return;
+        };
+        self.turns_since_checkpoint = 0;
+        if let Err(e) = checkpoint.save(&self.layout.snapshot()) {
+            tracing::error!("error saving checkpoint: {}", e);
+            self.layout.set_status(format!("autosave failed: {}", e));
+        }
+    }
+
+    /// Archive the session, if anything was sent in it.
+    fn save_history(&self) {
+        let Some((history, id)) = self.history.as_ref() else {
+            return;
+        };
+        let tree = self.layout.snapshot();
+        if !tree.nodes.iter().any(|node| node.locked) {
+            return;
+        }
+        if let Err(e) = history.save(id, &tree) {
+            tracing::error!("error saving session to history: {}", e);
+        }
+    }
+
+    /// Save the session under its name, so it can be continued with `--load`.
+    fn save_named(&mut self) {
+        let saved = match self.saved.take() {
+            Some(saved) => Ok(saved),
+            None => SavedSession::new(&format!("session-{}", History::new_id())),
+        };
+        let status = match saved {
+            Ok(saved) => {
+                let status = match saved.save(&self.layout.snapshot()) {
+                    Ok(()) => format!("saved session as '{}'", saved.name()),
+                    Err(e) => format!("saving session failed: {}", e),
+                };
+                self.saved = Some(saved);
+                status
+            }
+            Err(e) => format!("saving session failed: {}", e),
+        };
+        self.layout.set_status(status);
+    }
+
+    /// Show the hash of a request about to be sent, with `deterministic`.
+    fn show_request_hash(&mut self, messages: &[Message]) {
+        if !self.assistant.config.deterministic {
+            return;
+        }
+        let status = match self.assistant.request_hash(messages.to_vec()) {
+            Ok(hash) => format!("request hash: {}", hash),
+            Err(e) => format!("request hash failed: {}", e),
+        };
+        self.layout.set_status(status);
+    }
+
+    fn on_submit(&mut self) {
+        self.turns_since_checkpoint += 1;
+        let every_turns = self.autosave().and_then(|autosave| autosave.every_turns);
+        if every_turns.is_some_and(|n| self.turns_since_checkpoint >= n) {
+            self.save_checkpoint();
+        }
+    }
+
+    /// Copy the current branch, system prompt included, to the clipboard as Markdown.
+    fn yank_conversation(&mut self) {
+        let transcript = export::markdown_turns(&self.layout.turns());
+        let status = match self.clipboard.set_text(transcript) {
+            Ok(()) => "copied conversation to clipboard".to_string(),
+            Err(e) => format!("{}", e),
+        };
+        self.layout.set_status(status);
+    }
+
+    /// Copy the answer on screen to the clipboard.
+    fn yank_answer(&mut self) {
+        let Some(answer) = self.layout.assistant_area_to_draw().message() else {
+            self.layout.set_status("no answer to copy".to_string());
+            return;
+        };
+        let status = match self.clipboard.set_text(answer.content) {
+            Ok(()) => "copied answer to clipboard".to_string(),
+            Err(e) => {
+                tracing::warn!("error copying answer: {}", e);
+                format!("{}", e)
+            }
+        };
+        self.layout.set_status(status);
+    }
+
+    /// Send the current prompt to every model in `compare_models`, each answering on its own
+    /// sibling branch.
+    fn compare_models(&mut self, tx: &EventSender) {
+        let models = self.assistant.config.compare_models.clone();
+        if models.len() < 2 {
+            self.layout
+                .set_status("set at least two models with --compare-models".to_string());
+            return;
+        }
+        let messages = self.layout.messages();
+        tracing::debug!("comparing {:?} on messages: {:?}", models, messages);
+        let first = self.layout.current_node;
+        for (node, model) in self.layout.fork_for_models(&models) {
+            let in_flight = submit(
+                &self.assistant.with_model(model),
+                node,
+                messages.clone(),
+                tx,
+            );
+            self.in_flight.insert(node, in_flight);
+            self.layout.streaming.insert(node);
+        }
+        self.layout.new_child(first);
+        self.on_submit();
+    }
+
+    fn send(&mut self, node: NodeId, messages: Vec<Message>, tx: &EventSender) {
+        let in_flight = submit(&self.assistant, node, messages, tx);
+        self.in_flight.insert(node, in_flight);
+        self.layout.streaming.insert(node);
+    }
+
+    /// Stop the answers still on their way, ending them where they are. Returns whether there
+    /// were any.
+    async fn cancel(&mut self) -> bool {
+        self.in_flight
+            .retain(|_, in_flight| !in_flight.is_finished());
+        let cancelled = std::mem::take(&mut self.in_flight);
+        for (node, in_flight) in &cancelled {
+            in_flight.abort();
+            self.layout
+                .handle_assistant_event(*node, TextEvent::MessageStop)
+                .await;
+        }
+        if !cancelled.is_empty() {
+            self.layout.set_status("answer stopped".to_string());
+        }
+        !cancelled.is_empty()
+    }
+
+    /// Do what a key in the keymap is bound to, other than quitting or stopping.
+    fn act(&mut self, action: Action, tx: &EventSender) {
+        match action {
+            Action::Quit | Action::Stop => {}
+            Action::EditPrompt => {
+                self.layout.edit_prompt();
+            }
+            Action::SwitchPane => self.layout.switch_pane(),
+            Action::NewBranch => self.layout.new_branch_at_current(),
+            Action::NextBranch => {
+                self.layout.next_branch();
+            }
+            Action::PreviousBranch => {
+                self.layout.previous_branch();
+            }
+            Action::Up => {
+                self.layout.up_one();
+            }
+            Action::Down => {
+                self.layout.down_one();
+            }
+            Action::YankConversation => self.yank_conversation(),
+            Action::YankAnswer => self.yank_answer(),
+            // Of the active pane, unless it's locked
+            Action::Undo => self.layout.undo(),
+            Action::Redo => self.layout.redo(),
+            Action::Continue => {
+                if let Some((node, messages)) = self.layout.continue_truncated() {
+                    self.send(node, messages, tx);
+                }
+            }
+            Action::ToggleHscroll => self.layout.toggle_hscroll(),
+            Action::Save => self.save_named(),
+            Action::ToggleDiff => self.layout.toggle_diff(),
+            // Retry a dropped answer, or else ask again for the shown one
+            Action::Retry => {
+                if let Some((node, messages)) = self.layout.retry_interrupted() {
+                    self.send(node, messages, tx);
+                } else if let Some((node, messages)) = self.layout.regenerate() {
+                    self.send(node, messages, tx);
+                    self.on_submit();
+                }
+            }
+            // Like submitting, but the model continues what's in the assistant pane
+            Action::Prefill => match self.layout.prefill() {
+                Some(prefill) => {
+                    let mut messages = self.layout.messages();
+                    messages.push(prefill);
+                    tracing::debug!("sending prefilled messages to assistant: {:?}", messages);
+                    self.show_request_hash(&messages);
+                    let node = self.layout.current_node;
+                    self.send(node, messages, tx);
+                    self.layout.lock_node(node);
+                    self.layout.stream_prefilled.insert(node);
+                    self.layout.new_child_at_current();
+                    self.on_submit();
+                }
+                None => self
+                    .layout
+                    .set_status("nothing to prefill, the assistant pane is empty".to_string()),
+            },
+            Action::Submit => {
+                let messages = self.layout.messages();
+                tracing::debug!("sending messages to assistant: {:?}", messages);
+                self.show_request_hash(&messages);
+                let node = self.layout.current_node;
+                self.send(node, messages, tx);
+                self.layout.lock_node(node);
+                self.layout.new_child_at_current();
+                self.on_submit();
+            }
+            // Like submitting, but sent to each of the compared models at once, with a sibling
+            // branch per model
+            Action::CompareModels => self.compare_models(tx),
+        }
     }
 
     async fn run(&mut self, messages: &[Message]) -> Result<(), Error> {
-        enable_raw_mode()?;
-        crossterm::execute!(stdout(), EnterAlternateScreen, EnableMouseCapture)?;
+        let _terminal = TerminalGuard::enter()?;
         let mut term = Terminal::new(CrosstermBackend::new(stdout()))?;
         let mut eventstream = crossterm::event::EventStream::new();
         let (tx, mut rx) = tokio::sync::mpsc::channel(100);
+        let mut autosave_interval = self
+            .autosave()
+            .and_then(|autosave| autosave.every_minutes)
+            .map(|minutes| {
+                let period = std::time::Duration::from_secs(minutes * 60);
+                tokio::time::interval_at(tokio::time::Instant::now() + period, period)
+            });
+        let mut spinner_interval = tokio::time::interval(SPINNER_INTERVAL);
 
         if !messages.is_empty() {
             if let Err(e) = self.layout.update(messages, None) {
@@ -362,65 +1256,46 @@ impl SessionInner {
             tokio::select! {
                 // new input event
                 input = eventstream.next() => {
-                    if let Some(Ok(event)) = input {
+                    if let Some(Ok(crossterm::event::Event::Mouse(mouse))) = input {
+                        self.layout.mouse(mouse);
+                    } else if let Some(Ok(event)) = input {
                         tracing::trace!("event: {:?}", event);
-                        match event.into() {
-                            Input { key: Key::Esc, .. } => break,
-                            Input {key: Key::Tab, ..} => {
-                                self.layout.switch_pane();
-                            },
-                            Input {
-                                key: Key::Char('c'),
-                                ctrl: true,
-                                ..
-                            } => break,
-                            Input {
-                                key: Key::Char('b'),
-                                ctrl: true,
-                                ..
-                            } => {
-                                self.layout.new_branch_at_current();
-                            }
-                            Input {
-                                key: Key::Char('n'),
-                                ctrl: true,
-                                ..
-                            } => {
-                                    self.layout.next_branch();
-                            }
-                            Input {
-                                key: Key::Char('p'),
-                                ctrl: true,
-                                ..
-                            } => {
-                                self.layout.previous_branch();
-                            }
-                            Input {
-                                key: Key::Char('u'),
-                                ctrl: true,
-                                ..
-                            } => {
-                                self.layout.up_one();
+                        self.layout.status = None;
+                        let input = Input::from(event);
+                        match (self.assistant.config.keymap.action(&input), input) {
+                            (Some(Action::Quit), _) => break,
+                            // Stops the answers streaming in, if there are any
+                            (Some(Action::Stop), _) => {
+                                if !self.cancel().await {
+                                    break;
+                                }
                             }
-                            Input {
-                                key: Key::Char('d'),
-                                ctrl: true,
-                                ..
-                            } => {
-                                self.layout.down_one();
+                            // Only a sent prompt needs forking to edit, others take the key
+                            (Some(Action::EditPrompt), input) => {
+                                if self.layout.edit_prompt().is_none() {
+                                    self.layout.input(input);
+                                }
                             }
-                            Input {
-                                key: Key::Char('j'),
-                                ctrl: true,
-                                ..
-                            } => {
-                                let messages = self.layout.messages();
-                                tracing::debug!("sending messages to assistant: {:?}", messages);
-                                self.assistant.handle_input(messages, tx.clone());
-                                self.layout.lock_current_node();
-                                self.layout.new_child_at_current();
+                            (Some(action), _) => self.act(action, &tx),
+                            (
+                                None,
+                                Input {
+                                    key: Key::Char(c @ '1'..='9'),
+                                    alt: true,
+                                    ..
+                                },
+                            ) => {
+                                self.layout.goto_branch(c as usize - '0' as usize);
                             }
-                            input => {
+                            // Pages through the answer unless it's being edited
+                            (
+                                None,
+                                Input {
+                                    key: key @ (Key::PageUp | Key::PageDown),
+                                    ..
+                                },
+                            ) if self.layout.page_answer(key == Key::PageUp) => {}
+                            (None, input) => {
                                 self.layout.input(input);
                             }
                         }
@@ -434,22 +1309,371 @@ impl SessionInner {
                     })?;
                 }
                 tx = rx.recv() => {
-                    if let Some(event) = tx { self.layout.handle_assistant_event(event).await }
+                    if let Some((node, event)) = tx { self.layout.handle_assistant_event(node, event).await }
                     term.draw(|f| {
                         self.layout.draw(f);
                     })?;
                 }
+                _ = async {
+                    match autosave_interval.as_mut() {
+                        Some(interval) => interval.tick().await,
+                        None => std::future::pending().await,
+                    }
+                } => {
+                    self.save_checkpoint();
+                }
+                _ = spinner_interval.tick(), if !self.layout.streaming.is_empty() => {
+                    if self.layout.tick_spinner() {
+                        term.draw(|f| {
+                            self.layout.draw(f);
+                        })?;
+                    }
+                }
             }
         }
 
-        disable_raw_mode()?;
-        crossterm::execute!(
-            term.backend_mut(),
-            LeaveAlternateScreen,
-            DisableMouseCapture
-        )?;
-        term.show_cursor()?;
+        self.save_history();
+
+        // A clean exit leaves nothing to recover.
+        if let Some(checkpoint) = self.checkpoint.as_ref() {
+            if let Err(e) = checkpoint.remove() {
+                tracing::error!("error removing checkpoint: {}", e);
+            }
+        }
 
         Ok(())
     }
 }
+
+/// Raw mode, the alternate screen and mouse capture for as long as it lives. It puts the
+/// terminal back when dropped, on an early return too, and before a panic on this thread is
+/// printed, so the message isn't garbled and the shell is usable afterwards.
+struct TerminalGuard {
+    /// Whether the terminal reports keys with all their modifiers, e.g. Ctrl-Shift-Y apart from
+    /// Ctrl-Y, which most terminals can't without being asked.
+    enhanced: bool,
+    /// The panic hook from before, put back when the guard is dropped.
+    previous_hook: Arc<PanicHook>,
+}
+
+type PanicHook = dyn Fn(&std::panic::PanicHookInfo<'_>) + Sync + Send + 'static;
+
+impl TerminalGuard {
+    fn enter() -> Result<Self, Error> {
+        enable_raw_mode()?;
+        // From here on anything that fails is undone
+        let guard = TerminalGuard {
+            enhanced: supports_keyboard_enhancement().unwrap_or(false),
+            previous_hook: Arc::from(std::panic::take_hook()),
+        };
+        crossterm::execute!(stdout(), EnterAlternateScreen, EnableMouseCapture)?;
+        if guard.enhanced {
+            crossterm::execute!(
+                stdout(),
+                PushKeyboardEnhancementFlags(KeyboardEnhancementFlags::DISAMBIGUATE_ESCAPE_CODES)
+            )?;
+        }
+        let thread = std::thread::current().id();
+        let enhanced = guard.enhanced;
+        let hook = guard.previous_hook.clone();
+        std::panic::set_hook(Box::new(move |info| {
+            if std::thread::current().id() == thread {
+                Self::restore(enhanced);
+            }
+            hook(info);
+        }));
+        Ok(guard)
+    }
+
+    fn restore(enhanced: bool) {
+        if enhanced {
+            let _ = crossterm::execute!(stdout(), PopKeyboardEnhancementFlags);
+        }
+        let _ = disable_raw_mode();
+        let _ = crossterm::execute!(
+            stdout(),
+            LeaveAlternateScreen,
+            DisableMouseCapture,
+            crossterm::cursor::Show
+        );
+    }
+}
+
+impl Drop for TerminalGuard {
+    fn drop(&mut self) {
+        Self::restore(self.enhanced);
+        // Back to the hook from before, which can't be swapped while panicking
+        if !std::thread::panicking() {
+            let previous = self.previous_hook.clone();
+            std::panic::set_hook(Box::new(move |info| previous(info)));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::DEFAULT_PERSONA_LABEL;
+
+    #[test]
+    fn test_mouse() {
+        let mut layout = SessionLayout::new(&[], DEFAULT_PERSONA_LABEL);
+        let mut term = Terminal::new(ratatui::backend::TestBackend::new(80, 24)).unwrap();
+        term.draw(|f| layout.draw(f)).unwrap();
+        let click = |column, row| MouseEvent {
+            kind: MouseEventKind::Down(MouseButton::Left),
+            column,
+            row,
+            modifiers: crossterm::event::KeyModifiers::NONE,
+        };
+
+        layout.mouse(click(60, 10));
+        assert_eq!(layout.active, SessionAreaId::Assistant);
+        layout.mouse(click(10, 1));
+        assert_eq!(layout.active, SessionAreaId::System);
+        layout.mouse(click(10, 20));
+        assert_eq!(layout.active, SessionAreaId::User);
+
+        for c in "a\nb\nc\nd".chars() {
+            layout.input(Input {
+                key: match c {
+                    '\n' => Key::Enter,
+                    c => Key::Char(c),
+                },
+                ..Default::default()
+            });
+        }
+        layout.mouse(MouseEvent {
+            kind: MouseEventKind::ScrollUp,
+            ..click(10, 20)
+        });
+        let (row, _) = layout
+            .current_node_area(SessionAreaId::User)
+            .text_area()
+            .cursor();
+        assert_eq!(row, 0);
+    }
+
+    #[test]
+    fn test_scroll_answer() {
+        let mut layout = SessionLayout::new(&[], DEFAULT_PERSONA_LABEL);
+        let lines = (0..50).map(|i| i.to_string()).collect::<Vec<_>>();
+        let lines = lines.iter().map(String::as_str).collect::<Vec<_>>();
+        let area = layout.current_node_area_mut(SessionAreaId::Assistant);
+        *area = SessionTextArea::new(SessionAreaId::Assistant, &lines);
+        area.lock();
+        let mut term = Terminal::new(ratatui::backend::TestBackend::new(80, 24)).unwrap();
+        term.draw(|f| layout.draw(f)).unwrap();
+        let height = layout.answer_height().unwrap() as u16;
+        let bottom = layout.assistant_area_to_draw().lines().len() as u16 - height;
+
+        // Starts at the bottom, following the cursor
+        assert!(layout.page_answer(true));
+        assert_eq!(layout.answer_scroll, Some(bottom - height));
+        for _ in 0..5 {
+            layout.page_answer(true);
+        }
+        assert_eq!(layout.answer_scroll, Some(0));
+        layout.page_answer(false);
+        assert_eq!(layout.answer_scroll, Some(height));
+        for _ in 0..5 {
+            layout.page_answer(false);
+        }
+        assert_eq!(layout.answer_scroll, None);
+        layout.page_answer(true);
+
+        // An answer being edited scrolls with its cursor instead
+        layout.activate(SessionAreaId::Assistant);
+        layout
+            .current_node_area_mut(SessionAreaId::Assistant)
+            .unlock();
+        assert!(!layout.page_answer(true));
+
+        layout.switch_node(layout.current_node);
+        assert_eq!(layout.answer_scroll, None);
+    }
+
+    #[tokio::test]
+    async fn test_error_banner() {
+        let mut layout = SessionLayout::new(&[], DEFAULT_PERSONA_LABEL);
+        let node = layout.current_node;
+        layout
+            .handle_assistant_event(
+                node,
+                TextEvent::Error {
+                    message: "rate_limit_error: slow down".to_string(),
+                },
+            )
+            .await;
+        layout
+            .handle_assistant_event(node, TextEvent::Interrupted)
+            .await;
+        let footer = layout.footer_to_draw();
+        assert_eq!(
+            footer[0].spans[0].content,
+            "error: rate_limit_error: slow down"
+        );
+        assert_eq!(footer[0].style.fg, Some(Color::Red));
+        assert_eq!(layout.interrupted, Some(node));
+
+        let start = serde_json::from_str(
+            r#"{"type":"message_start","message":{"id":"msg_1","type":"message","role":"assistant","model":"claude","content":[],"stop_reason":null,"stop_sequence":null,"usage":{"input_tokens":1,"output_tokens":1}}}"#,
+        )
+        .unwrap();
+        layout.handle_assistant_event(node, start).await;
+        assert!(layout.error.is_none());
+    }
+
+    #[test]
+    fn test_status_line() {
+        let mut layout = SessionLayout::new(&[], DEFAULT_PERSONA_LABEL);
+        assert!(layout.footer_to_draw().is_empty());
+
+        layout.status_line = true;
+        layout.model = Some("claude".to_string());
+        let footer = layout.footer_to_draw();
+        assert_eq!(footer.len(), 1);
+        assert_eq!(footer[0].to_string(), " user │ claude");
+
+        layout.streaming.insert(layout.current_node);
+        assert!(layout.tick_spinner());
+        assert!(layout.footer_to_draw()[0]
+            .to_string()
+            .ends_with(&format!("{} streaming", SPINNER[1])));
+    }
+
+    #[tokio::test]
+    async fn test_abort_in_flight() {
+        let request = tokio::spawn(std::future::pending::<()>());
+        let forward = tokio::spawn(std::future::pending::<()>());
+        let in_flight = InFlight {
+            request: request.abort_handle(),
+            forward: forward.abort_handle(),
+        };
+        assert!(!in_flight.is_finished());
+        in_flight.abort();
+        assert!(request.await.unwrap_err().is_cancelled());
+        assert!(forward.await.unwrap_err().is_cancelled());
+        assert!(in_flight.is_finished());
+    }
+
+    #[test]
+    fn test_messages_system_prompt() {
+        let mut layout = SessionLayout::new(&[], DEFAULT_PERSONA_LABEL);
+        layout.input(Input {
+            key: Key::Char('?'),
+            ..Default::default()
+        });
+        assert_eq!(layout.messages(), vec![Message::user("?")]);
+
+        let system = Message::system("Be brief.");
+        let layout = SessionLayout::new(std::slice::from_ref(&system), DEFAULT_PERSONA_LABEL);
+        let messages = layout.messages();
+        assert_eq!(messages.first(), Some(&system));
+        assert_eq!(
+            messages.iter().filter(|m| m.role == Role::System).count(),
+            1
+        );
+    }
+
+    #[test]
+    fn test_fork_for_models() {
+        let mut layout = SessionLayout::new(&[], DEFAULT_PERSONA_LABEL);
+        layout.input(Input {
+            key: Key::Char('?'),
+            ..Default::default()
+        });
+        let models = vec!["model-a".to_string(), "model-b".to_string()];
+        let nodes = layout.fork_for_models(&models);
+        assert_eq!(nodes.len(), 2);
+        assert_eq!(nodes[0].0, layout.current_node);
+        for (id, model) in &nodes {
+            let node = layout.page_tree.get(*id).unwrap();
+            assert!(node.is_locked());
+            assert_eq!(node.model.as_ref(), Some(model));
+            assert_eq!(layout.turns_at(*id).last().unwrap().0.content, "?");
+            assert_eq!(layout.branch_stats[id].model, *model);
+        }
+        assert_eq!(
+            layout.page_tree.next_sibling(nodes[0].0).unwrap().id,
+            nodes[1].0
+        );
+    }
+
+    #[test]
+    fn test_regenerate() {
+        let mut layout = SessionLayout::new(&[], DEFAULT_PERSONA_LABEL);
+        layout.input(Input {
+            key: Key::Char('?'),
+            ..Default::default()
+        });
+        let node = layout.current_node;
+        layout.lock_node(node);
+        layout.new_child_at_current();
+        assert!(layout.regenerate().is_none());
+        layout
+            .page_tree
+            .get_mut(node)
+            .unwrap()
+            .area_mut(SessionAreaId::Assistant)
+            .force_input(Input {
+                key: Key::Char('!'),
+                ..Default::default()
+            });
+
+        layout.streaming.insert(node);
+        assert!(layout.regenerate().is_none());
+        layout.streaming.remove(&node);
+
+        let (fork, messages) = layout.regenerate().unwrap();
+        assert_eq!(messages.last().unwrap().content, "?");
+        assert_eq!(messages.last().unwrap().role, Role::User);
+        assert!(layout.page_tree.get(fork).unwrap().is_locked());
+        assert!(layout
+            .page_tree
+            .get(fork)
+            .unwrap()
+            .assistant_area
+            .is_empty());
+        assert!(!layout
+            .page_tree
+            .get(node)
+            .unwrap()
+            .assistant_area
+            .is_empty());
+        assert_eq!(layout.page_tree.sibling_index(fork), Some((2, 2)));
+        assert_eq!(layout.answer_node(), fork);
+    }
+
+    #[test]
+    fn test_edit_prompt() {
+        let char = |c| Input {
+            key: Key::Char(c),
+            ..Default::default()
+        };
+        let mut layout = SessionLayout::new(&[], DEFAULT_PERSONA_LABEL);
+        layout.input(char('?'));
+        let node = layout.current_node;
+        layout
+            .current_node_area_mut(SessionAreaId::Assistant)
+            .force_input(char('!'));
+        layout.lock_node(node);
+        layout.new_child_at_current();
+        // Nothing to fork before the prompt is sent
+        assert!(layout.edit_prompt().is_none());
+
+        layout.down_one();
+        let fork = layout.edit_prompt().unwrap();
+        assert_eq!(layout.current_node, fork);
+        assert_eq!(layout.active, SessionAreaId::User);
+        layout.input(char('x'));
+        assert_eq!(layout.messages().last().unwrap().content, "?x");
+
+        let fork = layout.page_tree.get(fork).unwrap();
+        assert!(fork.assistant_area.is_empty());
+        let original = layout.page_tree.get(node).unwrap();
+        assert_eq!(original.user_area.lines(), ["?"]);
+        assert_eq!(original.assistant_area.lines(), ["!"]);
+        assert_eq!(layout.page_tree.sibling_index(fork.id), Some((2, 2)));
+    }
+}
@@ -0,0 +1,124 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+use rgpt_types::message::Message;
+use serde::{Deserialize, Serialize};
+
+use crate::error::Error;
+
+/// A reusable prompt, loaded from `<config dir>/templates/<name>.json`.
+/// Both messages may contain `{{var}}` placeholders.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct Template {
+    pub system: Option<String>,
+    pub user: String,
+}
+
+impl Template {
+    pub fn path(name: &str) -> Option<PathBuf> {
+        rgpt_utils::paths::config_dir()
+            .map(|dir| dir.join("templates").join(format!("{}.json", name)))
+    }
+
+    pub fn load(name: &str) -> Result<Self, Error> {
+        let path =
+            Self::path(name).ok_or_else(|| Error::Template("no config directory".to_string()))?;
+        let bytes = std::fs::read(&path)
+            .map_err(|e| Error::Template(format!("{}: {}", path.display(), e)))?;
+        Ok(serde_json::from_slice(&bytes)?)
+    }
+
+    /// Substitute `vars` into the system and user messages.
+    /// Unresolved placeholders are an error unless `allow_missing` is set, in which case they
+    /// are left as they are.
+    pub fn render(
+        &self,
+        vars: &HashMap<String, String>,
+        allow_missing: bool,
+    ) -> Result<(Option<Message>, Message), Error> {
+        let system = self
+            .system
+            .as_deref()
+            .map(|system| substitute(system, vars, allow_missing))
+            .transpose()?
+            .map(Message::system);
+        let user = Message::user(substitute(&self.user, vars, allow_missing)?);
+        Ok((system, user))
+    }
+}
+
+/// Variables available to every template: `os`, `cwd` and, if given, `stdin`.
+pub fn builtin_vars(stdin: Option<String>) -> HashMap<String, String> {
+    let mut vars = HashMap::from([("os".to_string(), std::env::consts::OS.to_string())]);
+    if let Ok(cwd) = std::env::current_dir() {
+        vars.insert("cwd".to_string(), cwd.display().to_string());
+    }
+    if let Some(stdin) = stdin {
+        vars.insert("stdin".to_string(), stdin);
+    }
+    vars
+}
+
+fn substitute(
+    text: &str,
+    vars: &HashMap<String, String>,
+    allow_missing: bool,
+) -> Result<String, Error> {
+    let mut out = String::with_capacity(text.len());
+    let mut missing = vec![];
+    let mut rest = text;
+    while let Some(start) = rest.find("{{") {
+        let Some(len) = rest[start..].find("}}") else {
+            break;
+        };
+        let placeholder = &rest[start..start + len + 2];
+        let name = placeholder[2..placeholder.len() - 2].trim();
+        out.push_str(&rest[..start]);
+        match vars.get(name) {
+            Some(value) => out.push_str(value),
+            None => {
+                missing.push(name.to_string());
+                out.push_str(placeholder);
+            }
+        }
+        rest = &rest[start + len + 2..];
+    }
+    out.push_str(rest);
+
+    if !missing.is_empty() && !allow_missing {
+        return Err(Error::Template(format!(
+            "unresolved placeholders: {}",
+            missing.join(", ")
+        )));
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vars() -> HashMap<String, String> {
+        HashMap::from([("file".to_string(), "main.rs".to_string())])
+    }
+
+    #[test]
+    fn test_substitute() {
+        assert_eq!(
+            substitute("Review {{file}}, then {{ file }} again", &vars(), false).unwrap(),
+            "Review main.rs, then main.rs again"
+        );
+    }
+
+    #[test]
+    fn test_substitute_missing() {
+        assert!(matches!(
+            substitute("Review {{file}} on {{branch}}", &vars(), false),
+            Err(Error::Template(_))
+        ));
+        assert_eq!(
+            substitute("Review {{file}} on {{branch}}", &vars(), true).unwrap(),
+            "Review main.rs on {{branch}}"
+        );
+    }
+}
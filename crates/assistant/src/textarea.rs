@@ -1,6 +1,9 @@
+use ratatui::layout::Rect;
 use ratatui::style::{Color, Modifier, Style};
-use ratatui::widgets::{Block, Borders};
-use tui_textarea::{Input, Key, TextArea};
+use ratatui::text::{Line, Span};
+use ratatui::widgets::{Block, Borders, Paragraph};
+use tui_textarea::{CursorMove, Input, Key, TextArea};
+use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
 
 use rgpt_types::message::{Message, Role};
 
@@ -25,6 +28,72 @@ fn char_to_input(c: char) -> Input {
     }
 }
 
+/// Columns `c` takes up in a terminal, 2 for wide chars like CJK and most emoji.
+fn char_width(c: char) -> usize {
+    c.width().unwrap_or(0)
+}
+
+/// `line` if it fits in `width` columns, otherwise its head and an ellipsis.
+fn truncate_line(line: &str, width: usize) -> String {
+    if line.width() <= width {
+        return line.to_string();
+    }
+    if width == 0 {
+        return String::new();
+    }
+    let mut used = 0;
+    line.chars()
+        .take_while(|&c| {
+            used += char_width(c);
+            used < width
+        })
+        .chain(std::iter::once('…'))
+        .collect()
+}
+
+/// Char offsets where each display row of `line` starts when it is wrapped at `width` columns,
+/// breaking after the last whitespace that fits, or mid-word if there is none.
+fn wrap_points(line: &str, width: usize) -> Vec<usize> {
+    let chars = line.chars().collect::<Vec<_>>();
+    let mut starts = vec![0];
+    if width == 0 {
+        return starts;
+    }
+    let mut start = 0;
+    let mut used = 0;
+    let mut i = 0;
+    while i < chars.len() {
+        let w = char_width(chars[i]);
+        // A char wider than the whole row still gets one to itself
+        if used + w > width && i > start {
+            start = chars[start..i]
+                .iter()
+                .rposition(|c| c.is_whitespace())
+                .map_or(i, |j| start + j + 1);
+            starts.push(start);
+            used = chars[start..i].iter().copied().map(char_width).sum();
+        }
+        used += w;
+        i += 1;
+    }
+    starts
+}
+
+/// A display row with the cursor drawn on the char at `col`, or past its end.
+fn cursor_line(chars: &[char], col: usize, style: Style) -> Line<'static> {
+    let col = col.min(chars.len());
+    let cursor = chars.get(col).map_or(" ".to_string(), char::to_string);
+    Line::from(vec![
+        Span::raw(chars[..col].iter().collect::<String>()),
+        Span::styled(cursor, style),
+        Span::raw(
+            chars[(col + 1).min(chars.len())..]
+                .iter()
+                .collect::<String>(),
+        ),
+    ])
+}
+
 // FIXME: hacky-ass functions
 fn string_to_inputs(s: &str) -> Vec<Input> {
     s.chars().map(char_to_input).collect()
@@ -37,6 +106,16 @@ pub enum SessionAreaId {
     System,
 }
 
+impl std::fmt::Display for SessionAreaId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SessionAreaId::User => write!(f, "user"),
+            SessionAreaId::Assistant => write!(f, "assistant"),
+            SessionAreaId::System => write!(f, "system"),
+        }
+    }
+}
+
 impl From<rgpt_types::message::Role> for SessionAreaId {
     fn from(id: rgpt_types::message::Role) -> Self {
         match id {
@@ -84,9 +163,7 @@ pub struct SessionTextArea<'a> {
     pub title: String,
     pub text_area: TextArea<'a>,
     pub locked: bool,
-
-    // FIXME: patch until tui-textarea implements wrapping.
-    pub max_line_length: usize,
+    active: bool,
 }
 
 impl<'a> std::fmt::Debug for SessionTextArea<'a> {
@@ -98,14 +175,14 @@ impl<'a> std::fmt::Debug for SessionTextArea<'a> {
 }
 
 impl<'a> SessionTextArea<'a> {
-    pub fn new(id: SessionAreaId, lines: &[&str], max_line_length: usize) -> Self {
+    pub fn new(id: SessionAreaId, lines: &[&str]) -> Self {
         tracing::trace!("Creating new SessionTextArea with id: {:?}", id);
         let mut s = SessionTextArea {
             id,
-            title: "temp".to_string(),
+            title: id.to_string(),
             text_area: Self::text_area_format(),
-            max_line_length,
             locked: false,
+            active: false,
         };
         if !lines.is_empty() {
             for input in string_to_inputs(lines.join("\n").as_str()) {
@@ -116,6 +193,7 @@ impl<'a> SessionTextArea<'a> {
                 ..Default::default()
             });
         }
+        s.forget_history();
         s.inactivate();
         s
     }
@@ -132,9 +210,14 @@ impl<'a> SessionTextArea<'a> {
         self.locked
     }
 
+    /// Also redraws the border, keeping whether the area is active.
     pub fn set_title(&mut self, title: String) {
         tracing::trace!("Setting title for {:?} to: {}", self.id, title);
         self.title = title;
+        match self.active {
+            true => self.activate(),
+            false => self.inactivate(),
+        }
     }
 
     fn text_area_format() -> TextArea<'a> {
@@ -155,10 +238,11 @@ impl<'a> SessionTextArea<'a> {
     pub fn clear(&mut self) {
         self.text_area.select_all();
         self.text_area.cut();
+        self.forget_history();
         self.inactivate();
     }
 
-    fn lines(&self) -> &[String] {
+    pub fn lines(&self) -> &[String] {
         self.text_area.lines()
     }
 
@@ -178,6 +262,7 @@ impl<'a> SessionTextArea<'a> {
         for input in string_to_inputs(message.content.as_str()) {
             self.input(input);
         }
+        self.forget_history();
     }
 
     pub fn is_empty(&self) -> bool {
@@ -187,20 +272,7 @@ impl<'a> SessionTextArea<'a> {
 
     pub fn input(&mut self, input: Input) -> bool {
         match input.key {
-            Key::Char(_) => {
-                if self.is_locked() {
-                    return false;
-                }
-                let current_line_length = self.lines().last().map_or(0, |l| l.len());
-                if current_line_length + 1 >= self.max_line_length {
-                    self.text_area.input(Input {
-                        key: Key::Enter,
-                        ..input
-                    });
-                }
-                self.text_area.input(input)
-            }
-            Key::Backspace | Key::Delete | Key::Enter | Key::Tab => {
+            Key::Char(_) | Key::Backspace | Key::Delete | Key::Enter | Key::Tab => {
                 if self.is_locked() {
                     return false;
                 }
@@ -211,12 +283,127 @@ impl<'a> SessionTextArea<'a> {
         true
     }
 
+    /// Move the cursor `rows` lines down, or up if negative, which scrolls the drawn text with it.
+    pub fn scroll_cursor(&mut self, rows: i16) {
+        let cursor_move = match rows < 0 {
+            true => CursorMove::Up,
+            false => CursorMove::Down,
+        };
+        for _ in 0..rows.unsigned_abs() {
+            self.text_area.move_cursor(cursor_move);
+        }
+    }
+
+    pub fn move_cursor_to_end(&mut self) {
+        self.text_area.move_cursor(CursorMove::Bottom);
+        self.text_area.move_cursor(CursorMove::End);
+    }
+
+    /// Input regardless of the lock, for streamed answers. What was streamed in can't be undone.
+    /// Long lines aren't hard-wrapped, they are truncated or wrapped when drawn instead.
     pub fn force_input(&mut self, input: Input) {
-        self.locked = false;
-        self.input(input);
+        self.text_area.input(input);
+        self.forget_history();
         self.locked = true;
     }
 
+    /// Undo the last edit, unless the area is locked.
+    pub fn undo(&mut self) -> bool {
+        !self.is_locked() && self.text_area.undo()
+    }
+
+    /// Redo the last undone edit, unless the area is locked.
+    pub fn redo(&mut self) -> bool {
+        !self.is_locked() && self.text_area.redo()
+    }
+
+    /// Only edits made from here on can be undone.
+    fn forget_history(&mut self) {
+        self.text_area
+            .set_max_histories(self.text_area.max_histories());
+    }
+
+    /// Rows scrolled off the top of a pane of `height` rows to keep the cursor's line visible.
+    pub fn cursor_scroll(&self, height: usize) -> u16 {
+        let (row, _) = self.text_area.cursor();
+        (row + 1).saturating_sub(height) as u16
+    }
+
+    /// Rows scrolled off the top of a pane of `height` rows to show the last line.
+    pub fn bottom_scroll(&self, height: usize) -> u16 {
+        self.lines().len().saturating_sub(height) as u16
+    }
+
+    /// The text with every line that doesn't fit in `area` cut short with an ellipsis, scrolled
+    /// down `scroll` rows or else so the cursor's line is visible.
+    pub fn truncated(&self, area: Rect, scroll: Option<u16>) -> Paragraph<'_> {
+        let width = area.width.saturating_sub(2) as usize;
+        let height = area.height.saturating_sub(2) as usize;
+        let scroll = scroll.unwrap_or_else(|| self.cursor_scroll(height));
+        let lines = self
+            .lines()
+            .iter()
+            .map(|line| Line::raw(truncate_line(line, width)))
+            .collect::<Vec<_>>();
+        let mut paragraph = Paragraph::new(lines)
+            .style(self.text_area.style())
+            .scroll((scroll, 0));
+        if let Some(block) = self.text_area.block() {
+            paragraph = paragraph.block(block.clone());
+        }
+        paragraph
+    }
+
+    /// The text soft-wrapped to the width of `area`, scrolled so the cursor's line is visible.
+    /// Only the drawing wraps, the lines themselves stay as they are.
+    pub fn wrapped(&self, area: Rect) -> Paragraph<'_> {
+        let width = area.width.saturating_sub(2) as usize;
+        let height = area.height.saturating_sub(2) as usize;
+        let (cursor_row, cursor_col) = self.text_area.cursor();
+        let mut cursor_display_row = 0;
+        let mut lines = Vec::new();
+        for (row, line) in self.lines().iter().enumerate() {
+            let chars = line.chars().collect::<Vec<_>>();
+            let starts = wrap_points(line, width);
+            for (i, &start) in starts.iter().enumerate() {
+                let last = i + 1 == starts.len();
+                let end = starts.get(i + 1).copied().unwrap_or(chars.len());
+                if row == cursor_row && start <= cursor_col && (cursor_col < end || last) {
+                    cursor_display_row = lines.len();
+                    lines.push(cursor_line(
+                        &chars[start..end],
+                        cursor_col - start,
+                        self.text_area.cursor_style(),
+                    ));
+                } else {
+                    lines.push(Line::raw(chars[start..end].iter().collect::<String>()));
+                }
+            }
+        }
+        let scroll = (cursor_display_row + 1).saturating_sub(height) as u16;
+        let mut paragraph = Paragraph::new(lines)
+            .style(self.text_area.style())
+            .scroll((scroll, 0));
+        if let Some(block) = self.text_area.block() {
+            paragraph = paragraph.block(block.clone());
+        }
+        paragraph
+    }
+
+    /// The text rendered as Markdown, scrolled down `scroll` rows or else so the cursor's line is
+    /// visible. Lines too long for `area` are cut off.
+    pub fn markdown(&self, area: Rect, scroll: Option<u16>) -> Paragraph<'_> {
+        let height = area.height.saturating_sub(2) as usize;
+        let scroll = scroll.unwrap_or_else(|| self.cursor_scroll(height));
+        let mut paragraph = Paragraph::new(crate::markdown::render(self.lines()))
+            .style(self.text_area.style())
+            .scroll((scroll, 0));
+        if let Some(block) = self.text_area.block() {
+            paragraph = paragraph.block(block.clone());
+        }
+        paragraph
+    }
+
     pub fn text_area(&self) -> &TextArea<'a> {
         &self.text_area
     }
@@ -227,6 +414,7 @@ impl<'a> SessionTextArea<'a> {
             self.id,
             self.title()
         );
+        self.active = true;
         self.text_area
             .set_cursor_style(Style::default().add_modifier(Modifier::REVERSED));
         self.text_area.set_block(
@@ -238,6 +426,7 @@ impl<'a> SessionTextArea<'a> {
     }
 
     pub fn inactivate(&mut self) {
+        self.active = false;
         self.text_area.set_cursor_style(Style::default());
         self.text_area.set_block(
             Block::default()
@@ -256,3 +445,82 @@ impl<'a> From<&'a SessionTextArea<'a>> for Message {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ratatui::{buffer::Buffer, widgets::Widget};
+
+    #[test]
+    fn test_truncate_line() {
+        assert_eq!(truncate_line("ls -la", 6), "ls -la");
+        assert_eq!(truncate_line(r#"{"a":1,"b":2}"#, 6), r#"{"a":…"#);
+        assert_eq!(truncate_line("abc", 0), "");
+        assert_eq!(truncate_line("日本語です", 6), "日本…");
+        assert_eq!(truncate_line("日本語", 6), "日本語");
+    }
+
+    #[test]
+    fn test_wrap_points() {
+        assert_eq!(wrap_points("ls -la", 10), vec![0]);
+        assert_eq!(wrap_points("the quick brown fox", 10), vec![0, 10]);
+        assert_eq!(wrap_points("abcdefghij", 4), vec![0, 4, 8]);
+        assert_eq!(wrap_points("abc", 0), vec![0]);
+        assert_eq!(wrap_points("日本語です", 4), vec![0, 2, 4]);
+    }
+
+    #[test]
+    fn test_title() {
+        let mut area = SessionTextArea::new(SessionAreaId::Assistant, &[]);
+        assert_eq!(area.title, "assistant");
+
+        area.activate();
+        area.set_title("root > 0 : assistant".to_string());
+        assert_eq!(
+            area.text_area.cursor_style(),
+            Style::default().add_modifier(Modifier::REVERSED)
+        );
+        let mut buffer = Buffer::empty(Rect::new(0, 0, 30, 3));
+        area.text_area.render(buffer.area, &mut buffer);
+        let top = (0..30).map(|x| buffer[(x, 0)].symbol()).collect::<String>();
+        assert!(top.contains("root > 0 : assistant"));
+    }
+
+    #[test]
+    fn test_undo() {
+        let mut area = SessionTextArea::new(SessionAreaId::Assistant, &[]);
+        area.force_input(Input {
+            key: Key::Char('a'),
+            ..Default::default()
+        });
+        area.unlock();
+        assert!(!area.undo());
+
+        area.input(Input {
+            key: Key::Char('b'),
+            ..Default::default()
+        });
+        assert!(area.undo());
+        assert_eq!(area.lines(), ["a"]);
+        assert!(area.redo());
+        assert_eq!(area.lines(), ["ab"]);
+
+        area.lock();
+        assert!(!area.undo());
+        area.unlock();
+        area.clear();
+        assert!(!area.undo());
+        assert!(area.is_empty());
+    }
+
+    #[test]
+    fn test_long_lines_not_broken() {
+        let mut area = SessionTextArea::new(SessionAreaId::User, &[]);
+        let line = "word ".repeat(40);
+        for input in string_to_inputs(&line) {
+            area.input(input);
+        }
+        assert_eq!(area.lines(), [line.as_str()]);
+        assert_eq!(area.message().unwrap().content, line);
+    }
+}
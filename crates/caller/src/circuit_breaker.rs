@@ -0,0 +1,126 @@
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::error::Error;
+
+#[derive(Debug, Clone, Copy)]
+pub struct CircuitBreakerConfig {
+    /// Consecutive server-side failures within `window` that open the circuit.
+    pub failure_threshold: usize,
+    pub window: Duration,
+    /// How long requests are short-circuited once the circuit is open.
+    pub cooldown: Duration,
+}
+
+impl Default for CircuitBreakerConfig {
+    fn default() -> Self {
+        Self {
+            failure_threshold: 5,
+            window: Duration::from_secs(60),
+            cooldown: Duration::from_secs(30),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct State {
+    failures: VecDeque<Instant>,
+    open_until: Option<Instant>,
+}
+
+/// Stops sending requests for a while after repeated 5xx/overloaded responses, so callers
+/// fail fast during an incident instead of waiting through a full backoff every time.
+/// Clones share state.
+#[derive(Debug, Clone, Default)]
+pub struct CircuitBreaker {
+    config: CircuitBreakerConfig,
+    state: Arc<Mutex<State>>,
+}
+
+impl CircuitBreaker {
+    pub fn new(config: CircuitBreakerConfig) -> Self {
+        Self {
+            config,
+            state: Default::default(),
+        }
+    }
+
+    /// Err if the circuit is open.
+    pub fn check(&self) -> Result<(), Error> {
+        let mut state = self.state.lock().unwrap();
+        match state.open_until {
+            Some(until) if until > Instant::now() => {
+                Err(Error::CircuitOpen(until.duration_since(Instant::now())))
+            }
+            Some(_) => {
+                tracing::debug!("circuit breaker cooldown over");
+                state.open_until = None;
+                state.failures.clear();
+                Ok(())
+            }
+            None => Ok(()),
+        }
+    }
+
+    pub fn record_success(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.failures.clear();
+        state.open_until = None;
+    }
+
+    pub fn record_failure(&self) {
+        let mut state = self.state.lock().unwrap();
+        let now = Instant::now();
+        state.failures.push_back(now);
+        while let Some(&first) = state.failures.front() {
+            if now.duration_since(first) <= self.config.window {
+                break;
+            }
+            state.failures.pop_front();
+        }
+        if state.failures.len() >= self.config.failure_threshold {
+            tracing::warn!(
+                "{} server errors within {:?}, backing off for {:?}",
+                state.failures.len(),
+                self.config.window,
+                self.config.cooldown
+            );
+            state.open_until = Some(now + self.config.cooldown);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn breaker() -> CircuitBreaker {
+        CircuitBreaker::new(CircuitBreakerConfig {
+            failure_threshold: 2,
+            window: Duration::from_secs(60),
+            cooldown: Duration::from_millis(50),
+        })
+    }
+
+    #[test]
+    fn test_opens_after_threshold() {
+        let breaker = breaker();
+        breaker.record_failure();
+        assert!(breaker.check().is_ok());
+        breaker.record_failure();
+        assert!(matches!(breaker.check(), Err(Error::CircuitOpen(_))));
+
+        std::thread::sleep(Duration::from_millis(60));
+        assert!(breaker.check().is_ok());
+    }
+
+    #[test]
+    fn test_success_resets() {
+        let breaker = breaker();
+        breaker.record_failure();
+        breaker.record_success();
+        breaker.record_failure();
+        assert!(breaker.check().is_ok());
+    }
+}
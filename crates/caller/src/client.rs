@@ -1,4 +1,5 @@
 use std::pin::Pin;
+use std::time::Duration;
 
 use reqwest::header::HeaderMap;
 use reqwest_eventsource::{Event, EventSource, RequestBuilderExt};
@@ -6,39 +7,266 @@ use serde::de::DeserializeOwned;
 use serde::Serialize;
 use tokio_stream::{Stream, StreamExt};
 
-use super::error::{map_deserialization_error, Error, WrappedError};
+use super::circuit_breaker::{CircuitBreaker, CircuitBreakerConfig};
+use super::error::{
+    authentication_error, map_deserialization_error, status_error, Error, WrappedError,
+};
+
+pub const IDEMPOTENCY_KEY_HEADER: &str = "Idempotency-Key";
+
+/// Target the bodies of requests and responses are logged under at debug level, as sent and
+/// received, e.g. with `RUST_LOG=rgpt::wire=debug`.
+pub const WIRE_TARGET: &str = "rgpt::wire";
+
+/// Headers as logged, with the values of those that carry a key hidden.
+fn redacted(headers: &HeaderMap) -> String {
+    headers
+        .iter()
+        .map(|(name, value)| {
+            let secret = value.is_sensitive()
+                || matches!(name.as_str(), "authorization" | "x-api-key" | "api-key");
+            match (secret, value.to_str()) {
+                (true, _) => format!("{}: [redacted]", name),
+                (false, Ok(value)) => format!("{}: {}", name, value),
+                (false, Err(_)) => format!("{}: [binary]", name),
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+fn log_request(method: &str, uri: &str, headers: &HeaderMap, body: &[u8]) {
+    tracing::debug!(
+        target: WIRE_TARGET,
+        "{} {} [{}] {}",
+        method,
+        uri,
+        redacted(headers),
+        String::from_utf8_lossy(body)
+    );
+}
+
+fn log_response(status: reqwest::StatusCode, body: &[u8]) {
+    tracing::debug!(
+        target: WIRE_TARGET,
+        "{} {}",
+        status,
+        String::from_utf8_lossy(body)
+    );
+}
+
+/// Anthropic's status for an API that is temporarily overloaded.
+const OVERLOADED: u16 = 529;
+
+/// Overloaded or unavailable, worth retrying with plain backoff.
+fn is_overloaded(status: reqwest::StatusCode) -> bool {
+    status == reqwest::StatusCode::SERVICE_UNAVAILABLE || status.as_u16() == OVERLOADED
+}
+
+/// How long a rate limited response says to wait, as seconds or as an HTTP date. A date in the
+/// past leaves it to the backoff.
+fn retry_after(headers: &HeaderMap) -> Option<Duration> {
+    let value = headers
+        .get(reqwest::header::RETRY_AFTER)?
+        .to_str()
+        .ok()?
+        .trim();
+    match value.parse() {
+        Ok(secs) => Some(Duration::from_secs(secs)),
+        Err(_) => parse_http_date(value)?
+            .duration_since(std::time::SystemTime::now())
+            .ok(),
+    }
+}
+
+/// An IMF-fixdate, e.g. `Sun, 06 Nov 1994 08:49:37 GMT`, the only form servers may send.
+/// Ref: https://www.rfc-editor.org/rfc/rfc9110#section-5.6.7
+fn parse_http_date(date: &str) -> Option<std::time::SystemTime> {
+    const MONTHS: [&str; 12] = [
+        "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+    ];
+    let [_, day, month, year, time, "GMT"] = date.split_whitespace().collect::<Vec<_>>()[..] else {
+        return None;
+    };
+    let day: u64 = day.parse().ok()?;
+    let month = MONTHS.iter().position(|m| *m == month)? as u64 + 1;
+    let year: u64 = year.parse().ok()?;
+    let mut hms = time.split(':').map(|part| part.parse::<u64>().ok());
+    let (hours, minutes, seconds) = (hms.next()??, hms.next()??, hms.next()??);
+
+    // Days since the epoch of a civil date.
+    // Ref: https://howardhinnant.github.io/date_algorithms.html#days_from_civil
+    let y = if month <= 2 { year - 1 } else { year };
+    let era = y / 400;
+    let yoe = y - era * 400;
+    let mp = (month + 9) % 12;
+    let doy = (153 * mp + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    let days = (era * 146097 + doe).checked_sub(719468)?;
+
+    let secs = days * 86400 + hours * 3600 + minutes * 60 + seconds;
+    Some(std::time::UNIX_EPOCH + Duration::from_secs(secs))
+}
+
+/// How failed requests are retried. The defaults are `backoff`'s.
+#[derive(Debug, Clone, Copy)]
+pub struct BackoffConfig {
+    /// Wait before the first retry, growing with each one.
+    pub initial_interval: Duration,
+    /// Cap on the wait between retries.
+    pub max_interval: Duration,
+    /// Give up once retrying has taken this long, or never if `None`.
+    pub max_elapsed_time: Option<Duration>,
+}
+
+impl Default for BackoffConfig {
+    fn default() -> Self {
+        let backoff = backoff::ExponentialBackoff::default();
+        Self {
+            initial_interval: backoff.initial_interval,
+            max_interval: backoff.max_interval,
+            max_elapsed_time: backoff.max_elapsed_time,
+        }
+    }
+}
+
+impl From<BackoffConfig> for backoff::ExponentialBackoff {
+    fn from(config: BackoffConfig) -> Self {
+        backoff::ExponentialBackoffBuilder::new()
+            .with_initial_interval(config.initial_interval)
+            .with_max_interval(config.max_interval)
+            .with_max_elapsed_time(config.max_elapsed_time)
+            .build()
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct ClientConfig {
+    /// Limit on a whole request. Streams can run longer, they time out when nothing arrives for
+    /// this long.
+    pub timeout: Option<Duration>,
+    pub connect_timeout: Option<Duration>,
+    pub backoff: BackoffConfig,
+}
+
+impl Default for ClientConfig {
+    fn default() -> Self {
+        Self {
+            timeout: Some(Duration::from_secs(120)),
+            connect_timeout: Some(Duration::from_secs(10)),
+            backoff: BackoffConfig::default(),
+        }
+    }
+}
 
 #[derive(Debug)]
 pub struct Client {
     pub http_client: reqwest::Client,
     pub backoff: backoff::ExponentialBackoff,
     pub headers: HeaderMap,
+    pub circuit_breaker: CircuitBreaker,
+    timeout: Option<Duration>,
 }
 
 impl Client {
+    /// A client with the default config. Panics if the HTTP client can't be built, like
+    /// [`reqwest::Client::new`], use [`Client::with_config`] to handle that instead.
     pub fn new(headers: HeaderMap) -> Self {
+        Self::with_config(headers, ClientConfig::default()).expect("failed to build http client")
+    }
+
+    /// A client with the default config, retrying with `backoff`.
+    pub fn with_backoff(headers: HeaderMap, backoff: backoff::ExponentialBackoff) -> Self {
         Self {
-            http_client: reqwest::Client::new(),
-            backoff: Default::default(),
+            backoff,
+            ..Self::new(headers)
+        }
+    }
+
+    pub fn with_config(headers: HeaderMap, config: ClientConfig) -> Result<Self, Error> {
+        let mut builder = reqwest::Client::builder();
+        // A total timeout on the client would cut off long streams too
+        if let Some(timeout) = config.timeout {
+            builder = builder.read_timeout(timeout);
+        }
+        if let Some(connect_timeout) = config.connect_timeout {
+            builder = builder.connect_timeout(connect_timeout);
+        }
+        Ok(Self {
+            http_client: builder.build()?,
+            backoff: config.backoff.into(),
             headers,
+            circuit_breaker: Default::default(),
+            timeout: config.timeout,
+        })
+    }
+
+    /// POST builder for a request that is read in full, with the total timeout.
+    fn post_request(&self, uri: &str) -> reqwest::RequestBuilder {
+        let request = self.http_client.post(uri).headers(self.headers.clone());
+        match self.timeout {
+            Some(timeout) => request.timeout(timeout),
+            None => request,
         }
     }
 
+    pub fn circuit_breaker(mut self, config: CircuitBreakerConfig) -> Self {
+        self.circuit_breaker = CircuitBreaker::new(config);
+        self
+    }
+
     pub async fn post<I, O>(&self, uri: &str, request: I) -> Result<O, Error>
     where
         I: Serialize,
         O: DeserializeOwned,
     {
         let request = self
-            .http_client
-            .post(uri)
-            .headers(self.headers.clone())
+            .post_request(uri)
             .body(serde_json::to_vec(&request)?)
             .build()?;
 
         self.execute(request).await
     }
 
+    /// Same as [`Client::post`], with a key that lets the server recognize retries of the same
+    /// request. Retries resend the request as built here, so they all carry the same key.
+    pub async fn post_idempotent<I, O>(
+        &self,
+        uri: &str,
+        request: I,
+        idempotency_key: &str,
+    ) -> Result<O, Error>
+    where
+        I: Serialize,
+        O: DeserializeOwned,
+    {
+        let request = self
+            .post_request(uri)
+            .header(IDEMPOTENCY_KEY_HEADER, idempotency_key)
+            .body(serde_json::to_vec(&request)?)
+            .build()?;
+
+        self.execute(request).await
+    }
+
+    /// Single GET without retries, for probes that should fail fast.
+    pub async fn get<O>(&self, uri: &str, timeout: Duration) -> Result<O, Error>
+    where
+        O: DeserializeOwned,
+    {
+        self.circuit_breaker.check()?;
+        log_request("GET", uri, &self.headers, &[]);
+        let response = self
+            .http_client
+            .get(uri)
+            .headers(self.headers.clone())
+            .timeout(timeout)
+            .send()
+            .await?;
+
+        self.process_response(response).await
+    }
+
     pub async fn post_stream<I, O, E>(
         &self,
         uri: &str,
@@ -48,18 +276,59 @@ impl Client {
     where
         I: Serialize,
         O: DeserializeOwned + Send + 'static,
-        E: Send + 'static,
+        E: From<Error> + Send + 'static,
     {
         tracing::trace!("POSTing to {}", uri);
+        self.circuit_breaker.check()?;
+        let body = serde_json::to_vec(&request)?;
+        log_request("POST", uri, &self.headers, &body);
         let event_source = self
             .http_client
             .post(uri)
             .headers(self.headers.clone())
-            .body(serde_json::to_vec(&request)?)
+            .body(body)
             .eventsource()?;
 
         tracing::trace!("Starting event source");
-        Ok(stream(event_source, handler).await)
+        Ok(stream(event_source, handler, self.circuit_breaker.clone()).await)
+    }
+
+    /// POST whose response streams a JSON value per line, like Ollama's, each line passed to
+    /// `handler`. Fails if the server doesn't accept the request.
+    pub async fn post_lines<I, O, E>(
+        &self,
+        uri: &str,
+        request: I,
+        handler: impl Fn(&str) -> Result<O, E> + Send + 'static,
+    ) -> Result<Pin<Box<dyn Stream<Item = Result<O, E>> + Send>>, Error>
+    where
+        I: Serialize,
+        O: Send + 'static,
+        E: Send + 'static,
+    {
+        tracing::trace!("POSTing to {}", uri);
+        self.circuit_breaker.check()?;
+        let body = serde_json::to_vec(&request)?;
+        log_request("POST", uri, &self.headers, &body);
+        let response = self
+            .http_client
+            .post(uri)
+            .headers(self.headers.clone())
+            .body(body)
+            .send()
+            .await?;
+
+        let status = response.status();
+        if status.is_server_error() {
+            self.circuit_breaker.record_failure();
+        }
+        if !status.is_success() {
+            let body = response.bytes().await?;
+            log_response(status, &body);
+            return Err(status_error(status, &body));
+        }
+        self.circuit_breaker.record_success();
+        Ok(lines(response, handler))
     }
 
     async fn process_response<O>(&self, response: reqwest::Response) -> Result<O, Error>
@@ -68,7 +337,17 @@ impl Client {
     {
         let status = response.status();
         let bytes = response.bytes().await?;
+        log_response(status, &bytes);
 
+        if status.is_server_error() {
+            self.circuit_breaker.record_failure();
+        } else if status.is_success() {
+            self.circuit_breaker.record_success();
+        }
+
+        if let Some(e) = authentication_error(status, bytes.as_ref()) {
+            return Err(e);
+        }
         if !status.is_success() {
             let wrapped_error: WrappedError = serde_json::from_slice(bytes.as_ref())
                 .map_err(|e| map_deserialization_error(e, bytes.as_ref()))?;
@@ -76,8 +355,6 @@ impl Client {
             return Err(Error::ApiError(wrapped_error.error));
         }
 
-        tracing::trace!("Response body: {:?}", String::from_utf8_lossy(bytes.as_ref()));
-
         let response: O = serde_json::from_slice(bytes.as_ref())
             .map_err(|e| map_deserialization_error(e, bytes.as_ref()))?;
         Ok(response)
@@ -88,23 +365,50 @@ impl Client {
         O: DeserializeOwned,
     {
         let client = self.http_client.clone();
+        self.circuit_breaker.check()?;
+        log_request(
+            request.method().as_str(),
+            request.url().as_str(),
+            request.headers(),
+            request
+                .body()
+                .and_then(|body| body.as_bytes())
+                .unwrap_or_default(),
+        );
 
         match request.try_clone() {
             // Only clone-able requests can be retried
             Some(request) => {
                 backoff::future::retry(self.backoff.clone(), || async {
+                    self.circuit_breaker
+                        .check()
+                        .map_err(backoff::Error::Permanent)?;
                     let response = client
                         .execute(request.try_clone().unwrap())
                         .await
-                        .map_err(Error::Reqwest)
+                        .map_err(Error::from)
                         .map_err(backoff::Error::Permanent)?;
 
                     let status = response.status();
+                    let rate_limited = status == reqwest::StatusCode::TOO_MANY_REQUESTS;
+                    let retry_after = retry_after(response.headers()).filter(|_| rate_limited);
                     let bytes = response
                         .bytes()
                         .await
-                        .map_err(Error::Reqwest)
+                        .map_err(Error::from)
                         .map_err(backoff::Error::Permanent)?;
+                    log_response(status, &bytes);
+
+                    if status.is_server_error() {
+                        self.circuit_breaker.record_failure();
+                    } else if status.is_success() {
+                        self.circuit_breaker.record_success();
+                    }
+
+                    // A rejected key stays rejected
+                    if let Some(e) = authentication_error(status, bytes.as_ref()) {
+                        return Err(backoff::Error::Permanent(e));
+                    }
 
                     // Deserialize response body from either error object or actual response object
                     if !status.is_success() {
@@ -112,12 +416,32 @@ impl Client {
                             "Error response: {}",
                             String::from_utf8_lossy(bytes.as_ref())
                         );
-                        let wrapped_error: WrappedError = serde_json::from_slice(bytes.as_ref())
-                            .map_err(|e| map_deserialization_error(e, bytes.as_ref()))
-                            .map_err(backoff::Error::Permanent)?;
+                        let wrapped_error: WrappedError =
+                            match serde_json::from_slice(bytes.as_ref()) {
+                                Ok(wrapped_error) => wrapped_error,
+                                // A proxy in front of an overloaded API may not answer in JSON
+                                Err(e) if is_overloaded(status) => {
+                                    return Err(backoff::Error::Transient {
+                                        err: map_deserialization_error(e, bytes.as_ref()),
+                                        retry_after: None,
+                                    });
+                                }
+                                Err(e) => {
+                                    return Err(backoff::Error::Permanent(
+                                        map_deserialization_error(e, bytes.as_ref()),
+                                    ));
+                                }
+                            };
 
-                        // Retry if rate limited
-                        if status.as_u16() == 429 {
+                        // Retry if rate limited, when the response says to, or overloaded, with
+                        // backoff alone
+                        if rate_limited {
+                            return Err(backoff::Error::Transient {
+                                err: Error::ApiError(wrapped_error.error),
+                                retry_after,
+                            });
+                        } else if is_overloaded(status) || wrapped_error.error.r#type.is_transient()
+                        {
                             return Err(backoff::Error::Transient {
                                 err: Error::ApiError(wrapped_error.error),
                                 retry_after: None,
@@ -129,10 +453,6 @@ impl Client {
                         }
                     }
 
-                    tracing::trace!(
-                        "Response body: {:?}",
-                        String::from_utf8_lossy(bytes.as_ref())
-                    );
                     let response: O = serde_json::from_slice(bytes.as_ref())
                         .map_err(|e| map_deserialization_error(e, bytes.as_ref()))
                         .map_err(backoff::Error::Permanent)?;
@@ -148,13 +468,54 @@ impl Client {
     }
 }
 
+fn lines<O, E>(
+    response: reqwest::Response,
+    handler: impl Fn(&str) -> Result<O, E> + Send + 'static,
+) -> Pin<Box<dyn Stream<Item = Result<O, E>> + Send>>
+where
+    O: Send + 'static,
+    E: Send + 'static,
+{
+    let (tx, rx) = tokio::sync::mpsc::unbounded_channel();
+    let mut body = response.bytes_stream();
+    tokio::spawn(async move {
+        let mut buffer = Vec::new();
+        loop {
+            let chunk = body.next().await;
+            match &chunk {
+                Some(Ok(bytes)) => buffer.extend_from_slice(bytes),
+                Some(Err(e)) => tracing::error!("Error in line stream {:?}", e),
+                // A last line without a newline
+                None => buffer.push(b'\n'),
+            }
+            while let Some(end) = buffer.iter().position(|&b| b == b'\n') {
+                let line = buffer.drain(..=end).collect::<Vec<_>>();
+                let line = String::from_utf8_lossy(&line);
+                tracing::debug!(target: WIRE_TARGET, "{}", line.trim_end());
+                if !line.trim().is_empty() && tx.send(handler(line.trim())).is_err() {
+                    // rx dropped
+                    return;
+                }
+            }
+            if !matches!(chunk, Some(Ok(_))) {
+                break;
+            }
+        }
+    });
+
+    Box::pin(tokio_stream::wrappers::UnboundedReceiverStream::new(rx))
+}
+
+/// Events of `event_source` passed to `event_handler`. A stream that fails, including one the
+/// server doesn't accept, ends with the error.
 async fn stream<O, E>(
     mut event_source: EventSource,
     event_handler: impl Fn(Event) -> Result<O, E> + Send + 'static,
+    circuit_breaker: CircuitBreaker,
 ) -> Pin<Box<dyn Stream<Item = Result<O, E>> + Send>>
 where
     O: DeserializeOwned + Send + 'static,
-    E: Send + 'static,
+    E: From<Error> + Send + 'static,
 {
     let (tx, rx) = tokio::sync::mpsc::unbounded_channel();
 
@@ -163,14 +524,33 @@ where
         while let Some(ev) = event_source.next().await {
             match ev {
                 Ok(ev) => {
-                    tracing::trace!("Received event: {:?}", ev);
+                    if let Event::Message(message) = &ev {
+                        tracing::debug!(target: WIRE_TARGET, "{}: {}", message.event, message.data);
+                    }
+                    if let Event::Open = ev {
+                        circuit_breaker.record_success();
+                    }
                     if let Err(_e) = tx.send(event_handler(ev)) {
                         // rx dropped
                         break;
                     }
                 }
+                Err(reqwest_eventsource::Error::StreamEnded) => break,
                 Err(e) => {
                     tracing::error!("Error in event source stream {:?}", e);
+                    let error = match e {
+                        reqwest_eventsource::Error::InvalidStatusCode(status, response) => {
+                            if status.is_server_error() {
+                                circuit_breaker.record_failure();
+                            }
+                            let body = response.bytes().await.unwrap_or_default();
+                            log_response(status, &body);
+                            status_error(status, &body)
+                        }
+                        reqwest_eventsource::Error::Transport(e) => Error::from(e),
+                        e => Error::StreamError(e.to_string()),
+                    };
+                    let _ = tx.send(Err(error.into()));
                     break;
                 }
             }
@@ -181,3 +561,271 @@ where
 
     Box::pin(tokio_stream::wrappers::UnboundedReceiverStream::new(rx))
 }
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::error::ApiErrorKind;
+    use crate::test_server::{response, response_with_header, serve};
+
+    #[tokio::test]
+    async fn test_idempotency_key_reused_on_retry() {
+        let (uri, server) = serve(vec![
+            response(
+                "429 Too Many Requests",
+                r#"{"type":"error","error":{"type":"rate_limit_error","message":"slow down"}}"#,
+            ),
+            response("200 OK", r#"{"ok":true}"#),
+        ])
+        .await;
+        let mut client = Client::new(HeaderMap::new());
+        client.backoff.initial_interval = Duration::from_millis(10);
+
+        let response: serde_json::Value = client
+            .post_idempotent(&uri, serde_json::json!({}), "key-1")
+            .await
+            .unwrap();
+        assert_eq!(response["ok"], true);
+
+        let requests = server.await.unwrap();
+        assert_eq!(requests.len(), 2);
+        assert!(requests
+            .iter()
+            .all(|request| request.contains("idempotency-key: key-1")));
+    }
+
+    #[tokio::test]
+    async fn test_overloaded_retried() {
+        let (uri, server) = serve(vec![
+            response(
+                "529 Overloaded",
+                r#"{"type":"error","error":{"type":"overloaded_error","message":"Overloaded"}}"#,
+            ),
+            response("503 Service Unavailable", "upstream connect error"),
+            response("200 OK", r#"{"ok":true}"#),
+        ])
+        .await;
+        let mut client = Client::new(HeaderMap::new());
+        client.backoff.initial_interval = Duration::from_millis(10);
+
+        let response: serde_json::Value = client.post(&uri, serde_json::json!({})).await.unwrap();
+        assert_eq!(response["ok"], true);
+        assert_eq!(server.await.unwrap().len(), 3);
+    }
+
+    #[test]
+    fn test_retry_after() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(retry_after(&headers), None);
+        headers.insert(reqwest::header::RETRY_AFTER, "3".parse().unwrap());
+        assert_eq!(retry_after(&headers), Some(Duration::from_secs(3)));
+        headers.insert(
+            reqwest::header::RETRY_AFTER,
+            "Sun, 06 Nov 1994 08:49:37 GMT".parse().unwrap(),
+        );
+        assert_eq!(retry_after(&headers), None);
+
+        assert_eq!(
+            parse_http_date("Sun, 06 Nov 1994 08:49:37 GMT"),
+            Some(std::time::UNIX_EPOCH + Duration::from_secs(784111777))
+        );
+        assert_eq!(
+            parse_http_date("Thu, 29 Feb 2024 00:00:00 GMT"),
+            Some(std::time::UNIX_EPOCH + Duration::from_secs(1709164800))
+        );
+        assert_eq!(parse_http_date("Sunday, 06-Nov-94 08:49:37 GMT"), None);
+    }
+
+    #[test]
+    fn test_redacted() {
+        let mut headers = HeaderMap::new();
+        headers.insert("x-api-key", "sk-ant-secret".parse().unwrap());
+        headers.insert(
+            reqwest::header::AUTHORIZATION,
+            "Bearer sk-secret".parse().unwrap(),
+        );
+        headers.insert(
+            reqwest::header::CONTENT_TYPE,
+            "application/json".parse().unwrap(),
+        );
+        let logged = redacted(&headers);
+        assert!(!logged.contains("secret"));
+        assert!(logged.contains("x-api-key: [redacted]"));
+        assert!(logged.contains("content-type: application/json"));
+    }
+
+    #[tokio::test]
+    async fn test_retry_after_honored() {
+        let (uri, server) = serve(vec![
+            response_with_header(
+                "429 Too Many Requests",
+                Some("retry-after: 2"),
+                r#"{"type":"error","error":{"type":"rate_limit_error","message":"slow down"}}"#,
+            ),
+            response("200 OK", r#"{"ok":true}"#),
+        ])
+        .await;
+        let mut client = Client::new(HeaderMap::new());
+        client.backoff.initial_interval = Duration::from_millis(10);
+
+        let started = std::time::Instant::now();
+        let response: serde_json::Value = client.post(&uri, serde_json::json!({})).await.unwrap();
+        assert_eq!(response["ok"], true);
+        assert!(started.elapsed() >= Duration::from_secs(2));
+        assert_eq!(server.await.unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_max_elapsed_time() {
+        let overloaded = response(
+            "529 Overloaded",
+            r#"{"type":"error","error":{"type":"overloaded_error","message":"Overloaded"}}"#,
+        );
+        let (uri, _server) = serve(vec![overloaded; 50]).await;
+        let client = Client::with_config(
+            HeaderMap::new(),
+            ClientConfig {
+                backoff: BackoffConfig {
+                    initial_interval: Duration::from_millis(20),
+                    max_interval: Duration::from_millis(50),
+                    max_elapsed_time: Some(Duration::from_millis(300)),
+                },
+                ..Default::default()
+            },
+        )
+        .unwrap()
+        .circuit_breaker(CircuitBreakerConfig {
+            failure_threshold: 100,
+            ..Default::default()
+        });
+
+        let started = std::time::Instant::now();
+        let result = client
+            .post::<_, serde_json::Value>(&uri, serde_json::json!({}))
+            .await;
+        assert!(matches!(result, Err(Error::ApiError(_))));
+        assert!(started.elapsed() < Duration::from_secs(2));
+    }
+
+    #[tokio::test]
+    async fn test_authentication_not_retried() {
+        let (uri, server) = serve(vec![response(
+            "401 Unauthorized",
+            r#"{"type":"error","error":{"type":"authentication_error","message":"invalid x-api-key"}}"#,
+        )])
+        .await;
+        let mut client = Client::new(HeaderMap::new());
+        client.backoff.initial_interval = Duration::from_millis(10);
+
+        let result = client
+            .post::<_, serde_json::Value>(&uri, serde_json::json!({}))
+            .await;
+        assert!(matches!(
+            result,
+            Err(Error::Authentication { status: 401, ref message }) if message == "invalid x-api-key"
+        ));
+        assert_eq!(server.await.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_stream_authentication() {
+        let (uri, server) = serve(vec![response(
+            "401 Unauthorized",
+            r#"{"type":"error","error":{"type":"authentication_error","message":"invalid x-api-key"}}"#,
+        )])
+        .await;
+        let client = Client::new(HeaderMap::new());
+
+        let events = client
+            .post_stream(&uri, serde_json::json!({}), |_| {
+                Ok::<_, Error>(serde_json::Value::Null)
+            })
+            .await
+            .unwrap()
+            .collect::<Vec<_>>()
+            .await;
+        assert!(matches!(
+            &events[..],
+            [Err(Error::Authentication { status: 401, message })] if message == "invalid x-api-key"
+        ));
+        server.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_stream_rate_limited() {
+        let (uri, server) = serve(vec![
+            response(
+                "429 Too Many Requests",
+                r#"{"type":"error","error":{"type":"rate_limit_error","message":"slow down"}}"#,
+            ),
+            response(
+                "529 Overloaded",
+                r#"{"type":"error","error":{"type":"overloaded_error","message":"Overloaded"}}"#,
+            ),
+        ])
+        .await;
+        let client = Client::new(HeaderMap::new());
+
+        for kind in [ApiErrorKind::RateLimit, ApiErrorKind::Overloaded] {
+            let events = client
+                .post_stream(&uri, serde_json::json!({}), |_| {
+                    Ok::<_, Error>(serde_json::Value::Null)
+                })
+                .await
+                .unwrap()
+                .collect::<Vec<_>>()
+                .await;
+            assert!(matches!(&events[..], [Err(e)] if e.api_error_kind() == Some(&kind)));
+            assert!(events[0].as_ref().unwrap_err().is_transient());
+        }
+        server.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_post_lines() {
+        let (uri, server) = serve(vec![
+            response("200 OK", "{\"n\":1}\n\n{\"n\":2}"),
+            response("404 Not Found", r#"{"error":"model not found"}"#),
+        ])
+        .await;
+        let client = Client::new(HeaderMap::new());
+        let parse = |line: &str| serde_json::from_str::<serde_json::Value>(line);
+
+        let lines = client
+            .post_lines(&uri, serde_json::json!({}), parse)
+            .await
+            .unwrap()
+            .map(|line| line.unwrap()["n"].as_u64())
+            .collect::<Vec<_>>()
+            .await;
+        assert_eq!(lines, vec![Some(1), Some(2)]);
+
+        let result = client.post_lines(&uri, serde_json::json!({}), parse).await;
+        assert!(matches!(result, Err(Error::StreamError(e)) if e.contains("model not found")));
+        server.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_timeout() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let uri = format!("http://{}/v1/messages", listener.local_addr().unwrap());
+        // Accept the connection and never answer
+        let server = tokio::spawn(async move { listener.accept().await });
+        let client = Client::with_config(
+            HeaderMap::new(),
+            ClientConfig {
+                timeout: Some(Duration::from_millis(100)),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+
+        let result = client
+            .post::<_, serde_json::Value>(&uri, serde_json::json!({}))
+            .await;
+        assert!(matches!(result, Err(Error::Timeout(_))));
+        server.abort();
+    }
+}
@@ -5,13 +5,19 @@ use serde::Deserialize;
 pub enum Error {
     /// Underlying error from reqwest library after an API call was made
     #[error("http error: {0}")]
-    Reqwest(#[from] reqwest::Error),
+    Reqwest(reqwest::Error),
+    /// No response, or a stalled one, within the client's timeout
+    #[error("request timed out: {0}")]
+    Timeout(reqwest::Error),
     /// OpenAI returns error object with details of API call failure
     #[error("{}: {}", .0.r#type, .0.message)]
     ApiError(ApiError),
     /// Error when a response cannot be deserialized into a Rust type
     #[error("failed to deserialize api response: {0}")]
     JSONDeserialize(serde_json::Error),
+    /// The API key was rejected, with a 401 or 403
+    #[error("authentication failed ({status}): {message}")]
+    Authentication { status: u16, message: String },
     /// Error on SSE streaming
     #[error("stream failed: {0}")]
     StreamError(String),
@@ -22,25 +28,212 @@ pub enum Error {
 
     #[error("Serialization error: {0}")]
     SerializationError(#[from] serde_json::Error),
+    /// Too many recent server-side failures, requests are short-circuited for a while
+    #[error("service degraded, backing off for another {0:?}")]
+    CircuitOpen(std::time::Duration),
     #[error("reqwest eventsource cannot clone request: {0}")]
     ReqwestEventSource(#[from] reqwest_eventsource::CannotCloneRequestError),
 }
 
+impl From<reqwest::Error> for Error {
+    fn from(e: reqwest::Error) -> Self {
+        if e.is_timeout() {
+            Error::Timeout(e)
+        } else {
+            Error::Reqwest(e)
+        }
+    }
+}
+
+impl Error {
+    /// The kind of error the API reported, if this is an API error.
+    pub fn api_error_kind(&self) -> Option<&ApiErrorKind> {
+        match self {
+            Error::ApiError(e) => Some(&e.r#type),
+            Error::Authentication { status: 403, .. } => Some(&ApiErrorKind::Permission),
+            Error::Authentication { .. } => Some(&ApiErrorKind::Authentication),
+            _ => None,
+        }
+    }
+
+    /// Whether sending the request again may succeed: timeouts, dropped connections, server
+    /// errors and transient API errors.
+    pub fn is_transient(&self) -> bool {
+        match self {
+            Error::Timeout(_) => true,
+            Error::Reqwest(e) => {
+                e.is_connect()
+                    || e.is_request()
+                    || e.is_body()
+                    || e.status().is_some_and(|status| status.is_server_error())
+            }
+            Error::ApiError(e) => e.r#type.is_transient(),
+            _ => false,
+        }
+    }
+
+    /// Whether the request got no answer, having timed out or lost its connection. Unlike
+    /// other transient errors, [`Client`](crate::client::Client) doesn't retry these itself.
+    pub fn is_connection(&self) -> bool {
+        match self {
+            Error::Timeout(_) => true,
+            Error::Reqwest(e) => e.is_connect() || e.is_request() || e.is_body(),
+            _ => false,
+        }
+    }
+}
+
 /// Anthropic API returns error object on failure
 #[derive(Debug, Deserialize)]
 pub struct ApiError {
     pub message: String,
-    pub r#type: String,
+    pub r#type: ApiErrorKind,
     pub param: Option<serde_json::Value>,
     pub code: Option<serde_json::Value>,
 }
 
+/// The `type` of an API error object.
+/// Ref: https://docs.anthropic.com/en/api/errors
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(from = "String")]
+pub enum ApiErrorKind {
+    InvalidRequest,
+    Authentication,
+    Permission,
+    NotFound,
+    RequestTooLarge,
+    RateLimit,
+    Api,
+    Overloaded,
+    /// A type this client doesn't know about, kept verbatim.
+    Other(String),
+}
+
+impl ApiErrorKind {
+    /// Whether the same request may succeed if sent again later.
+    pub fn is_transient(&self) -> bool {
+        matches!(
+            self,
+            ApiErrorKind::RateLimit | ApiErrorKind::Api | ApiErrorKind::Overloaded
+        )
+    }
+
+    pub fn as_str(&self) -> &str {
+        match self {
+            ApiErrorKind::InvalidRequest => "invalid_request_error",
+            ApiErrorKind::Authentication => "authentication_error",
+            ApiErrorKind::Permission => "permission_error",
+            ApiErrorKind::NotFound => "not_found_error",
+            ApiErrorKind::RequestTooLarge => "request_too_large",
+            ApiErrorKind::RateLimit => "rate_limit_error",
+            ApiErrorKind::Api => "api_error",
+            ApiErrorKind::Overloaded => "overloaded_error",
+            ApiErrorKind::Other(kind) => kind,
+        }
+    }
+}
+
+impl From<String> for ApiErrorKind {
+    fn from(kind: String) -> Self {
+        match kind.as_str() {
+            "invalid_request_error" => ApiErrorKind::InvalidRequest,
+            "authentication_error" => ApiErrorKind::Authentication,
+            "permission_error" => ApiErrorKind::Permission,
+            "not_found_error" => ApiErrorKind::NotFound,
+            "request_too_large" => ApiErrorKind::RequestTooLarge,
+            "rate_limit_error" => ApiErrorKind::RateLimit,
+            "api_error" => ApiErrorKind::Api,
+            "overloaded_error" => ApiErrorKind::Overloaded,
+            _ => ApiErrorKind::Other(kind),
+        }
+    }
+}
+
+impl std::fmt::Display for ApiErrorKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
 /// Wrapper to deserialize the error object nested in "error" JSON key
 #[derive(Debug, Deserialize)]
 pub(crate) struct WrappedError {
     pub(crate) error: ApiError,
 }
 
+/// An [`Error::Authentication`] if `status` is 401 or 403, with the API's message if the body
+/// has one.
+pub(crate) fn authentication_error(status: reqwest::StatusCode, bytes: &[u8]) -> Option<Error> {
+    if status != reqwest::StatusCode::UNAUTHORIZED && status != reqwest::StatusCode::FORBIDDEN {
+        return None;
+    }
+    let message = match serde_json::from_slice::<WrappedError>(bytes) {
+        Ok(wrapped) => wrapped.error.message,
+        Err(_) => String::from_utf8_lossy(bytes).trim().to_string(),
+    };
+    Some(Error::Authentication {
+        status: status.as_u16(),
+        message,
+    })
+}
+
+/// The error of a request that failed with `status` before anything was streamed, the API's
+/// error object if the body is one, as for a request that isn't streamed.
+pub(crate) fn status_error(status: reqwest::StatusCode, bytes: &[u8]) -> Error {
+    if let Some(e) = authentication_error(status, bytes) {
+        return e;
+    }
+    match serde_json::from_slice::<WrappedError>(bytes) {
+        Ok(wrapped) => Error::ApiError(wrapped.error),
+        Err(_) => Error::StreamError(format!(
+            "{}: {}",
+            status,
+            String::from_utf8_lossy(bytes).trim()
+        )),
+    }
+}
+
 pub(crate) fn map_deserialization_error(e: serde_json::Error, _bytes: &[u8]) -> Error {
     Error::JSONDeserialize(e)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_api_error_kind() {
+        let body = r#"{"type":"error","error":{"type":"overloaded_error","message":"Overloaded"}}"#;
+        let wrapped: WrappedError = serde_json::from_str(body).unwrap();
+        assert_eq!(wrapped.error.r#type, ApiErrorKind::Overloaded);
+        assert!(wrapped.error.r#type.is_transient());
+        assert_eq!(
+            Error::ApiError(wrapped.error).to_string(),
+            "overloaded_error: Overloaded"
+        );
+
+        let kind = ApiErrorKind::from("billing_error".to_string());
+        assert_eq!(kind, ApiErrorKind::Other("billing_error".to_string()));
+        assert!(!kind.is_transient());
+    }
+
+    #[test]
+    fn test_is_transient() {
+        let api_error = |kind: &str| {
+            Error::ApiError(ApiError {
+                message: String::new(),
+                r#type: ApiErrorKind::from(kind.to_string()),
+                param: None,
+                code: None,
+            })
+        };
+        assert!(api_error("api_error").is_transient());
+        assert!(!api_error("invalid_request_error").is_transient());
+        assert!(!Error::Authentication {
+            status: 401,
+            message: String::new()
+        }
+        .is_transient());
+        assert!(!api_error("overloaded_error").is_connection());
+    }
+}
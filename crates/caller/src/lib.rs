@@ -1,2 +1,5 @@
+pub mod circuit_breaker;
 pub mod client;
 pub mod error;
+#[cfg(any(test, feature = "test-util"))]
+pub mod test_server;
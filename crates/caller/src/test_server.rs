@@ -0,0 +1,47 @@
+//! A local HTTP server for tests that make requests.
+
+use tokio::io::{AsyncReadExt as _, AsyncWriteExt as _};
+use tokio::task::JoinHandle;
+
+/// Answer each connection with the next response, returning the server's base URL, e.g.
+/// `http://127.0.0.1:1234`, and the requests received.
+pub async fn serve(responses: Vec<String>) -> (String, JoinHandle<Vec<String>>) {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let base = format!("http://{}", listener.local_addr().unwrap());
+    let server = tokio::spawn(async move {
+        let mut requests = vec![];
+        for response in responses {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut buf = vec![0; 4096];
+            let n = socket.read(&mut buf).await.unwrap();
+            requests.push(String::from_utf8_lossy(&buf[..n]).into_owned());
+            socket.write_all(response.as_bytes()).await.unwrap();
+        }
+        requests
+    });
+    (base, server)
+}
+
+/// A response with a JSON body.
+pub fn response(status: &str, body: &str) -> String {
+    response_with_header(status, None, body)
+}
+
+pub fn response_with_header(status: &str, header: Option<&str>, body: &str) -> String {
+    let header = header.map(|h| format!("{}\r\n", h)).unwrap_or_default();
+    format!(
+        "HTTP/1.1 {}\r\ncontent-type: application/json\r\n{}content-length: {}\r\nconnection: close\r\n\r\n{}",
+        status,
+        header,
+        body.len(),
+        body
+    )
+}
+
+/// A `200 OK` streaming `body` until the connection is closed, e.g. server-sent events.
+pub fn stream_response(content_type: &str, body: &str) -> String {
+    format!(
+        "HTTP/1.1 200 OK\r\ncontent-type: {}\r\nconnection: close\r\n\r\n{}",
+        content_type, body
+    )
+}
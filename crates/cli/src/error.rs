@@ -2,4 +2,15 @@
 pub enum Error {
     #[error("Assistant error: {0}")]
     AssistantError(#[from] rgpt_assistant::error::Error),
+
+    #[error("IO error: {0}")]
+    Io(#[from] std::io::Error),
+
+    /// Flags, or config file settings, that can't be used together.
+    #[error("{0}")]
+    Usage(#[from] clap::Error),
+
+    /// `--check` failed, with the reason.
+    #[error("{0}")]
+    Check(String),
 }
@@ -1,39 +1,614 @@
 pub mod error;
 
-use clap::Parser;
+use std::collections::HashMap;
+use std::io::{IsTerminal as _, Read};
+use std::path::PathBuf;
+use std::process::ExitCode;
+
+use clap::{CommandFactory as _, Parser};
 use error::Error;
-use rgpt_assistant::{config::Config, Assistant};
+use rgpt_assistant::{
+    config::{Autosave, Config, Format, Mode},
+    error::{ApiErrorKind, Error as AssistantError},
+    template::{builtin_vars, Template},
+    Assistant,
+};
 use rgpt_types::message::Message;
 
 #[derive(Parser, Debug)]
 struct Args {
     #[clap(short, long)]
     session: bool,
-    #[clap(short, long, default_value = "general")]
-    mode: String,
+    /// `general`, `dev`, `bash` or `code`, defaulting to the config file's or else `general`.
+    // Parsed with `FromStr`, clap would otherwise prefer the lenient `From<&str>`.
+    #[clap(short, long, value_parser = str::parse::<Mode>)]
+    mode: Option<Mode>,
+    /// Config file to use instead of `config.toml` in the config dir. Flags override it.
+    #[clap(long, value_name = "PATH")]
+    config: Option<PathBuf>,
+    /// Model to answer with, instead of the provider's default. Empty means unset.
+    #[clap(long, env = "RGPT_MODEL")]
+    model: Option<String>,
+    /// Sampling temperature from 0.0 to 1.0, higher is more random. Unset uses the provider's
+    /// default.
+    #[clap(long, value_parser = parse_temperature)]
+    temperature: Option<f32>,
+    /// System prompt to use instead of the mode's.
+    #[clap(long)]
+    system: Option<String>,
+    /// Maximum number of tokens in an answer.
+    #[clap(long)]
+    max_tokens: Option<usize>,
+    /// Sample only from the most likely tokens that add up to this probability.
+    #[clap(long)]
+    top_p: Option<f32>,
+    /// Sample only from this many of the most likely tokens.
+    #[clap(long)]
+    top_k: Option<usize>,
+    /// End the answer before this text. Can be repeated.
+    #[clap(long = "stop", value_name = "SEQ", value_parser = parse_stop)]
+    stop: Vec<String>,
+    /// Use temperature 0, don't resume dropped streams, and print a hash of the request so runs
+    /// can be compared. The API doesn't guarantee identical answers even so.
+    #[clap(long)]
+    deterministic: bool,
+    /// Don't run deterministically, even if the config file says to.
+    #[clap(long, conflicts_with = "deterministic")]
+    no_deterministic: bool,
+    /// Wait for the whole answer and print it at once instead of streaming it.
+    #[clap(long)]
+    no_stream: bool,
+    /// Print the number of input and output tokens after the answer.
+    #[clap(long)]
+    usage: bool,
+    /// Don't print token usage, even if the config file turns it on.
+    #[clap(long, conflicts_with = "usage")]
+    no_usage: bool,
+    /// Print extended thinking before the answer instead of hiding it.
+    #[clap(long)]
+    show_thinking: bool,
+    /// Hide extended thinking, even if the config file shows it.
+    #[clap(long, conflicts_with = "show_thinking")]
+    no_show_thinking: bool,
+    /// Split piped stdin on this delimiter and send each part as a separate query.
+    #[clap(long)]
+    stdin_delimiter: Option<String>,
+    /// Checkpoint the session every N submitted turns.
+    #[clap(long)]
+    autosave_turns: Option<usize>,
+    /// Checkpoint the session every N minutes.
+    #[clap(long)]
+    autosave_minutes: Option<u64>,
+    /// Label shown for the assistant's output, e.g. "Reviewer".
+    #[clap(long)]
+    persona: Option<String>,
+    /// Restart the request if the stream drops mid-answer, continuing from the text received.
+    #[clap(long)]
+    resume_on_drop: bool,
+    /// Don't restart dropped streams, even if the config file says to.
+    #[clap(long, conflicts_with = "resume_on_drop")]
+    no_resume_on_drop: bool,
+    /// Log executed commands here instead of `audit.log` in the state dir.
+    #[clap(long)]
+    audit_log: Option<PathBuf>,
+    /// Execute the first command in bash mode without asking first.
+    #[clap(long)]
+    dangerous_skip_confirm: bool,
+    /// Offer to apply the diffs in the answer to files in the working directory, in code mode.
+    #[clap(long)]
+    apply: bool,
+    /// Don't offer to apply diffs, even if the config file turns it on.
+    #[clap(long, conflicts_with = "apply")]
+    no_apply: bool,
+    /// Run the command picked in bash mode without asking to confirm it.
+    #[clap(short, long)]
+    yes: bool,
+    /// Pipe the answer through this shell command before printing it, e.g. `rustfmt`.
+    #[clap(long)]
+    post_process: Option<String>,
+    /// `text` streams the answer, `json` prints it as one object with the model, stop reason
+    /// and usage once complete.
+    #[clap(long, value_parser = str::parse::<Format>)]
+    format: Option<Format>,
+    /// Also write the answer to this file, without colors.
+    #[clap(long, value_name = "PATH")]
+    output: Option<PathBuf>,
+    /// No spinner while waiting for an answer that isn't streamed.
+    #[clap(short, long)]
+    quiet: bool,
+    /// Show the spinner, even if the config file sets `quiet`.
+    #[clap(long, conflicts_with = "quiet")]
+    no_quiet: bool,
+    /// Print the answer as received, skipping `--post-process`.
+    #[clap(long)]
+    raw: bool,
+    /// Show session answers as Markdown, with code blocks highlighted.
+    #[clap(long)]
+    render_markdown: bool,
+    /// Show session answers as plain text, even if the config file renders Markdown.
+    #[clap(long, conflicts_with = "render_markdown")]
+    no_render_markdown: bool,
+    /// Show the focused pane, model and branch under a session.
+    #[clap(long)]
+    status_line: bool,
+    /// Hide the status line, even if the config file shows it.
+    #[clap(long, conflicts_with = "status_line")]
+    no_status_line: bool,
+    /// Models Ctrl-O in a session sends the prompt to side by side, comma separated.
+    #[clap(long, value_delimiter = ',')]
+    compare_models: Vec<String>,
+    /// Archive finished sessions, keeping the N most recent.
+    #[clap(long, value_name = "N")]
+    keep_sessions: Option<usize>,
+    /// Continue the session saved under this name with Ctrl-S.
+    #[clap(long, value_name = "NAME", conflicts_with_all = ["input", "template", "history"])]
+    load: Option<String>,
+    /// Pick a saved session to open or delete.
+    #[clap(long, conflicts_with_all = ["input", "template"])]
+    history: bool,
+    /// Print the number of input tokens the prompt would use, then exit without an answer.
+    #[clap(long, conflicts_with_all = ["session", "history", "load"])]
+    count_tokens: bool,
+    /// Print the request the prompt would be sent as, then exit without sending it. Needs no key.
+    #[clap(long, conflicts_with_all = ["session", "history", "load", "count_tokens"])]
+    dry_run: bool,
+    /// Check that the API is reachable and accepts the key, then exit.
+    #[clap(long)]
+    check: bool,
+    /// Ask and answer turn after turn in the terminal, keeping the conversation, without the
+    /// session's interface. `/reset` forgets the conversation, `/quit` or end of input exits.
+    #[clap(long, conflicts_with_all = ["session", "history", "load", "count_tokens", "dry_run", "input", "template"])]
+    repl: bool,
+    /// Answer a prompt sent before with the same settings from `~/.cache/rgpt`, and cache new
+    /// answers there.
+    #[clap(long)]
+    cache: bool,
+    /// Don't use the cache, even if the config file turns it on.
+    #[clap(long, conflicts_with = "cache")]
+    no_cache: bool,
+    /// Minutes a cached answer is used for. Forever by default.
+    #[clap(long, value_name = "MINUTES")]
+    cache_ttl: Option<u64>,
+    /// Log the bodies of requests and responses to `rgpt.log`, keys redacted, and nothing else.
+    /// Same as `RUST_LOG=rgpt::wire=debug`.
+    #[clap(long)]
+    log_wire: bool,
+    /// Print the models the API offers, for `--model`, then exit.
+    #[clap(long)]
+    list_models: bool,
+    /// Build the prompt from a template in the config directory's `templates/`.
+    #[clap(long, alias = "prompt-template", conflicts_with = "input")]
+    template: Option<String>,
+    /// Template variable, as `key=value`. Can be repeated.
+    #[clap(long = "var", value_parser = parse_var, requires = "template")]
+    vars: Vec<(String, String)>,
+    /// Leave unresolved template placeholders as they are instead of failing.
+    #[clap(long, requires = "template")]
+    allow_missing: bool,
 
     input: Option<String>,
 }
 
 impl Args {
     async fn execute(&self) -> Result<(), Error> {
-        let cfg = Config::builder().mode(self.mode.as_str().into()).build();
-        let messages = self
-            .input
-            .as_ref()
-            .map_or_else(Vec::new, |input| vec![Message::from(input.clone())]);
+        if self.check {
+            return Self::check().await;
+        }
+        if self.list_models {
+            return Self::list_models().await;
+        }
+        let file = Config::load(self.config.as_deref())?;
+        let compare_models = match self.compare_models.is_empty() {
+            true => file.compare_models.clone(),
+            false => self.compare_models.clone(),
+        };
+        let mut builder = Config::builder()
+            .modes(file.modes.clone())
+            .mode(self.mode.unwrap_or(file.mode))
+            .messages(file.messages.clone().unwrap_or_default())
+            .palette(file.palette)
+            .keymap(file.keymap)
+            .system_prompt(self.system.clone().or(file.system_prompt.clone()))
+            .extra_messages(file.extra_messages.clone())
+            .stream(!self.no_stream && file.stream)
+            .show_thinking(flag(
+                self.show_thinking,
+                self.no_show_thinking,
+                file.show_thinking,
+            ))
+            .show_usage(flag(self.usage, self.no_usage, file.show_usage))
+            .temperature(self.temperature.or(file.temperature))
+            .max_tokens(self.max_tokens.or(file.max_tokens))
+            .top_p(self.top_p.or(file.top_p))
+            .top_k(self.top_k.or(file.top_k))
+            .stop_sequences(match self.stop.is_empty() {
+                true => file.stop_sequences.clone(),
+                false => Some(self.stop.clone()),
+            })
+            .deterministic(flag(
+                self.deterministic,
+                self.no_deterministic,
+                file.deterministic,
+            ))
+            .autosave(self.autosave().or(file.autosave))
+            .persona_label(self.persona.clone().or(file.persona_label.clone()))
+            .resume_on_drop(flag(
+                self.resume_on_drop,
+                self.no_resume_on_drop,
+                file.resume_on_drop,
+            ))
+            .audit_log(self.audit_log.clone().or(file.audit_log.clone()))
+            .skip_confirm(self.dangerous_skip_confirm)
+            .confirm_execute(!self.yes && file.confirm_execute)
+            .apply(flag(self.apply, self.no_apply, file.apply))
+            .post_process(
+                self.post_process
+                    .clone()
+                    .or(file.post_process.clone())
+                    .filter(|_| !self.raw),
+            )
+            .output(self.output.clone().or(file.output.clone()))
+            .format(self.format.unwrap_or(file.format))
+            .retry_attempts(file.retry_attempts)
+            .tools(file.tools.clone())
+            .max_tool_rounds(file.max_tool_rounds)
+            .quiet(flag(self.quiet, self.no_quiet, file.quiet))
+            .render_markdown(flag(
+                self.render_markdown,
+                self.no_render_markdown,
+                file.render_markdown,
+            ))
+            .status_line(flag(
+                self.status_line,
+                self.no_status_line,
+                file.status_line,
+            ))
+            .compare_models(compare_models)
+            .keep_sessions(self.keep_sessions.or(file.keep_sessions))
+            .cache(flag(self.cache, self.no_cache, file.cache))
+            .cache_ttl_minutes(self.cache_ttl.or(file.cache_ttl_minutes));
+        if let Some(model) = self.model().or(file.model) {
+            builder = builder.model(model);
+        }
+        let prompts = match &self.template {
+            Some(name) => {
+                let (system, user) = self.render_template(name)?;
+                if let Some(system) = system {
+                    builder = builder.append_system(system.content);
+                }
+                vec![user.content]
+            }
+            // The repl reads its prompts from stdin as they are typed
+            None if self.repl => vec![],
+            None => self.prompts()?,
+        };
+        let cfg = builder.build();
+        Self::check_conflicts(&cfg)?;
+        if self.dry_run {
+            // Each prompt is a query of its own
+            let queries = match prompts.as_slice() {
+                [] => vec![vec![]],
+                prompts => prompts
+                    .iter()
+                    .map(|prompt| vec![Message::from(prompt.clone())])
+                    .collect(),
+            };
+            for messages in queries {
+                println!("{}", Assistant::dry_run(&cfg, &messages)?);
+            }
+            return Ok(());
+        }
         tracing::debug!("Starting assistant with config: {:?}", cfg);
         let assistant = Assistant::new(cfg)?;
-        match self.session {
-            true => assistant.session(&messages).await?,
-            false => assistant.query(&messages).await?,
+        if self.history {
+            return Ok(assistant.history().await?);
+        }
+        if let Some(name) = &self.load {
+            return Ok(assistant.load_session(name).await?);
+        }
+        if self.count_tokens {
+            let messages = prompts
+                .iter()
+                .map(|prompt| Message::from(prompt.clone()))
+                .collect::<Vec<_>>();
+            println!("{}", assistant.count_tokens(&messages).await?);
+            return Ok(());
+        }
+        if self.repl {
+            return Ok(assistant.repl().await?);
+        }
+        match (self.session, prompts.as_slice()) {
+            (true, prompts) => {
+                let messages = prompts
+                    .first()
+                    .map_or_else(Vec::new, |prompt| vec![Message::from(prompt.clone())]);
+                assistant.session(&messages).await?
+            }
+            (false, []) => assistant.query(&[]).await?,
+            (false, [prompt]) => assistant.query(&[Message::from(prompt.clone())]).await?,
+            (false, prompts) => {
+                let messages = prompts
+                    .iter()
+                    .map(|prompt| Message::from(prompt.clone()))
+                    .collect::<Vec<_>>();
+                let delimiter = self.stdin_delimiter.as_deref().unwrap_or_default();
+                assistant.query_each(&messages, delimiter).await?
+            }
         }
         tracing::info!("Assistant finished");
         Ok(())
     }
+
+    /// Print whether the key works, exiting non-zero if it doesn't.
+    async fn check() -> Result<(), Error> {
+        let status = match Assistant::new(Config::default()) {
+            Ok(assistant) => assistant.health_check().await,
+            Err(e) => Err(e),
+        };
+        match status {
+            Ok(()) => {
+                println!("ok: API reachable and key accepted");
+                Ok(())
+            }
+            Err(e) => {
+                let reason = match &e {
+                    AssistantError::NoApiKey(_) => {
+                        "no API key found, set ANTHROPIC_API_KEY or OPENAI_API_KEY, add one to credentials.toml, or set RGPT_PROVIDER=ollama"
+                    }
+                    AssistantError::Authentication { .. } => "authentication failed",
+                    AssistantError::Provider(e) => match e.api_error_kind() {
+                        Some(ApiErrorKind::Authentication | ApiErrorKind::Permission) => {
+                            "authentication failed"
+                        }
+                        Some(_) => "API error",
+                        None => "connection failed",
+                    },
+                    _ => "check failed",
+                };
+                Err(Error::Check(format!("{}: {}", reason, e)))
+            }
+        }
+    }
+
+    /// One model a line: its id, name and creation date.
+    async fn list_models() -> Result<(), Error> {
+        let models = Assistant::new(Config::default())?.list_models().await?;
+        let id_width = models.iter().map(|model| model.id.len()).max();
+        let name_width = models.iter().map(|model| model.display_name.len()).max();
+        for model in &models {
+            println!(
+                "{:<id_width$}  {:<name_width$}  {}",
+                model.id,
+                model.display_name,
+                model.created_at.as_deref().unwrap_or(""),
+                id_width = id_width.unwrap_or(0),
+                name_width = name_width.unwrap_or(0),
+            );
+        }
+        Ok(())
+    }
+
+    /// `--model` or `RGPT_MODEL`, unless empty.
+    fn model(&self) -> Option<String> {
+        self.model.clone().filter(|model| !model.is_empty())
+    }
+
+    fn autosave(&self) -> Option<Autosave> {
+        match (self.autosave_turns, self.autosave_minutes) {
+            (None, None) => None,
+            (every_turns, every_minutes) => Some(Autosave {
+                every_turns,
+                every_minutes,
+            }),
+        }
+    }
+
+    /// Everything piped to stdin, if it isn't a terminal.
+    fn read_stdin() -> Result<Option<String>, Error> {
+        if std::io::stdin().is_terminal() {
+            return Ok(None);
+        }
+        read_piped(std::io::stdin()).map(Some)
+    }
+
+    /// The positional input, or else whatever was piped to stdin.
+    fn prompts(&self) -> Result<Vec<String>, Error> {
+        if let Some(input) = &self.input {
+            return Ok(vec![input.clone()]);
+        }
+        Ok(Self::read_stdin()?.map_or_else(Vec::new, |stdin| {
+            split_prompts(&stdin, self.stdin_delimiter.as_deref())
+        }))
+    }
+
+    /// Render a template with the builtin variables, overridden by `--var`s.
+    fn render_template(&self, name: &str) -> Result<(Option<Message>, Message), Error> {
+        let mut vars = builtin_vars(Self::read_stdin()?);
+        vars.extend(self.vars.iter().cloned().collect::<HashMap<_, _>>());
+        Ok(Template::load(name)?.render(&vars, self.allow_missing)?)
+    }
+
+    /// The json format prints the answer as it is, so it can't be written to a file or piped
+    /// through a command. Checked once flags and config file are merged, as either can set them.
+    fn check_conflicts(cfg: &Config) -> Result<(), clap::Error> {
+        if cfg.format != Format::Json {
+            return Ok(());
+        }
+        let other = match (&cfg.output, &cfg.post_process) {
+            (Some(_), _) => "an output file",
+            (_, Some(_)) => "a post-process command",
+            (None, None) => return Ok(()),
+        };
+        Err(Self::command().error(
+            clap::error::ErrorKind::ArgumentConflict,
+            format!("the json format can't be used with {}", other),
+        ))
+    }
+}
+
+fn parse_var(var: &str) -> Result<(String, String), String> {
+    var.split_once('=')
+        .map(|(key, value)| (key.trim().to_string(), value.to_string()))
+        .ok_or_else(|| format!("expected key=value, got '{}'", var))
+}
+
+fn parse_temperature(temperature: &str) -> Result<f32, String> {
+    let temperature: f32 = temperature.parse().map_err(|e| format!("{}", e))?;
+    match (0.0..=1.0).contains(&temperature) {
+        true => Ok(temperature),
+        false => Err(format!("must be between 0.0 and 1.0, got {}", temperature)),
+    }
+}
+
+fn parse_stop(stop: &str) -> Result<String, String> {
+    match stop.is_empty() {
+        true => Err("stop sequences can't be empty".to_string()),
+        false => Ok(stop.to_string()),
+    }
+}
+
+/// A boolean set by `--x`/`--no-x`, falling back to the config file's when neither is given.
+fn flag(on: bool, off: bool, file: bool) -> bool {
+    match (on, off) {
+        (true, _) => true,
+        (_, true) => false,
+        _ => file,
+    }
+}
+
+/// Read `input` to EOF.
+fn read_piped(mut input: impl Read) -> Result<String, Error> {
+    let mut piped = String::new();
+    input.read_to_string(&mut piped)?;
+    Ok(piped)
+}
+
+fn split_prompts(input: &str, delimiter: Option<&str>) -> Vec<String> {
+    let prompts = match delimiter {
+        Some(delimiter) => input.split(delimiter).collect(),
+        None => vec![input],
+    };
+    prompts
+        .into_iter()
+        .map(str::trim)
+        .filter(|prompt| !prompt.is_empty())
+        .map(String::from)
+        .collect()
 }
 
 #[tokio::main]
-async fn main() -> Result<(), Error> {
-    Args::parse().execute().await
+async fn main() -> Result<ExitCode, Error> {
+    let args = Args::parse();
+    if args.log_wire || std::env::var_os("RUST_LOG").is_some() {
+        // Not worth failing the query over
+        if let Err(e) = rgpt_utils::logging::init_logger(None, args.log_wire) {
+            eprintln!("not logging, can't create rgpt.log: {}", e);
+        }
+    }
+    // Returned rather than exiting, so everything is dropped first
+    match args.execute().await {
+        Err(Error::AssistantError(e @ AssistantError::Authentication { .. })) => {
+            eprintln!("{}", e);
+            Ok(ExitCode::FAILURE)
+        }
+        Err(Error::Usage(e)) => {
+            let _ = e.print();
+            Ok(ExitCode::from(e.exit_code() as u8))
+        }
+        Err(e @ Error::Check(_)) => {
+            eprintln!("{}", e);
+            Ok(ExitCode::FAILURE)
+        }
+        result => result.map(|()| ExitCode::SUCCESS),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_empty_model_unset() {
+        let args = Args::parse_from(["rgpt", "--model", "", "hi"]);
+        assert_eq!(args.model(), None);
+        let args = Args::parse_from(["rgpt", "--model", "claude-3-5-haiku-latest", "hi"]);
+        assert_eq!(args.model().as_deref(), Some("claude-3-5-haiku-latest"));
+    }
+
+    #[test]
+    fn test_temperature_range() {
+        let args = Args::try_parse_from(["rgpt", "--temperature", "0.7", "hi"]).unwrap();
+        assert_eq!(args.temperature, Some(0.7));
+        assert!(Args::try_parse_from(["rgpt", "--temperature", "1.5", "hi"]).is_err());
+        assert!(Args::try_parse_from(["rgpt", "--temperature", "-0.1", "hi"]).is_err());
+    }
+
+    #[test]
+    fn test_stop_sequences() {
+        let args = Args::try_parse_from(["rgpt", "--stop", "```", "--stop", "\n\n", "hi"]).unwrap();
+        assert_eq!(args.stop, vec!["```", "\n\n"]);
+        assert!(Args::try_parse_from(["rgpt", "--stop", "", "hi"]).is_err());
+    }
+
+    #[test]
+    fn test_piped_prompt() {
+        let stdin = read_piped("explain this\n  ".as_bytes()).unwrap();
+        let messages = split_prompts(&stdin, None)
+            .into_iter()
+            .map(Message::from)
+            .collect::<Vec<_>>();
+        assert_eq!(messages, vec![Message::from("explain this".to_string())]);
+    }
+
+    #[test]
+    fn test_unknown_mode() {
+        assert_eq!(
+            Args::try_parse_from(["rgpt", "--mode", "bash", "hi"])
+                .unwrap()
+                .mode,
+            Some(Mode::Bash)
+        );
+        let error = Args::try_parse_from(["rgpt", "--mode", "bsh", "hi"]).unwrap_err();
+        assert_eq!(error.kind(), clap::error::ErrorKind::ValueValidation);
+    }
+
+    #[test]
+    fn test_json_conflicts() {
+        let check = |format, output: Option<&str>, post_process: Option<&str>| {
+            Args::check_conflicts(
+                &Config::builder()
+                    .format(format)
+                    .output(output.map(PathBuf::from))
+                    .post_process(post_process.map(String::from))
+                    .build(),
+            )
+        };
+        let error = check(Format::Json, Some("out.json"), None).unwrap_err();
+        assert_eq!(error.kind(), clap::error::ErrorKind::ArgumentConflict);
+        assert!(check(Format::Json, None, Some("jq .")).is_err());
+        assert!(check(Format::Text, Some("out.txt"), None).is_ok());
+        assert!(check(Format::Json, None, None).is_ok());
+    }
+
+    #[test]
+    fn test_no_flags() {
+        let args = Args::try_parse_from(["rgpt", "--no-quiet", "--no-usage", "hi"]).unwrap();
+        assert!(!flag(args.quiet, args.no_quiet, true));
+        assert!(!flag(args.usage, args.no_usage, true));
+        assert!(flag(args.show_thinking, args.no_show_thinking, true));
+        assert!(Args::try_parse_from(["rgpt", "--quiet", "--no-quiet", "hi"]).is_err());
+    }
+
+    #[test]
+    fn test_split_prompts() {
+        let input = "list files\n---\nshow disk usage\n---\n";
+        assert_eq!(
+            split_prompts(input, Some("---")),
+            vec!["list files", "show disk usage"]
+        );
+        assert_eq!(
+            split_prompts(input, None),
+            vec!["list files\n---\nshow disk usage\n---"]
+        );
+    }
 }
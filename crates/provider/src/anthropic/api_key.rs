@@ -9,7 +9,7 @@ impl From<String> for ApiKey {
 }
 
 impl ApiKey {
-    const API_KEY_ENV_VAR: &'static str = "ANTHROPIC_API_KEY";
+    pub const API_KEY_ENV_VAR: &'static str = "ANTHROPIC_API_KEY";
     pub fn get() -> Option<Self> {
         get().map(Self::from)
     }
@@ -21,6 +21,15 @@ impl From<ApiKey> for String {
     }
 }
 
+/// Base url to send requests to instead of the Anthropic API, if set.
+pub fn api_base() -> Option<String> {
+    std::env::var("ANTHROPIC_BASE_URL")
+        .ok()
+        .filter(|base| !base.is_empty())
+}
+
 pub fn get() -> Option<String> {
-    std::env::var(ApiKey::API_KEY_ENV_VAR).ok()
+    std::env::var(ApiKey::API_KEY_ENV_VAR)
+        .ok()
+        .map(|key| key.trim().to_string())
 }
@@ -1,5 +1,5 @@
 //! Definition of errors used in the library.
-use serde::Deserialize;
+pub use rgpt_caller::error::{ApiError, ApiErrorKind};
 
 #[derive(Debug, thiserror::Error)]
 pub enum Error {
@@ -27,11 +27,30 @@ pub enum Error {
     Caller(#[from] rgpt_caller::error::Error),
 }
 
-/// Anthropic API returns error object on failure
-#[derive(Debug, Deserialize)]
-pub struct ApiError {
-    pub message: String,
-    pub r#type: String,
-    pub param: Option<serde_json::Value>,
-    pub code: Option<serde_json::Value>,
+impl Error {
+    /// The kind of error the API reported, if this is an API error.
+    pub fn api_error_kind(&self) -> Option<&ApiErrorKind> {
+        match self {
+            Error::Api(e) => Some(&e.r#type),
+            Error::Caller(e) => e.api_error_kind(),
+            _ => None,
+        }
+    }
+
+    /// Whether sending the request again may succeed.
+    pub fn is_transient(&self) -> bool {
+        match self {
+            Error::Api(e) => e.r#type.is_transient(),
+            Error::Caller(e) => e.is_transient(),
+            _ => false,
+        }
+    }
+
+    /// Whether the request got no answer, which the caller doesn't retry.
+    pub fn is_connection(&self) -> bool {
+        match self {
+            Error::Caller(e) => e.is_connection(),
+            _ => false,
+        }
+    }
 }
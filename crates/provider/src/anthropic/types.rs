@@ -1,6 +1,7 @@
-use rgpt_types::completion::{Request, TextEvent};
+use rgpt_types::completion::{Request, TextEvent, Tool};
 use serde::{Deserialize, Serialize};
 
+use crate::anthropic::error::Error;
 use crate::anthropic::DEFAULT_MODEL;
 
 use super::DEFAULT_MAX_TOKENS;
@@ -11,6 +12,7 @@ pub enum StopReason {
     MaxTokens,
     StopSequence,
     EndTurn,
+    ToolUse,
 }
 
 impl From<StopReason> for rgpt_types::completion::StopReason {
@@ -19,6 +21,7 @@ impl From<StopReason> for rgpt_types::completion::StopReason {
             StopReason::MaxTokens => Self::MaxTokens,
             StopReason::StopSequence => Self::StopSequence,
             StopReason::EndTurn => Self::EndTurn,
+            StopReason::ToolUse => Self::ToolUse,
         }
     }
 }
@@ -98,18 +101,120 @@ impl From<rgpt_types::message::Role> for Role {
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct Message {
     pub role: Role,
-    pub content: String,
+    pub content: MessageContent,
 }
 
-impl From<rgpt_types::message::Message> for Message {
-    fn from(message: rgpt_types::message::Message) -> Self {
+/// Plain text, or blocks when the message has images or tool calls.
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
+#[serde(untagged)]
+pub enum MessageContent {
+    Text(String),
+    Blocks(Vec<MessageBlock>),
+}
+
+impl From<&str> for MessageContent {
+    fn from(text: &str) -> Self {
+        Self::Text(text.to_string())
+    }
+}
+
+impl From<String> for MessageContent {
+    fn from(text: String) -> Self {
+        Self::Text(text)
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+#[serde(tag = "type")]
+pub enum MessageBlock {
+    Text { text: String },
+    Image { source: ImageSource },
+    ToolUse {
+        id: String,
+        name: String,
+        input: serde_json::Value,
+    },
+    ToolResult {
+        tool_use_id: String,
+        content: String,
+        #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+        is_error: bool,
+    },
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
+pub struct ImageSource {
+    /// Always `base64`.
+    #[serde(rename = "type")]
+    pub type_: String,
+    pub media_type: String,
+    pub data: String,
+}
+
+impl From<rgpt_types::message::Image> for MessageBlock {
+    fn from(image: rgpt_types::message::Image) -> Self {
+        Self::Image {
+            source: ImageSource {
+                type_: "base64".to_string(),
+                media_type: image.media_type,
+                data: image.data,
+            },
+        }
+    }
+}
+
+impl From<rgpt_types::message::RichMessage> for Message {
+    fn from(message: rgpt_types::message::RichMessage) -> Self {
+        let rgpt_types::message::Message { role, content } = message.message;
+        if message.images.is_empty()
+            && message.tool_uses.is_empty()
+            && message.tool_results.is_empty()
+        {
+            return Self {
+                role: role.into(),
+                content: MessageContent::Text(content),
+            };
+        }
+        // Tool results must come first, and images go before the text that asks about them
+        let text = Some(content)
+            .filter(|text| !text.is_empty())
+            .map(|text| MessageBlock::Text { text });
+        let tool_results = message.tool_results.into_iter().map(|result| MessageBlock::ToolResult {
+            tool_use_id: result.tool_use_id,
+            content: result.content,
+            is_error: result.is_error,
+        });
+        let tool_uses = message.tool_uses.into_iter().map(|tool_use| MessageBlock::ToolUse {
+            id: tool_use.id,
+            name: tool_use.name,
+            input: tool_use.input,
+        });
         Self {
-            role: message.role.into(),
-            content: message.content,
+            role: role.into(),
+            content: MessageContent::Blocks(
+                tool_results
+                    .chain(message.images.into_iter().map(MessageBlock::from))
+                    .chain(text)
+                    .chain(tool_uses)
+                    .collect(),
+            ),
         }
     }
 }
 
+impl From<rgpt_types::message::Message> for Message {
+    fn from(message: rgpt_types::message::Message) -> Self {
+        rgpt_types::message::RichMessage::from(message).into()
+    }
+}
+
+impl From<&rgpt_types::message::Message> for Message {
+    fn from(message: &rgpt_types::message::Message) -> Self {
+        message.clone().into()
+    }
+}
+
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct MessagesRequest {
     pub messages: Vec<Message>,
@@ -122,8 +227,15 @@ pub struct MessagesRequest {
     pub system: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub temperature: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub top_p: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub top_k: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tools: Option<Vec<Tool>>,
 }
 
+/// The system prompt is `Request::system` if set, otherwise the last system message.
 impl From<Request> for MessagesRequest {
     fn from(val: Request) -> Self {
         let (system, messages) =
@@ -131,7 +243,7 @@ impl From<Request> for MessagesRequest {
                 .into_iter()
                 .fold((None, vec![]), |(system, mut messages), message| {
                     if message.role == rgpt_types::message::Role::System {
-                        (Some(message.content), messages)
+                        (Some(message.message.content), messages)
                     } else {
                         messages.push(message.into());
                         (system, messages)
@@ -143,9 +255,40 @@ impl From<Request> for MessagesRequest {
             max_tokens: val.max_tokens,
             stop_sequences: val.stop_sequences,
             stream: val.stream,
-            system,
+            system: val.system.or(system),
             temperature: val.temperature,
+            top_p: val.top_p,
+            top_k: val.top_k,
+            tools: val.tools,
+        }
+    }
+}
+
+impl MessagesRequest {
+    /// Client side checks for requests the API would reject.
+    pub fn validate(&self) -> Result<(), Error> {
+        if self.messages.is_empty() {
+            return Err(Error::InvalidArgument(
+                "messages must contain at least one message".into(),
+            ));
+        }
+        // The API only takes the system prompt in `system`
+        if self.messages.iter().any(|message| message.role == Role::System) {
+            return Err(Error::InvalidArgument(
+                "system messages go in system, not messages".into(),
+            ));
         }
+        if self
+            .stop_sequences
+            .iter()
+            .flatten()
+            .any(|stop| stop.is_empty())
+        {
+            return Err(Error::InvalidArgument(
+                "stop sequences can't be empty".into(),
+            ));
+        }
+        Ok(())
     }
 }
 
@@ -159,10 +302,40 @@ impl Default for MessagesRequest {
             stream: false,
             system: None,
             temperature: None,
+            top_p: None,
+            top_k: None,
+            tools: None,
+        }
+    }
+}
+
+/// Body of `POST /v1/messages/count_tokens`, a messages request without the sampling options.
+#[derive(Debug, Clone, Serialize)]
+pub struct CountTokensRequest {
+    pub messages: Vec<Message>,
+    pub model: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub system: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tools: Option<Vec<Tool>>,
+}
+
+impl From<MessagesRequest> for CountTokensRequest {
+    fn from(val: MessagesRequest) -> Self {
+        CountTokensRequest {
+            messages: val.messages,
+            model: val.model,
+            system: val.system,
+            tools: val.tools,
         }
     }
 }
 
+#[derive(Debug, Clone, Deserialize)]
+pub struct CountTokensResponse {
+    pub input_tokens: usize,
+}
+
 #[derive(Debug, Clone, Deserialize, Serialize)]
 #[serde(rename_all = "snake_case")]
 #[serde(tag = "type")]
@@ -170,14 +343,26 @@ pub enum Content {
     Text{
         text: String,
     },
-    Other,
+    Thinking {
+        thinking: String,
+    },
+    ToolUse {
+        id: String,
+        name: String,
+        input: serde_json::Value,
+    },
+    /// E.g. a server tool's result, which is passed on as it is.
+    #[serde(untagged, deserialize_with = "rgpt_types::completion::unknown_block")]
+    Other(serde_json::Value),
 }
 
 impl From<Content> for rgpt_types::completion::Content {
     fn from(content: Content) -> Self {
         match content {
             Content::Text{text} => Self::Text{text},
-            Content::Other => Self::Other,
+            Content::Thinking { thinking } => Self::Thinking { thinking },
+            Content::ToolUse { id, name, input } => Self::ToolUse { id, name, input },
+            Content::Other(block) => Self::Other(block),
         }
     }
 }
@@ -256,6 +441,8 @@ pub enum MessagesEvent {
     },
     MessageDelta {
         delta: MessageDelta,
+        #[serde(default)]
+        usage: Option<DeltaUsage>,
     },
     MessageStop,
 }
@@ -280,18 +467,35 @@ pub struct MessageDelta {
     pub stop_sequence: Option<String>,
 }
 
+#[derive(Debug, Deserialize, Clone, Serialize)]
+pub struct DeltaUsage {
+    pub output_tokens: usize,
+}
+
 #[derive(Debug, Deserialize, Clone, Serialize)]
 #[serde(rename_all = "snake_case")]
 #[serde(tag = "type")]
 pub enum ContentBlock {
     Text { text: String },
+    Thinking { thinking: String },
+    ToolUse {
+        id: String,
+        name: String,
+        input: serde_json::Value,
+    },
+    #[serde(untagged, deserialize_with = "rgpt_types::completion::unknown_block")]
+    Other(serde_json::Value),
 }
 
 #[derive(Debug, Deserialize, Clone, Serialize)]
 #[serde(rename_all = "snake_case")]
 #[serde(tag = "type")]
+#[allow(clippy::enum_variant_names)]
 pub enum Delta {
     TextDelta { text: String },
+    ThinkingDelta { thinking: String },
+    SignatureDelta { signature: String },
+    InputJsonDelta { partial_json: String },
 }
 
 impl From<MessagesEvent> for TextEvent {
@@ -314,8 +518,9 @@ impl From<MessagesEvent> for TextEvent {
                 index,
                 delta: delta.into(),
             },
-            MessagesEvent::MessageDelta { delta } => TextEvent::MessageDelta {
+            MessagesEvent::MessageDelta { delta, usage } => TextEvent::MessageDelta {
                 delta: delta.into(),
+                usage: usage.map(Into::into),
             },
             MessagesEvent::MessageStop => TextEvent::MessageStop,
         }
@@ -326,6 +531,14 @@ impl From<ContentBlock> for rgpt_types::completion::ContentBlock {
     fn from(content_block: ContentBlock) -> Self {
         match content_block {
             ContentBlock::Text { text } => Self::Text { text },
+            ContentBlock::Thinking { thinking } => Self::Thinking { thinking },
+            ContentBlock::ToolUse { id, name, input } => Self::ToolUse {
+                id,
+                name,
+                input,
+                partial_json: String::new(),
+            },
+            ContentBlock::Other(block) => Self::Other(block),
         }
     }
 }
@@ -334,6 +547,9 @@ impl From<Delta> for rgpt_types::completion::ContentDelta {
     fn from(delta: Delta) -> Self {
         match delta {
             Delta::TextDelta { text } => Self::TextDelta { text },
+            Delta::ThinkingDelta { thinking } => Self::ThinkingDelta { thinking },
+            Delta::SignatureDelta { signature } => Self::SignatureDelta { signature },
+            Delta::InputJsonDelta { partial_json } => Self::InputJsonDelta { partial_json },
         }
     }
 }
@@ -369,3 +585,326 @@ impl From<MessageStartData> for rgpt_types::completion::MessageStartData {
         }
     }
 }
+
+impl From<DeltaUsage> for rgpt_types::completion::DeltaUsage {
+    fn from(usage: DeltaUsage) -> Self {
+        Self {
+            output_tokens: usage.output_tokens,
+        }
+    }
+}
+
+/// A page of `GET /v1/models`, newest first.
+#[derive(Debug, Clone, Deserialize)]
+pub struct ModelsResponse {
+    pub data: Vec<Model>,
+    pub has_more: bool,
+    pub last_id: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Model {
+    pub id: String,
+    pub display_name: String,
+    /// RFC 3339
+    pub created_at: String,
+}
+
+impl From<Model> for crate::ModelInfo {
+    fn from(model: Model) -> Self {
+        Self {
+            id: model.id,
+            display_name: model.display_name,
+            created_at: Some(model.created_at),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rgpt_types::message::{Message, RichMessage, Role};
+    use serde_json::json;
+
+    use super::*;
+
+    fn request_json(request: Request) -> serde_json::Value {
+        serde_json::to_value(MessagesRequest::from(request)).unwrap()
+    }
+
+    #[test]
+    fn test_system_extraction() {
+        let request = Request::builder()
+            .messages(vec![
+                Message::system("You are a bartender."),
+                Message::user("A human walks into a bar"),
+            ])
+            .build();
+        let json = request_json(request);
+        assert_eq!(json["system"], "You are a bartender.");
+        assert_eq!(
+            json["messages"],
+            json!([{ "role": "user", "content": "A human walks into a bar" }])
+        );
+    }
+
+    #[test]
+    fn test_message_roles() {
+        let message = super::Message::from(&Message::user("A human walks into a bar"));
+        assert_eq!(
+            serde_json::to_value(message).unwrap(),
+            json!({ "role": "user", "content": "A human walks into a bar" })
+        );
+        let message = super::Message::from(&Message::assistant("Ouch"));
+        assert_eq!(serde_json::to_value(message).unwrap()["role"], "assistant");
+
+        let mut request = MessagesRequest::from(
+            Request::builder()
+                .messages(vec![
+                    Message::system("You are a bartender."),
+                    Message::user("A human walks into a bar"),
+                ])
+                .build(),
+        );
+        assert!(request
+            .messages
+            .iter()
+            .all(|message| message.role != super::Role::System));
+        assert!(request.validate().is_ok());
+        request
+            .messages
+            .push(super::Message::from(&Message::system("You are a bouncer.")));
+        assert!(request.validate().is_err());
+    }
+
+    #[test]
+    fn test_explicit_system_wins() {
+        let request = Request::builder()
+            .messages(vec![
+                Message::system("You are a bartender."),
+                Message::user("A human walks into a bar"),
+            ])
+            .system("You are a bouncer.".to_string())
+            .build();
+        let json = request_json(request);
+        assert_eq!(json["system"], "You are a bouncer.");
+        assert_eq!(json["messages"].as_array().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_optional_fields_omitted() {
+        let request = Request::builder()
+            .messages(vec![Message::user("A human walks into a bar")])
+            .build();
+        let json = request_json(request);
+        let object = json.as_object().unwrap();
+        assert!(!object.contains_key("temperature"));
+        assert!(!object.contains_key("stop_sequences"));
+        assert!(!object.contains_key("system"));
+        assert!(!object.contains_key("top_p"));
+        assert!(!object.contains_key("top_k"));
+        assert_eq!(json["model"], DEFAULT_MODEL);
+        assert_eq!(json["stream"], false);
+    }
+
+    #[test]
+    fn test_optional_fields_included() {
+        let request = Request::builder()
+            .messages(vec![Message::user("A human walks into a bar")])
+            .temperature(Some(0.5))
+            .stop_sequences(Some(vec!["\n\nHuman:".to_string()]))
+            .top_p(Some(0.25))
+            .top_k(Some(40))
+            .build();
+        let json = request_json(request);
+        assert_eq!(json["temperature"], 0.5);
+        assert_eq!(json["top_p"], 0.25);
+        assert_eq!(json["top_k"], 40);
+        assert_eq!(json["stop_sequences"], json!(["\n\nHuman:"]));
+    }
+
+    #[test]
+    fn test_image_content() {
+        let mut message =
+            Message::with_image(Role::User, "image/png".to_string(), "iVBORw0KGgo=".to_string());
+        message.content = "What is this?".to_string();
+        let request = Request::builder()
+            .messages(vec![message, Message::user("A human walks into a bar").into()])
+            .build();
+        let json = request_json(request);
+        assert_eq!(
+            json["messages"][0]["content"],
+            json!([
+                {
+                    "type": "image",
+                    "source": { "type": "base64", "media_type": "image/png", "data": "iVBORw0KGgo=" }
+                },
+                { "type": "text", "text": "What is this?" }
+            ])
+        );
+        assert_eq!(json["messages"][1]["content"], "A human walks into a bar");
+    }
+
+    #[test]
+    fn test_max_tokens() {
+        let request = Request::builder()
+            .messages(vec![Message::user("A human walks into a bar")])
+            .build();
+        assert_eq!(request_json(request)["max_tokens"], DEFAULT_MAX_TOKENS);
+
+        let request = Request::builder()
+            .messages(vec![Message::user("A human walks into a bar")])
+            .max_tokens(16384)
+            .build();
+        assert_eq!(request_json(request)["max_tokens"], 16384);
+    }
+
+    #[test]
+    fn test_tool_use() {
+        let tool = Tool {
+            name: "get_weather".to_string(),
+            description: Some("Current weather in a city".to_string()),
+            input_schema: json!({"type": "object", "properties": {"city": {"type": "string"}}}),
+        };
+        let request = Request::builder()
+            .messages(vec![Message::user("Weather in Paris?")])
+            .tools(Some(vec![tool]))
+            .build();
+        let json = request_json(request);
+        assert_eq!(json["tools"][0]["name"], "get_weather");
+        assert_eq!(json["tools"][0]["input_schema"]["type"], "object");
+
+        let start: MessagesEvent = serde_json::from_str(
+            r#"{"type":"content_block_start","index":1,"content_block":{"type":"tool_use","id":"toolu_01","name":"get_weather","input":{}}}"#,
+        )
+        .unwrap();
+        let TextEvent::ContentBlockStart {
+            mut content_block, ..
+        } = start.into()
+        else {
+            panic!("expected a content block start");
+        };
+        for partial_json in [r#"{"city": "#, r#""Paris"}"#] {
+            let delta: MessagesEvent = serde_json::from_str(
+                &json!({
+                    "type": "content_block_delta",
+                    "index": 1,
+                    "delta": {"type": "input_json_delta", "partial_json": partial_json}
+                })
+                .to_string(),
+            )
+            .unwrap();
+            let TextEvent::ContentBlockDelta { delta, .. } = delta.into() else {
+                panic!("expected a content block delta");
+            };
+            content_block.update(&delta);
+        }
+        content_block.finish();
+        assert!(matches!(
+            content_block,
+            rgpt_types::completion::ContentBlock::ToolUse { input, .. }
+                if input == json!({"city": "Paris"})
+        ));
+    }
+
+    #[test]
+    fn test_tool_round_trip() {
+        let response: MessagesResponse = serde_json::from_value(json!({
+            "id": "msg_01",
+            "type": "message",
+            "role": "assistant",
+            "model": "claude-sonnet-4-5",
+            "content": [
+                {"type": "text", "text": "Let me check."},
+                {"type": "tool_use", "id": "toolu_01", "name": "get_weather", "input": {"city": "Paris"}}
+            ],
+            "stop_reason": "tool_use",
+            "stop_sequence": null,
+            "usage": {"input_tokens": 10, "output_tokens": 20}
+        }))
+        .unwrap();
+        let answer = RichMessage::from(rgpt_types::completion::Response::from(response));
+        assert_eq!(answer.role, Role::Assistant);
+        assert_eq!(answer.content, "Let me check.");
+        assert_eq!(answer.tool_uses[0].name, "get_weather");
+
+        let result = Message::with_tool_results(vec![rgpt_types::message::ToolResult {
+            tool_use_id: "toolu_01".to_string(),
+            content: "Sunny".to_string(),
+            is_error: false,
+        }]);
+        let request = Request::builder()
+            .messages(vec![Message::user("Weather in Paris?").into(), answer, result])
+            .build();
+        let json = request_json(request);
+        assert_eq!(
+            json["messages"][1]["content"],
+            json!([
+                { "type": "text", "text": "Let me check." },
+                { "type": "tool_use", "id": "toolu_01", "name": "get_weather", "input": {"city": "Paris"} }
+            ])
+        );
+        assert_eq!(
+            json["messages"][2]["content"],
+            json!([{ "type": "tool_result", "tool_use_id": "toolu_01", "content": "Sunny" }])
+        );
+    }
+
+    #[test]
+    fn test_unsupported_content() {
+        let response: MessagesResponse = serde_json::from_value(json!({
+            "id": "msg_1",
+            "type": "message",
+            "role": "assistant",
+            "model": "claude",
+            "content": [
+                { "type": "text", "text": "Searching." },
+                { "type": "server_tool_use", "id": "srvtoolu_1", "name": "web_search", "input": {} },
+            ],
+            "stop_reason": "end_turn",
+            "stop_sequence": null,
+            "usage": { "input_tokens": 1, "output_tokens": 1 },
+        }))
+        .unwrap();
+        let response = rgpt_types::completion::Response::from(response);
+        assert_eq!(
+            response.content[1].text().as_deref(),
+            Some("[unsupported: server_tool_use]")
+        );
+
+        let event: MessagesEvent = serde_json::from_str(
+            r#"{"type":"content_block_start","index":1,"content_block":{"type":"image","source":{}}}"#,
+        )
+        .unwrap();
+        let TextEvent::ContentBlockStart { content_block, .. } = event.into() else {
+            panic!("not a content block start");
+        };
+        assert_eq!(content_block.text().as_deref(), Some("[unsupported: image]"));
+
+        // A known type that doesn't parse isn't taken for an unknown one
+        assert!(serde_json::from_str::<Content>(r#"{"type":"text","txt":"Hi"}"#).is_err());
+        assert!(serde_json::from_str::<MessagesEvent>(
+            r#"{"type":"content_block_start","index":0,"content_block":{"type":"tool_use","id":"toolu_1"}}"#,
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_empty_messages_rejected() {
+        let request = MessagesRequest::from(Request::builder().build());
+        assert!(matches!(request.validate(), Err(Error::InvalidArgument(_))));
+    }
+
+    #[test]
+    fn test_empty_stop_sequence_rejected() {
+        let request = |stop: &str| {
+            MessagesRequest::from(
+                Request::builder()
+                    .messages(vec![Message::user("A human walks into a bar")])
+                    .stop_sequences(Some(vec!["\n\nHuman:".to_string(), stop.to_string()]))
+                    .build(),
+            )
+        };
+        assert!(request("```").validate().is_ok());
+        assert!(matches!(request("").validate(), Err(Error::InvalidArgument(_))));
+    }
+}
@@ -1,17 +1,191 @@
+use std::path::{Path, PathBuf};
+
+use serde::Deserialize;
+
+/// Service keyring entries are stored under, with the provider as the user, e.g.
+/// `secret-tool store --label rgpt service rgpt username anthropic`.
+#[cfg(feature = "keyring")]
+const KEYRING_SERVICE: &str = "rgpt";
+
+/// Picks the provider, e.g. `RGPT_PROVIDER=ollama` for one that needs no key or
+/// `RGPT_PROVIDER=openai` for OpenAI's key when Anthropic's is set too.
+const PROVIDER_ENV_VAR: &str = "RGPT_PROVIDER";
+
 pub enum ApiKey {
     Anthropic(crate::anthropic::api_key::ApiKey),
+    OpenAI(crate::openai::api_key::ApiKey),
+    /// A local server, which takes no key.
+    Ollama,
+}
+
+/// Where a key is looked for, in the order they are tried.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum KeySource {
+    Env,
+    File(PathBuf),
+    Keyring,
+}
+
+impl std::fmt::Display for KeySource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Env => write!(f, "environment"),
+            Self::File(path) => write!(f, "{}", path.display()),
+            Self::Keyring => write!(f, "keyring"),
+        }
+    }
+}
+
+/// `credentials.toml` in the config dir.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct Credentials {
+    anthropic: Option<Credential>,
+    openai: Option<Credential>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Credential {
+    api_key: String,
+}
+
+fn credentials_path() -> Option<PathBuf> {
+    rgpt_utils::paths::config_dir().map(|dir| dir.join("credentials.toml"))
 }
 
 impl ApiKey {
+    /// The provider `RGPT_PROVIDER` picks, otherwise the first key set, Anthropic's before
+    /// OpenAI's.
     pub fn get() -> Option<Self> {
-        crate::anthropic::api_key::ApiKey::get().map(Self::Anthropic)
+        Self::find().map(|(key, _)| key)
+    }
+
+    /// The first key set and where it was found. Keys in the environment come first, then
+    /// those in the credentials file, then the keyring.
+    pub fn find() -> Option<(Self, KeySource)> {
+        Self::from_env()
+            .map(|key| (key, KeySource::Env))
+            .or_else(|| {
+                let path = credentials_path()?;
+                Self::from_file(&path).map(|key| (key, KeySource::File(path)))
+            })
+            .or_else(|| Self::from_keyring().map(|key| (key, KeySource::Keyring)))
+    }
+
+    /// Where [`ApiKey::find`] looks, in order.
+    pub fn sources() -> Vec<KeySource> {
+        [
+            Some(KeySource::Env),
+            credentials_path().map(KeySource::File),
+            cfg!(feature = "keyring").then_some(KeySource::Keyring),
+        ]
+        .into_iter()
+        .flatten()
+        .collect()
     }
 
-    pub fn get_provider(&self) -> crate::Provider {
+    fn from_env() -> Option<Self> {
+        match std::env::var(PROVIDER_ENV_VAR).as_deref() {
+            Ok("ollama") => return Some(Self::Ollama),
+            Ok("anthropic") => {
+                return crate::anthropic::api_key::ApiKey::get().map(Self::Anthropic)
+            }
+            Ok("openai") => return crate::openai::api_key::ApiKey::get().map(Self::OpenAI),
+            Ok(provider) => tracing::warn!("unknown {} '{}'", PROVIDER_ENV_VAR, provider),
+            Err(_) => {}
+        }
+        crate::anthropic::api_key::ApiKey::get()
+            .map(Self::Anthropic)
+            .or_else(|| crate::openai::api_key::ApiKey::get().map(Self::OpenAI))
+    }
+
+    fn from_file(path: &Path) -> Option<Self> {
+        let credentials = std::fs::read_to_string(path).ok()?;
+        Self::from_credentials(&credentials)
+    }
+
+    fn from_credentials(credentials: &str) -> Option<Self> {
+        let credentials = toml::from_str::<Credentials>(credentials)
+            .map_err(|e| tracing::warn!("invalid credentials file: {}", e))
+            .ok()?;
+        let key = |credential: Option<Credential>| {
+            credential
+                .map(|credential| credential.api_key.trim().to_string())
+                .filter(|key| !key.is_empty())
+        };
+        key(credentials.anthropic)
+            .map(|key| Self::Anthropic(key.into()))
+            .or_else(|| key(credentials.openai).map(|key| Self::OpenAI(key.into())))
+    }
+
+    #[cfg(feature = "keyring")]
+    fn from_keyring() -> Option<Self> {
+        let key = |user: &str| {
+            keyring::Entry::new(KEYRING_SERVICE, user)
+                .and_then(|entry| entry.get_password())
+                .map_err(|e| tracing::debug!("no {} key in keyring: {}", user, e))
+                .ok()
+                .map(|key| key.trim().to_string())
+                .filter(|key| !key.is_empty())
+        };
+        key("anthropic")
+            .map(|key| Self::Anthropic(key.into()))
+            .or_else(|| key("openai").map(|key| Self::OpenAI(key.into())))
+    }
+
+    #[cfg(not(feature = "keyring"))]
+    fn from_keyring() -> Option<Self> {
+        None
+    }
+
+    pub fn api(&self) -> crate::Api {
         match self {
-            Self::Anthropic(key) => crate::Provider::Anthropic(
-                crate::anthropic::provider::Provider::new(key.key.clone()),
-            ),
+            Self::Anthropic(_) => crate::Api::Anthropic,
+            Self::OpenAI(_) => crate::Api::OpenAI,
+            Self::Ollama => crate::Api::Ollama,
         }
     }
+
+    /// Fails if the key can't be sent in a header, e.g. for a control character in it.
+    pub fn get_provider(&self) -> Result<crate::Provider, crate::error::Error> {
+        Ok(match self {
+            Self::Anthropic(key) => {
+                crate::Provider::Anthropic(crate::anthropic::provider::Provider::with_config(
+                    crate::anthropic::provider::AnthropicConfig {
+                        api_key: key.key.clone(),
+                        api_base: crate::anthropic::api_key::api_base(),
+                    },
+                )?)
+            }
+            Self::OpenAI(key) => {
+                crate::Provider::OpenAI(crate::openai::provider::Provider::new(key.key.clone())?)
+            }
+            Self::Ollama => crate::Provider::Ollama(crate::ollama::provider::Provider::new(
+                crate::ollama::api_base(),
+            )),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_credentials() {
+        let key = ApiKey::from_credentials(
+            "[openai]\napi_key = \"sk-o\"\n\n[anthropic]\napi_key = \"sk-a\"\n",
+        );
+        assert!(matches!(key, Some(ApiKey::Anthropic(key)) if key.key == "sk-a"));
+
+        let key = ApiKey::from_credentials("[anthropic]\napi_key = \"sk-a\\n\"\n");
+        assert!(matches!(key, Some(ApiKey::Anthropic(key)) if key.key == "sk-a"));
+
+        let key =
+            ApiKey::from_credentials("[anthropic]\napi_key = \"\"\n[openai]\napi_key = \"sk-o\"\n");
+        assert!(matches!(key, Some(ApiKey::OpenAI(key)) if key.key == "sk-o"));
+
+        assert!(ApiKey::from_credentials("").is_none());
+        assert!(ApiKey::from_credentials("api_key = ").is_none());
+    }
 }
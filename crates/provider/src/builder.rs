@@ -24,7 +24,7 @@ impl Builder {
         self
     }
 
-    pub fn build(self) -> Provider {
+    pub fn build(self) -> Result<Provider, crate::error::Error> {
         self.api_key.get_provider()
     }
 }
@@ -1,5 +1,87 @@
+pub use rgpt_caller::error::ApiErrorKind;
+
 #[derive(thiserror::Error, Debug)]
 pub enum Error {
+    /// The key was rejected, `key` being the variable it is usually set in
+    #[error("{key} rejected ({status}): {message}")]
+    Authentication {
+        key: &'static str,
+        status: u16,
+        message: String,
+    },
+
     #[error("Anthropic error: {0}")]
-    Anthropic(#[from] crate::anthropic::error::Error),
+    Anthropic(crate::anthropic::error::Error),
+
+    #[error("OpenAI error: {0}")]
+    OpenAI(Box<crate::openai::error::Error>),
+
+    #[error("Ollama error: {0}")]
+    Ollama(#[from] crate::ollama::error::Error),
+}
+
+impl From<crate::anthropic::error::Error> for Error {
+    fn from(e: crate::anthropic::error::Error) -> Self {
+        match e {
+            crate::anthropic::error::Error::Caller(rgpt_caller::error::Error::Authentication {
+                status,
+                message,
+            }) => Error::Authentication {
+                key: crate::anthropic::api_key::ApiKey::API_KEY_ENV_VAR,
+                status,
+                message,
+            },
+            e => Error::Anthropic(e),
+        }
+    }
+}
+
+// Boxed to keep the error no larger than it was with Anthropic alone
+impl From<crate::openai::error::Error> for Error {
+    fn from(e: crate::openai::error::Error) -> Self {
+        match e {
+            crate::openai::error::Error::Caller(rgpt_caller::error::Error::Authentication {
+                status,
+                message,
+            }) => Error::Authentication {
+                key: crate::openai::api_key::ApiKey::API_KEY_ENV_VAR,
+                status,
+                message,
+            },
+            e => Error::OpenAI(Box::new(e)),
+        }
+    }
+}
+
+impl Error {
+    /// The kind of error the API reported, if this is an API error.
+    pub fn api_error_kind(&self) -> Option<&ApiErrorKind> {
+        match self {
+            Error::Authentication { status: 403, .. } => Some(&ApiErrorKind::Permission),
+            Error::Authentication { .. } => Some(&ApiErrorKind::Authentication),
+            Error::Anthropic(e) => e.api_error_kind(),
+            Error::OpenAI(e) => e.api_error_kind(),
+            Error::Ollama(e) => e.api_error_kind(),
+        }
+    }
+
+    /// Whether sending the request again may succeed. A rejected key never does.
+    pub fn is_transient(&self) -> bool {
+        match self {
+            Error::Authentication { .. } => false,
+            Error::Anthropic(e) => e.is_transient(),
+            Error::OpenAI(e) => e.is_transient(),
+            Error::Ollama(e) => e.is_transient(),
+        }
+    }
+
+    /// Whether the request got no answer, having timed out or lost its connection.
+    pub fn is_connection(&self) -> bool {
+        match self {
+            Error::Authentication { .. } => false,
+            Error::Anthropic(e) => e.is_connection(),
+            Error::OpenAI(e) => e.is_connection(),
+            Error::Ollama(e) => e.is_connection(),
+        }
+    }
 }
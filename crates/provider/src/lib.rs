@@ -2,17 +2,66 @@ use std::pin::Pin;
 
 use error::Error;
 use rgpt_types::completion::{Request, Response, TextEvent};
+use tokenizer::{Heuristic, Tokenizer};
 
-use rgpt_utils::stream::adapt_stream;
+use rgpt_utils::{hash::sha256_hex, stream::adapt_stream};
 use tokio_stream::Stream;
 
 mod anthropic;
 pub mod api_key;
 pub mod builder;
 pub mod error;
+mod ollama;
+mod openai;
+pub mod tokenizer;
+
+/// The API a request is shaped for, which is known without a key.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Api {
+    Anthropic,
+    OpenAI,
+    Ollama,
+}
+
+impl Api {
+    /// The API of the key that would be used, or Anthropic's if there is none.
+    pub fn current() -> Self {
+        api_key::ApiKey::get().map_or(Api::Anthropic, |key| key.api())
+    }
+
+    /// `request` as the JSON body sent to the API, indented if `pretty`.
+    pub fn request_body(&self, request: Request, pretty: bool) -> Result<Vec<u8>, Error> {
+        fn to_vec<T: serde::Serialize>(body: &T, pretty: bool) -> serde_json::Result<Vec<u8>> {
+            match pretty {
+                true => serde_json::to_vec_pretty(body),
+                false => serde_json::to_vec(body),
+            }
+        }
+        Ok(match self {
+            Api::Anthropic => to_vec(&anthropic::types::MessagesRequest::from(request), pretty)
+                .map_err(anthropic::error::Error::from)?,
+            Api::OpenAI => to_vec(&openai::types::ChatRequest::from(request), pretty)
+                .map_err(openai::error::Error::from)?,
+            Api::Ollama => to_vec(&ollama::types::ChatRequest::from(request), pretty)
+                .map_err(ollama::error::Error::from)?,
+        })
+    }
+}
+
+/// A model the API can be asked to use.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ModelInfo {
+    /// What to pass as the model
+    pub id: String,
+    pub display_name: String,
+    /// When the model was released, or for Ollama, pulled
+    pub created_at: Option<String>,
+}
 
 pub enum Provider {
     Anthropic(anthropic::provider::Provider),
+    OpenAI(openai::provider::Provider),
+    Ollama(ollama::provider::Provider),
 }
 
 pub type ResponseStream = Pin<Box<dyn Stream<Item = Result<Response, Error>> + Send>>;
@@ -21,18 +70,91 @@ pub type EventsStream = Pin<Box<dyn Stream<Item = Result<TextEvent, Error>> + Se
 impl Provider {
     pub async fn complete(&self, request: Request) -> Result<Response, Error> {
         Ok(match self {
-            Self::Anthropic(provider) => provider.messages(request).await,
-        }?
-        .into())
+            Self::Anthropic(provider) => provider.messages(request).await?.into(),
+            Self::OpenAI(provider) => provider.chat(request).await?.into(),
+            Self::Ollama(provider) => provider.chat(request).await?.into(),
+        })
+    }
+
+    pub fn api(&self) -> Api {
+        match self {
+            Self::Anthropic(_) => Api::Anthropic,
+            Self::OpenAI(_) => Api::OpenAI,
+            Self::Ollama(_) => Api::Ollama,
+        }
+    }
+
+    /// Cheap request that fails if the API is unreachable or rejects the key.
+    pub async fn health_check(&self) -> Result<(), Error> {
+        match self {
+            Self::Anthropic(provider) => provider.health_check().await?,
+            Self::OpenAI(provider) => provider.health_check().await?,
+            Self::Ollama(provider) => provider.health_check().await?,
+        }
+        Ok(())
+    }
+
+    /// The models the API offers.
+    pub async fn list_models(&self) -> Result<Vec<ModelInfo>, Error> {
+        Ok(match self {
+            Self::Anthropic(provider) => provider.list_models().await?,
+            Self::OpenAI(provider) => provider.list_models().await?,
+            Self::Ollama(provider) => provider.list_models().await?,
+        })
+    }
+
+    /// Input tokens `request` would use. Anthropic counts them, other providers' are estimated
+    /// with the local tokenizer.
+    pub async fn count_tokens(&self, request: Request) -> Result<usize, Error> {
+        let tokenizer = self.tokenizer(request.model.as_deref());
+        let estimate = |request: Request| {
+            request
+                .system
+                .iter()
+                .chain(request.messages.iter().map(|message| &message.content))
+                .map(|text| tokenizer.count(text))
+                .sum()
+        };
+        Ok(match self {
+            Self::Anthropic(provider) => provider.count_tokens(request).await?,
+            Self::OpenAI(_) | Self::Ollama(_) => estimate(request),
+        })
+    }
+
+    /// The most accurate local tokenizer for `model`. Anthropic publishes no tokenizer for its
+    /// models, so theirs are estimated.
+    #[cfg_attr(not(feature = "tiktoken"), allow(unused_variables))]
+    pub fn tokenizer(&self, model: Option<&str>) -> Box<dyn Tokenizer> {
+        #[cfg(feature = "tiktoken")]
+        if let Some(bpe) = model.and_then(tokenizer::Bpe::for_model) {
+            return Box::new(bpe);
+        }
+        match self {
+            Self::Anthropic(_) | Self::OpenAI(_) | Self::Ollama(_) => Box::new(Heuristic),
+        }
+    }
+
+    /// Stable hash of the request body as it would be sent, to tell whether two runs sent the
+    /// same thing.
+    pub fn request_hash(&self, request: Request) -> Result<String, Error> {
+        let body = self.api().request_body(request, false)?;
+        Ok(sha256_hex(&body))
     }
 
     pub async fn complete_stream(&self, request: Request) -> Result<EventsStream, Error> {
-        let stream = match self {
-            Self::Anthropic(provider) => provider.messages_stream(request).await,
-        }?;
         tracing::trace!("adapting stream");
-        Ok(adapt_stream(stream, |res| {
-            res.map(Into::into).map_err(Into::into)
-        }))
+        Ok(match self {
+            Self::Anthropic(provider) => {
+                adapt_stream(provider.messages_stream(request).await?, |res| {
+                    res.map(Into::into).map_err(Into::into)
+                })
+            }
+            Self::OpenAI(provider) => adapt_stream(provider.chat_stream(request).await?, |res| {
+                res.map(Into::into).map_err(Into::into)
+            }),
+            Self::Ollama(provider) => adapt_stream(provider.chat_stream(request).await?, |res| {
+                res.map(Into::into).map_err(Into::into)
+            }),
+        })
     }
 }
@@ -0,0 +1,48 @@
+//! Definition of errors used in the library.
+pub use rgpt_caller::error::ApiErrorKind;
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    /// Nothing answers at the server's url
+    #[error("no Ollama server at {0}, is `ollama serve` running?")]
+    Unreachable(String),
+    /// Error when a response cannot be deserialized into a Rust type
+    #[error("failed to deserialize api response: {0}")]
+    JSONDeserialize(serde_json::Error),
+    /// Error from client side validation
+    #[error("invalid args: {0}")]
+    InvalidArgument(String),
+
+    #[error("Serialization error: {0}")]
+    JSONSerialize(#[from] serde_json::Error),
+
+    #[error("Caller error: {0}")]
+    Caller(#[from] rgpt_caller::error::Error),
+}
+
+impl Error {
+    /// The kind of error the API reported, if this is an API error.
+    pub fn api_error_kind(&self) -> Option<&ApiErrorKind> {
+        match self {
+            Error::Caller(e) => e.api_error_kind(),
+            _ => None,
+        }
+    }
+
+    /// Whether sending the request again may succeed.
+    pub fn is_transient(&self) -> bool {
+        match self {
+            Error::Caller(e) => e.is_transient(),
+            _ => false,
+        }
+    }
+
+    /// Whether the request got no answer, which the caller doesn't retry.
+    pub fn is_connection(&self) -> bool {
+        match self {
+            Error::Unreachable(_) => true,
+            Error::Caller(e) => e.is_connection(),
+            _ => false,
+        }
+    }
+}
@@ -0,0 +1,21 @@
+pub mod error;
+pub mod provider;
+pub mod types;
+
+/// Default model to use.
+pub const DEFAULT_MODEL: &str = "llama3.2";
+/// Default server base url.
+pub const API_BASE: &str = "http://localhost:11434";
+
+/// Base url of the server, from `OLLAMA_HOST` like the Ollama CLI, which may leave out the
+/// scheme.
+pub fn api_base() -> String {
+    match std::env::var("OLLAMA_HOST")
+        .ok()
+        .filter(|host| !host.is_empty())
+    {
+        Some(host) if host.contains("://") => host.trim_end_matches('/').to_string(),
+        Some(host) => format!("http://{}", host.trim_end_matches('/')),
+        None => API_BASE.to_string(),
+    }
+}
@@ -0,0 +1,193 @@
+use std::pin::Pin;
+
+use reqwest::header::{HeaderMap, ACCEPT, CONTENT_TYPE};
+use rgpt_caller::client::Client;
+use tokio_stream::{Stream, StreamExt as _};
+
+use crate::ollama::error::Error;
+use crate::ollama::types::{ChatEvent, ChatRequest, ChatResponse, TagsResponse};
+
+const HEALTH_CHECK_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(10);
+
+pub type ChatEventStream = Pin<Box<dyn Stream<Item = Result<ChatEvent, Error>> + Send>>;
+
+/// A local Ollama server. It takes no API key.
+#[derive(Debug)]
+pub struct Provider {
+    pub api_base: String,
+    caller: Client,
+}
+
+impl Provider {
+    pub fn new(api_base: String) -> Self {
+        let mut headers = HeaderMap::new();
+        headers.insert(CONTENT_TYPE, "application/json".parse().unwrap());
+        headers.insert(ACCEPT, "application/json".parse().unwrap());
+        let caller = Client::new(headers);
+        Self { api_base, caller }
+    }
+
+    fn url(&self, path: &str) -> String {
+        format!("{}{}", self.api_base, path)
+    }
+
+    /// A connection refused means there is no server, rather than that it failed.
+    fn error(&self, e: rgpt_caller::error::Error) -> Error {
+        match e {
+            rgpt_caller::error::Error::Reqwest(e) if e.is_connect() => {
+                Error::Unreachable(self.api_base.clone())
+            }
+            e => e.into(),
+        }
+    }
+
+    /// List the local models, which checks the server is up.
+    pub async fn health_check(&self) -> Result<(), Error> {
+        self.caller
+            .get::<serde_json::Value>(&self.url("/api/tags"), HEALTH_CHECK_TIMEOUT)
+            .await
+            .map_err(|e| self.error(e))?;
+        Ok(())
+    }
+
+    pub async fn list_models(&self) -> Result<Vec<crate::ModelInfo>, Error> {
+        let response: TagsResponse = self
+            .caller
+            .get(&self.url("/api/tags"), HEALTH_CHECK_TIMEOUT)
+            .await
+            .map_err(|e| self.error(e))?;
+        Ok(response.models.into_iter().map(Into::into).collect())
+    }
+
+    pub async fn chat<R>(&self, request: R) -> Result<ChatResponse, Error>
+    where
+        R: Into<ChatRequest>,
+    {
+        let request = request.into();
+        if request.stream {
+            return Err(Error::InvalidArgument(
+                "When stream is true, use chat_stream() instead".into(),
+            ));
+        }
+        request.validate()?;
+        self.caller
+            .post(&self.url("/api/chat"), request)
+            .await
+            .map_err(|e| self.error(e))
+    }
+
+    pub async fn chat_stream<R>(&self, request: R) -> Result<ChatEventStream, Error>
+    where
+        R: Into<ChatRequest>,
+    {
+        let request = request.into();
+        tracing::debug!("request: {:?}", request);
+        if !request.stream {
+            return Err(Error::InvalidArgument(
+                "When stream is false, use chat() instead".into(),
+            ));
+        }
+        request.validate()?;
+        let model = request.model.clone();
+        let mut lines = self
+            .caller
+            .post_lines(&self.url("/api/chat"), request, Self::chat_handler)
+            .await
+            .map_err(|e| self.error(e))?;
+
+        // Bracket the lines with the start and end of the message, which Ollama doesn't send. A
+        // stream cut off before the line marked done gets no end, like a dropped SSE stream.
+        let (tx, rx) = tokio::sync::mpsc::unbounded_channel();
+        tokio::spawn(async move {
+            let _ = tx.send(Ok(ChatEvent::Start { model }));
+            while let Some(event) = lines.next().await {
+                let done = matches!(&event, Ok(ChatEvent::Chunk(chunk)) if chunk.done);
+                if tx.send(event).is_err() {
+                    return;
+                }
+                if done {
+                    let _ = tx.send(Ok(ChatEvent::Done));
+                    return;
+                }
+            }
+        });
+        Ok(Box::pin(
+            tokio_stream::wrappers::UnboundedReceiverStream::new(rx),
+        ))
+    }
+
+    pub fn chat_handler(line: &str) -> Result<ChatEvent, Error> {
+        tracing::debug!("line: {:?}", line);
+        match serde_json::from_str(line) {
+            Ok(chunk) => Ok(ChatEvent::Chunk(chunk)),
+            Err(e) => {
+                tracing::error!("error deserializing line: {:?}", e);
+                Err(Error::JSONDeserialize(e))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rgpt_caller::test_server::{serve, stream_response};
+    use rgpt_types::completion::{Request, StopReason, TextEvent};
+    use rgpt_types::message::Message;
+
+    use super::*;
+
+    /// An `/api/chat` stream as sent on the wire.
+    const TRANSCRIPT: &str = r#"{"model":"llama3.2","created_at":"2024-01-01T00:00:00Z","message":{"role":"assistant","content":"Hello"},"done":false}
+{"model":"llama3.2","created_at":"2024-01-01T00:00:00Z","message":{"role":"assistant","content":", world"},"done":false}
+{"model":"llama3.2","created_at":"2024-01-01T00:00:01Z","message":{"role":"assistant","content":""},"done":true,"done_reason":"stop","prompt_eval_count":12,"eval_count":4}
+"#;
+
+    fn request() -> Request {
+        Request::builder()
+            .messages(vec![Message::from("A human walks into a bar".to_string())])
+            .stream(true)
+            .build()
+    }
+
+    #[tokio::test]
+    async fn test_chat_stream() {
+        let (api_base, _server) =
+            serve(vec![stream_response("application/x-ndjson", TRANSCRIPT)]).await;
+
+        let events = Provider::new(api_base)
+            .chat_stream(request())
+            .await
+            .unwrap()
+            .map(|event| TextEvent::from(event.unwrap()))
+            .collect::<Vec<_>>()
+            .await;
+
+        assert_eq!(events.len(), 5);
+        assert!(matches!(events[0], TextEvent::MessageStart { .. }));
+        let text = events
+            .iter()
+            .filter_map(TextEvent::text)
+            .collect::<String>();
+        assert_eq!(text, "Hello, world");
+        assert_eq!(events[3].stop_reason(), Some(&StopReason::EndTurn));
+        assert!(matches!(events[4], TextEvent::MessageStop));
+    }
+
+    #[tokio::test]
+    async fn test_unreachable() {
+        // Nothing listens on the port once the listener is dropped
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let api_base = format!("http://{}", listener.local_addr().unwrap());
+        drop(listener);
+
+        let provider = Provider::new(api_base);
+        assert!(matches!(
+            provider.health_check().await,
+            Err(Error::Unreachable(_))
+        ));
+        assert!(matches!(
+            provider.chat_stream(request()).await,
+            Err(Error::Unreachable(_))
+        ));
+    }
+}
@@ -0,0 +1,359 @@
+use rgpt_types::completion::{
+    Content, ContentDelta, DeltaUsage, MessageDelta, MessageStartData, Request, Response,
+    StopReason, TextEvent, Usage,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::ollama::error::Error;
+use crate::ollama::DEFAULT_MODEL;
+use crate::openai::types::ChatTool;
+
+#[derive(Debug, Deserialize, Clone, Serialize, Eq, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Role {
+    User,
+    Assistant,
+    System,
+    /// The result of a tool call.
+    Tool,
+}
+
+impl From<rgpt_types::message::Role> for Role {
+    fn from(role: rgpt_types::message::Role) -> Self {
+        match role {
+            rgpt_types::message::Role::User => Self::User,
+            rgpt_types::message::Role::Assistant => Self::Assistant,
+            rgpt_types::message::Role::System => Self::System,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Message {
+    pub role: Role,
+    pub content: String,
+    /// Base64 encoded, without a media type.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub images: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tool_calls: Vec<ToolCall>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ToolCall {
+    pub function: FunctionCall,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct FunctionCall {
+    pub name: String,
+    pub arguments: serde_json::Value,
+}
+
+/// Tool results are messages of their own, one per call, before the rest of the message.
+fn chat_messages(message: rgpt_types::message::RichMessage) -> Vec<Message> {
+    let rgpt_types::message::RichMessage {
+        message: rgpt_types::message::Message { role, content },
+        images,
+        tool_uses,
+        tool_results,
+    } = message;
+    let has_results = !tool_results.is_empty();
+    let results = tool_results.into_iter().map(|result| Message {
+        role: Role::Tool,
+        content: result.content,
+        images: vec![],
+        tool_calls: vec![],
+    });
+    let rest = (!has_results || !content.is_empty() || !images.is_empty()).then(|| Message {
+        role: role.into(),
+        content,
+        images: images.into_iter().map(|image| image.data).collect(),
+        tool_calls: tool_uses
+            .into_iter()
+            .map(|tool_use| ToolCall {
+                function: FunctionCall {
+                    name: tool_use.name,
+                    arguments: tool_use.input,
+                },
+            })
+            .collect(),
+    });
+    results.chain(rest).collect()
+}
+
+/// Sampling settings, which Ollama takes apart from the rest of the request.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct Options {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub temperature: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub top_p: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub top_k: Option<usize>,
+    /// The maximum number of tokens to generate.
+    pub num_predict: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stop: Option<Vec<String>>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ChatRequest {
+    pub model: String,
+    pub messages: Vec<Message>,
+    pub stream: bool,
+    pub options: Options,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tools: Option<Vec<ChatTool>>,
+}
+
+impl From<Request> for ChatRequest {
+    fn from(val: Request) -> Self {
+        // An explicit system prompt replaces the system messages
+        let explicit = val.system.is_some();
+        let system = val.system.map(|content| Message {
+            role: Role::System,
+            content,
+            images: vec![],
+            tool_calls: vec![],
+        });
+        ChatRequest {
+            model: val.model.unwrap_or(DEFAULT_MODEL.to_string()),
+            messages: system
+                .into_iter()
+                .chain(
+                    val.messages
+                        .into_iter()
+                        .filter(|message| {
+                            !explicit || message.role != rgpt_types::message::Role::System
+                        })
+                        .flat_map(chat_messages),
+                )
+                .collect(),
+            stream: val.stream,
+            options: Options {
+                temperature: val.temperature,
+                top_p: val.top_p,
+                top_k: val.top_k,
+                num_predict: val.max_tokens,
+                stop: val.stop_sequences,
+            },
+            tools: val
+                .tools
+                .map(|tools| tools.into_iter().map(ChatTool::from).collect()),
+        }
+    }
+}
+
+impl ChatRequest {
+    /// Client side checks for requests the server would reject.
+    pub fn validate(&self) -> Result<(), Error> {
+        if self.messages.is_empty() {
+            return Err(Error::InvalidArgument(
+                "messages must contain at least one message".into(),
+            ));
+        }
+        if self
+            .options
+            .stop
+            .iter()
+            .flatten()
+            .any(|stop| stop.is_empty())
+        {
+            return Err(Error::InvalidArgument(
+                "stop sequences can't be empty".into(),
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// The whole answer, or when streaming, one line of it. The last line has `done` set and the
+/// token counts, and no text.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ChatResponse {
+    pub model: String,
+    pub created_at: String,
+    pub message: Message,
+    pub done: bool,
+    #[serde(default)]
+    pub done_reason: Option<String>,
+    #[serde(default)]
+    pub prompt_eval_count: usize,
+    #[serde(default)]
+    pub eval_count: usize,
+}
+
+impl ChatResponse {
+    fn stop_reason(&self) -> Option<StopReason> {
+        match self.done_reason.as_deref() {
+            _ if !self.message.tool_calls.is_empty() => Some(StopReason::ToolUse),
+            Some("length") => Some(StopReason::MaxTokens),
+            Some(_) => Some(StopReason::EndTurn),
+            None => None,
+        }
+    }
+}
+
+impl From<ChatResponse> for Response {
+    fn from(response: ChatResponse) -> Self {
+        let stop_reason = response.stop_reason();
+        let text = Some(response.message.content)
+            .filter(|text| !text.is_empty())
+            .map(|text| Content::Text { text });
+        // Ollama has no ids for messages or tool calls
+        let tool_uses = response
+            .message
+            .tool_calls
+            .into_iter()
+            .enumerate()
+            .map(|(i, call)| Content::ToolUse {
+                id: format!("call_{}", i),
+                name: call.function.name,
+                input: call.function.arguments,
+            });
+        Self {
+            stop_reason,
+            stop_sequence: None,
+            content: text.into_iter().chain(tool_uses).collect(),
+            model: response.model,
+            id: response.created_at,
+            type_: "message".to_string(),
+            role: "assistant".to_string(),
+            usage: Usage {
+                input_tokens: response.prompt_eval_count,
+                output_tokens: response.eval_count,
+            },
+        }
+    }
+}
+
+/// One event of a streamed answer. The lines Ollama sends don't say where the answer starts and
+/// ends, so the provider adds those.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub enum ChatEvent {
+    Start { model: String },
+    Chunk(ChatResponse),
+    Done,
+}
+
+impl From<ChatEvent> for TextEvent {
+    fn from(event: ChatEvent) -> Self {
+        let chunk = match event {
+            ChatEvent::Start { model } => {
+                return TextEvent::MessageStart {
+                    message: MessageStartData {
+                        id: String::new(),
+                        type_: "message".to_string(),
+                        role: "assistant".to_string(),
+                        model,
+                        content: vec![],
+                        stop_reason: None,
+                        stop_sequence: None,
+                        usage: Usage::default(),
+                    },
+                }
+            }
+            ChatEvent::Done => return TextEvent::MessageStop,
+            ChatEvent::Chunk(chunk) => chunk,
+        };
+        if chunk.done {
+            return TextEvent::MessageDelta {
+                delta: MessageDelta {
+                    stop_reason: chunk.stop_reason(),
+                    stop_sequence: None,
+                },
+                usage: Some(DeltaUsage {
+                    output_tokens: chunk.eval_count,
+                }),
+            };
+        }
+        match chunk.message.content.is_empty() {
+            true => TextEvent::Null,
+            false => TextEvent::ContentBlockDelta {
+                index: 0,
+                delta: ContentDelta::TextDelta {
+                    text: chunk.message.content,
+                },
+            },
+        }
+    }
+}
+
+/// The models pulled locally, from `GET /api/tags`.
+#[derive(Debug, Clone, Deserialize)]
+pub struct TagsResponse {
+    pub models: Vec<Tag>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Tag {
+    pub name: String,
+    pub modified_at: String,
+}
+
+impl From<Tag> for crate::ModelInfo {
+    fn from(tag: Tag) -> Self {
+        Self {
+            display_name: tag.name.clone(),
+            id: tag.name,
+            created_at: Some(tag.modified_at),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rgpt_types::message::{Role as MessageRole, ToolResult};
+
+    use super::*;
+
+    #[test]
+    fn test_chat_request() {
+        let mut image = rgpt_types::message::Message::with_image(
+            MessageRole::User,
+            "image/png".to_string(),
+            "iVBORw0KGgo=".to_string(),
+        );
+        image.content = "What is this?".to_string();
+        let request = Request::builder()
+            .messages(vec![
+                image,
+                rgpt_types::message::Message::with_tool_results(vec![ToolResult {
+                    tool_use_id: "call_0".to_string(),
+                    content: "Sunny".to_string(),
+                    is_error: false,
+                }]),
+            ])
+            .system("Be brief.".to_string())
+            .max_tokens(256)
+            .build();
+        let json = serde_json::to_value(ChatRequest::from(request)).unwrap();
+        assert_eq!(json["model"], DEFAULT_MODEL);
+        assert_eq!(json["messages"][0]["role"], "system");
+        assert_eq!(json["messages"][1]["images"][0], "iVBORw0KGgo=");
+        assert_eq!(json["messages"][2]["role"], "tool");
+        assert_eq!(json["messages"].as_array().unwrap().len(), 3);
+        assert_eq!(json["options"]["num_predict"], 256);
+        assert!(json["options"].get("temperature").is_none());
+    }
+
+    #[test]
+    fn test_chunks_to_events() {
+        let chunk =
+            |line: &str| TextEvent::from(ChatEvent::Chunk(serde_json::from_str(line).unwrap()));
+        let text = chunk(
+            r#"{"model":"llama3.2","created_at":"2024-01-01T00:00:00Z","message":{"role":"assistant","content":"Hello"},"done":false}"#,
+        );
+        assert_eq!(text.text().as_deref(), Some("Hello"));
+
+        let stop = chunk(
+            r#"{"model":"llama3.2","created_at":"2024-01-01T00:00:01Z","message":{"role":"assistant","content":""},"done":true,"done_reason":"length","prompt_eval_count":12,"eval_count":64}"#,
+        );
+        assert_eq!(stop.stop_reason(), Some(&StopReason::MaxTokens));
+        assert!(matches!(
+            stop,
+            TextEvent::MessageDelta { usage: Some(usage), .. } if usage.output_tokens == 64
+        ));
+    }
+}
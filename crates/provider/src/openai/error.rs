@@ -0,0 +1,50 @@
+//! Definition of errors used in the library.
+pub use rgpt_caller::error::{ApiError, ApiErrorKind};
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    /// OpenAI returns error object with details of API call failure
+    #[error("{}: {}", .0.r#type, .0.message)]
+    Api(ApiError),
+    /// Error when a response cannot be deserialized into a Rust type
+    #[error("failed to deserialize api response: {0}")]
+    JSONDeserialize(serde_json::Error),
+    /// Error from client side validation
+    /// or when builder fails to build request before making API call
+    #[error("invalid args: {0}")]
+    InvalidArgument(String),
+
+    #[error("Serialization error: {0}")]
+    JSONSerialize(#[from] serde_json::Error),
+
+    #[error("Caller error: {0}")]
+    Caller(#[from] rgpt_caller::error::Error),
+}
+
+impl Error {
+    /// The kind of error the API reported, if this is an API error.
+    pub fn api_error_kind(&self) -> Option<&ApiErrorKind> {
+        match self {
+            Error::Api(e) => Some(&e.r#type),
+            Error::Caller(e) => e.api_error_kind(),
+            _ => None,
+        }
+    }
+
+    /// Whether sending the request again may succeed.
+    pub fn is_transient(&self) -> bool {
+        match self {
+            Error::Api(e) => e.r#type.is_transient(),
+            Error::Caller(e) => e.is_transient(),
+            _ => false,
+        }
+    }
+
+    /// Whether the request got no answer, which the caller doesn't retry.
+    pub fn is_connection(&self) -> bool {
+        match self {
+            Error::Caller(e) => e.is_connection(),
+            _ => false,
+        }
+    }
+}
@@ -0,0 +1,9 @@
+pub mod api_key;
+pub mod error;
+pub mod provider;
+pub mod types;
+
+/// Default model to use.
+pub const DEFAULT_MODEL: &str = "gpt-4o";
+/// Default v1 API base url.
+pub const API_BASE: &str = "https://api.openai.com";
@@ -0,0 +1,132 @@
+use std::pin::Pin;
+
+use reqwest::header::{HeaderMap, ACCEPT, CONTENT_TYPE};
+use reqwest_eventsource::Event;
+use rgpt_caller::client::Client;
+use tokio_stream::{Stream, StreamExt as _};
+
+use crate::openai::error::Error;
+use crate::openai::types::{ChatEvent, ChatRequest, ChatResponse, ModelsResponse};
+use crate::openai::API_BASE;
+
+const HEALTH_CHECK_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(10);
+
+/// Sent by the API after the last chunk of a stream.
+const DONE: &str = "[DONE]";
+
+pub type ChatEventStream = Pin<Box<dyn Stream<Item = Result<ChatEvent, Error>> + Send>>;
+
+#[derive(Debug)]
+pub struct Provider {
+    pub api_key: String,
+    caller: Client,
+}
+
+impl Provider {
+    /// Fails if the key can't be sent in a header.
+    pub fn new(api_key: String) -> Result<Self, Error> {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            reqwest::header::AUTHORIZATION,
+            format!("Bearer {}", api_key)
+                .parse()
+                .map_err(|e| Error::InvalidArgument(format!("invalid API key: {}", e)))?,
+        );
+        headers.insert(CONTENT_TYPE, "application/json".parse().unwrap());
+        headers.insert(ACCEPT, "application/json".parse().unwrap());
+        let caller = Client::new(headers);
+        Ok(Self { api_key, caller })
+    }
+
+    /// List the models, which checks the key and connectivity without spending tokens.
+    pub async fn health_check(&self) -> Result<(), Error> {
+        self.caller
+            .get::<serde_json::Value>(&format!("{}/v1/models", API_BASE), HEALTH_CHECK_TIMEOUT)
+            .await?;
+        Ok(())
+    }
+
+    pub async fn list_models(&self) -> Result<Vec<crate::ModelInfo>, Error> {
+        let response: ModelsResponse = self
+            .caller
+            .get(&format!("{}/v1/models", API_BASE), HEALTH_CHECK_TIMEOUT)
+            .await?;
+        Ok(response.data.into_iter().map(Into::into).collect())
+    }
+
+    pub async fn chat<R>(&self, request: R) -> Result<ChatResponse, Error>
+    where
+        R: Into<ChatRequest>,
+    {
+        let request = request.into();
+        if request.stream {
+            return Err(Error::InvalidArgument(
+                "When stream is true, use chat_stream() instead".into(),
+            ));
+        }
+        request.validate()?;
+        Ok(self
+            .caller
+            .post(&format!("{}/v1/chat/completions", API_BASE), request)
+            .await?)
+    }
+
+    pub async fn chat_stream<R>(&self, request: R) -> Result<ChatEventStream, Error>
+    where
+        R: Into<ChatRequest>,
+    {
+        let request = request.into();
+        tracing::debug!("request: {:?}", request);
+        if !request.stream {
+            return Err(Error::InvalidArgument(
+                "When stream is false, use chat() instead".into(),
+            ));
+        }
+        request.validate()?;
+        let mut stream = self
+            .caller
+            .post_stream(
+                &format!("{}/v1/chat/completions", API_BASE),
+                request,
+                Self::chat_handler,
+            )
+            .await?;
+
+        let (tx, rx) = tokio::sync::mpsc::unbounded_channel();
+        tokio::spawn(async move {
+            while let Some(event) = stream.next().await {
+                let events = match event {
+                    Ok(ChatEvent::Chunk(chunk)) => chunk
+                        .split_role()
+                        .into_iter()
+                        .map(|chunk| Ok(ChatEvent::Chunk(chunk)))
+                        .collect(),
+                    event => vec![event],
+                };
+                for event in events {
+                    if tx.send(event).is_err() {
+                        return;
+                    }
+                }
+            }
+        });
+        Ok(Box::pin(
+            tokio_stream::wrappers::UnboundedReceiverStream::new(rx),
+        ))
+    }
+
+    pub fn chat_handler(event: reqwest_eventsource::Event) -> Result<ChatEvent, Error> {
+        tracing::debug!("event: {:?}", event);
+        match event {
+            Event::Open => Ok(ChatEvent::Open),
+            Event::Message(message) if message.data.trim() == DONE => Ok(ChatEvent::Done),
+            Event::Message(message) => match serde_json::from_str(&message.data) {
+                Ok(chunk) => Ok(ChatEvent::Chunk(chunk)),
+                Err(e) => {
+                    tracing::error!("error deserializing event: {:?}", e);
+                    Err(Error::JSONDeserialize(e))
+                }
+            },
+        }
+    }
+}
@@ -0,0 +1,559 @@
+use rgpt_types::completion::{
+    Content, ContentDelta, DeltaUsage, MessageDelta, MessageStartData, Request, Response,
+    StopReason, TextEvent, Tool, Usage,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::openai::error::Error;
+use crate::openai::DEFAULT_MODEL;
+
+#[derive(Debug, Deserialize, Clone, Serialize, Eq, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Role {
+    User,
+    Assistant,
+    System,
+    /// The result of a tool call.
+    Tool,
+}
+
+impl From<rgpt_types::message::Role> for Role {
+    fn from(role: rgpt_types::message::Role) -> Self {
+        match role {
+            rgpt_types::message::Role::User => Self::User,
+            rgpt_types::message::Role::Assistant => Self::Assistant,
+            rgpt_types::message::Role::System => Self::System,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Message {
+    pub role: Role,
+    pub content: MessageContent,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tool_calls: Option<Vec<ToolCall>>,
+    /// The call a `tool` message answers.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tool_call_id: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ToolCall {
+    pub id: String,
+    /// Always `function`.
+    pub r#type: String,
+    pub function: FunctionCall,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct FunctionCall {
+    pub name: String,
+    /// The input, as a JSON string.
+    pub arguments: String,
+}
+
+impl From<rgpt_types::message::ToolUse> for ToolCall {
+    fn from(tool_use: rgpt_types::message::ToolUse) -> Self {
+        Self {
+            id: tool_use.id,
+            r#type: "function".to_string(),
+            function: FunctionCall {
+                name: tool_use.name,
+                arguments: tool_use.input.to_string(),
+            },
+        }
+    }
+}
+
+impl From<ToolCall> for Content {
+    fn from(call: ToolCall) -> Self {
+        Content::ToolUse {
+            id: call.id,
+            name: call.function.name,
+            input: serde_json::from_str(&call.function.arguments)
+                .unwrap_or(serde_json::Value::String(call.function.arguments)),
+        }
+    }
+}
+
+/// Plain text, or parts when the message has images.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(untagged)]
+pub enum MessageContent {
+    Text(String),
+    Parts(Vec<ContentPart>),
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+#[serde(tag = "type")]
+pub enum ContentPart {
+    Text { text: String },
+    ImageUrl { image_url: ImageUrl },
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ImageUrl {
+    pub url: String,
+}
+
+impl From<rgpt_types::message::Image> for ContentPart {
+    fn from(image: rgpt_types::message::Image) -> Self {
+        Self::ImageUrl {
+            image_url: ImageUrl {
+                url: format!("data:{};base64,{}", image.media_type, image.data),
+            },
+        }
+    }
+}
+
+impl From<rgpt_types::message::RichMessage> for Message {
+    fn from(message: rgpt_types::message::RichMessage) -> Self {
+        let rgpt_types::message::Message { role, content } = message.message;
+        let content = match message.images.is_empty() {
+            true => MessageContent::Text(content),
+            false => MessageContent::Parts(
+                message
+                    .images
+                    .into_iter()
+                    .map(ContentPart::from)
+                    .chain(
+                        Some(content)
+                            .filter(|text| !text.is_empty())
+                            .map(|text| ContentPart::Text { text }),
+                    )
+                    .collect(),
+            ),
+        };
+        Self {
+            role: role.into(),
+            content,
+            tool_calls: Some(message.tool_uses)
+                .filter(|tool_uses| !tool_uses.is_empty())
+                .map(|tool_uses| tool_uses.into_iter().map(ToolCall::from).collect()),
+            tool_call_id: None,
+        }
+    }
+}
+
+/// Tool results are messages of their own here, one per call, before the rest of the message.
+fn chat_messages(mut message: rgpt_types::message::RichMessage) -> Vec<Message> {
+    let results = std::mem::take(&mut message.tool_results)
+        .into_iter()
+        .map(|result| Message {
+            role: Role::Tool,
+            content: MessageContent::Text(result.content),
+            tool_calls: None,
+            tool_call_id: Some(result.tool_use_id),
+        })
+        .collect::<Vec<_>>();
+    let rest = (results.is_empty() || !message.content.is_empty() || !message.images.is_empty())
+        .then(|| Message::from(message));
+    results.into_iter().chain(rest).collect()
+}
+
+// Chat completions API
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ChatRequest {
+    pub messages: Vec<Message>,
+    pub model: String,
+    pub max_completion_tokens: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stop: Option<Vec<String>>,
+    pub stream: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub temperature: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub top_p: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tools: Option<Vec<ChatTool>>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ChatTool {
+    /// Always `function`.
+    pub r#type: String,
+    pub function: Function,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Function {
+    pub name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    pub parameters: serde_json::Value,
+}
+
+impl From<Tool> for ChatTool {
+    fn from(tool: Tool) -> Self {
+        Self {
+            r#type: "function".to_string(),
+            function: Function {
+                name: tool.name,
+                description: tool.description,
+                parameters: tool.input_schema,
+            },
+        }
+    }
+}
+
+impl From<Request> for ChatRequest {
+    fn from(val: Request) -> Self {
+        // System prompts are messages like any other here. An explicit one replaces those in
+        // the messages.
+        let explicit = val.system.is_some();
+        let system = val.system.map(|content| Message {
+            role: Role::System,
+            content: MessageContent::Text(content),
+            tool_calls: None,
+            tool_call_id: None,
+        });
+        ChatRequest {
+            messages: system
+                .into_iter()
+                .chain(
+                    val.messages
+                        .into_iter()
+                        .filter(|message| {
+                            !explicit || message.role != rgpt_types::message::Role::System
+                        })
+                        .flat_map(chat_messages),
+                )
+                .collect(),
+            model: val.model.unwrap_or(DEFAULT_MODEL.to_string()),
+            max_completion_tokens: val.max_tokens,
+            stop: val.stop_sequences,
+            stream: val.stream,
+            temperature: val.temperature,
+            // The chat API has no top_k
+            top_p: val.top_p,
+            tools: val
+                .tools
+                .map(|tools| tools.into_iter().map(ChatTool::from).collect()),
+        }
+    }
+}
+
+impl ChatRequest {
+    /// Client side checks for requests the API would reject.
+    pub fn validate(&self) -> Result<(), Error> {
+        if self.messages.is_empty() {
+            return Err(Error::InvalidArgument(
+                "messages must contain at least one message".into(),
+            ));
+        }
+        if self.stop.iter().flatten().any(|stop| stop.is_empty()) {
+            return Err(Error::InvalidArgument(
+                "stop sequences can't be empty".into(),
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Why the model stopped. Reasons without an Anthropic counterpart, like `content_filter`, are
+/// treated as the end of the turn.
+fn stop_reason(finish_reason: &str) -> StopReason {
+    match finish_reason {
+        "length" => StopReason::MaxTokens,
+        "tool_calls" => StopReason::ToolUse,
+        _ => StopReason::EndTurn,
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ChatUsage {
+    pub prompt_tokens: usize,
+    pub completion_tokens: usize,
+}
+
+impl From<ChatUsage> for Usage {
+    fn from(usage: ChatUsage) -> Self {
+        Self {
+            input_tokens: usage.prompt_tokens,
+            output_tokens: usage.completion_tokens,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ChatResponse {
+    pub id: String,
+    pub model: String,
+    pub choices: Vec<Choice>,
+    pub usage: ChatUsage,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Choice {
+    pub message: ChoiceMessage,
+    pub finish_reason: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ChoiceMessage {
+    pub role: String,
+    pub content: Option<String>,
+    #[serde(default)]
+    pub tool_calls: Option<Vec<ToolCall>>,
+}
+
+impl From<ChatResponse> for Response {
+    fn from(response: ChatResponse) -> Self {
+        let choice = response.choices.into_iter().next();
+        let (text, tool_calls) = choice
+            .as_ref()
+            .map(|choice| {
+                (
+                    choice.message.content.clone(),
+                    choice.message.tool_calls.clone(),
+                )
+            })
+            .unwrap_or_default();
+        Self {
+            stop_reason: choice
+                .as_ref()
+                .and_then(|choice| choice.finish_reason.as_deref())
+                .map(stop_reason),
+            stop_sequence: None,
+            content: text
+                .map(|text| Content::Text { text })
+                .into_iter()
+                .chain(tool_calls.into_iter().flatten().map(Content::from))
+                .collect(),
+            model: response.model,
+            id: response.id,
+            type_: "message".to_string(),
+            role: "assistant".to_string(),
+            usage: response.usage.into(),
+        }
+    }
+}
+
+/// One server-sent event of a streamed chat completion.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub enum ChatEvent {
+    Open,
+    Chunk(ChatChunk),
+    /// The `[DONE]` sentinel that ends the stream.
+    Done,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ChatChunk {
+    pub id: String,
+    pub model: String,
+    pub choices: Vec<ChunkChoice>,
+    #[serde(default)]
+    pub usage: Option<ChatUsage>,
+}
+
+impl ChatChunk {
+    /// The chunk with the role, and then one with the rest of it if it also has text or a
+    /// finish reason, which would otherwise be lost when the role starts the message.
+    pub fn split_role(mut self) -> Vec<ChatChunk> {
+        let Some(choice) = self.choices.first_mut() else {
+            return vec![self];
+        };
+        let has_rest = choice
+            .delta
+            .content
+            .as_deref()
+            .is_some_and(|text| !text.is_empty())
+            || choice.finish_reason.is_some();
+        if choice.delta.role.is_none() || !has_rest {
+            return vec![self];
+        }
+        let mut rest = self.clone();
+        rest.choices[0].delta.role = None;
+        let choice = &mut self.choices[0];
+        choice.delta.content = None;
+        choice.finish_reason = None;
+        self.usage = None;
+        vec![self, rest]
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ChunkChoice {
+    pub delta: ChunkDelta,
+    pub finish_reason: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct ChunkDelta {
+    #[serde(default)]
+    pub role: Option<String>,
+    #[serde(default)]
+    pub content: Option<String>,
+}
+
+/// Chunks carry no event type, so the first one, which has the role, starts the message, the
+/// one with a finish reason ends it, and the rest are text. Text sent with the role is split
+/// off by [`ChatChunk::split_role`] first.
+impl From<ChatEvent> for TextEvent {
+    fn from(event: ChatEvent) -> Self {
+        let chunk = match event {
+            ChatEvent::Open => return TextEvent::Null,
+            ChatEvent::Done => return TextEvent::MessageStop,
+            ChatEvent::Chunk(chunk) => chunk,
+        };
+        let Some(choice) = chunk.choices.into_iter().next() else {
+            return TextEvent::Null;
+        };
+        if let Some(role) = choice.delta.role {
+            return TextEvent::MessageStart {
+                message: MessageStartData {
+                    id: chunk.id,
+                    type_: "message".to_string(),
+                    role,
+                    model: chunk.model,
+                    content: vec![],
+                    stop_reason: None,
+                    stop_sequence: None,
+                    usage: Usage {
+                        input_tokens: 0,
+                        output_tokens: 0,
+                    },
+                },
+            };
+        }
+        if let Some(finish_reason) = choice.finish_reason {
+            return TextEvent::MessageDelta {
+                delta: MessageDelta {
+                    stop_reason: Some(stop_reason(&finish_reason)),
+                    stop_sequence: None,
+                },
+                usage: chunk.usage.map(|usage| DeltaUsage {
+                    output_tokens: usage.completion_tokens,
+                }),
+            };
+        }
+        match choice.delta.content {
+            Some(text) => TextEvent::ContentBlockDelta {
+                index: 0,
+                delta: ContentDelta::TextDelta { text },
+            },
+            None => TextEvent::Null,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ModelsResponse {
+    pub data: Vec<Model>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Model {
+    pub id: String,
+}
+
+impl From<Model> for crate::ModelInfo {
+    fn from(model: Model) -> Self {
+        Self {
+            display_name: model.id.clone(),
+            id: model.id,
+            created_at: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rgpt_types::message::Role as MessageRole;
+
+    use super::*;
+
+    fn chunk(data: &str) -> TextEvent {
+        ChatEvent::Chunk(serde_json::from_str(data).unwrap()).into()
+    }
+
+    #[test]
+    fn test_chat_request() {
+        let request = Request::builder()
+            .messages(vec![rgpt_types::message::Message {
+                role: MessageRole::User,
+                content: "hi".to_string(),
+            }])
+            .system("Be brief.".to_string())
+            .build();
+        let json = serde_json::to_value(ChatRequest::from(request)).unwrap();
+        assert_eq!(json["model"], DEFAULT_MODEL);
+        assert_eq!(json["messages"][0]["role"], "system");
+        assert_eq!(json["messages"][1]["content"], "hi");
+        assert!(json.get("stop").is_none());
+    }
+
+    #[test]
+    fn test_tool_calls() {
+        let response: ChatResponse = serde_json::from_str(
+            r#"{"id":"c1","model":"gpt-4o","choices":[{"index":0,"message":{"role":"assistant","content":null,"tool_calls":[{"id":"call_1","type":"function","function":{"name":"get_weather","arguments":"{\"city\":\"Paris\"}"}}]},"finish_reason":"tool_calls"}],"usage":{"prompt_tokens":10,"completion_tokens":5}}"#,
+        )
+        .unwrap();
+        let response = Response::from(response);
+        assert_eq!(response.stop_reason, Some(StopReason::ToolUse));
+        let answer = rgpt_types::message::RichMessage::from(response);
+        assert_eq!(answer.tool_uses[0].input["city"], "Paris");
+
+        let result = rgpt_types::message::Message::with_tool_results(vec![
+            rgpt_types::message::ToolResult {
+                tool_use_id: "call_1".to_string(),
+                content: "Sunny".to_string(),
+                is_error: false,
+            },
+        ]);
+        let request = Request::builder().messages(vec![answer, result]).build();
+        let json = serde_json::to_value(ChatRequest::from(request)).unwrap();
+        assert_eq!(
+            json["messages"][0]["tool_calls"][0]["function"]["name"],
+            "get_weather"
+        );
+        assert_eq!(json["messages"][1]["role"], "tool");
+        assert_eq!(json["messages"][1]["tool_call_id"], "call_1");
+        assert_eq!(json["messages"].as_array().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_chunks_to_events() {
+        let start = chunk(
+            r#"{"id":"c1","model":"gpt-4o","choices":[{"index":0,"delta":{"role":"assistant","content":""},"finish_reason":null}]}"#,
+        );
+        assert!(matches!(start, TextEvent::MessageStart { .. }));
+
+        let text = chunk(
+            r#"{"id":"c1","model":"gpt-4o","choices":[{"index":0,"delta":{"content":"Hello"},"finish_reason":null}]}"#,
+        );
+        assert_eq!(text.text().as_deref(), Some("Hello"));
+
+        let stop = chunk(
+            r#"{"id":"c1","model":"gpt-4o","choices":[{"index":0,"delta":{},"finish_reason":"length"}]}"#,
+        );
+        assert_eq!(stop.stop_reason(), Some(&StopReason::MaxTokens));
+
+        assert!(TextEvent::from(ChatEvent::Done).is_complete());
+    }
+
+    #[test]
+    fn test_split_role() {
+        let first: ChatChunk = serde_json::from_str(
+            r#"{"id":"c1","model":"gpt-4o","choices":[{"index":0,"delta":{"role":"assistant","content":"Hi"},"finish_reason":null}]}"#,
+        )
+        .unwrap();
+        let events = first
+            .split_role()
+            .into_iter()
+            .map(|chunk| TextEvent::from(ChatEvent::Chunk(chunk)))
+            .collect::<Vec<_>>();
+        assert_eq!(events.len(), 2);
+        assert!(matches!(events[0], TextEvent::MessageStart { .. }));
+        assert_eq!(events[1].text().as_deref(), Some("Hi"));
+
+        let empty: ChatChunk = serde_json::from_str(
+            r#"{"id":"c1","model":"gpt-4o","choices":[{"index":0,"delta":{"role":"assistant","content":""},"finish_reason":null}]}"#,
+        )
+        .unwrap();
+        assert_eq!(empty.split_role().len(), 1);
+    }
+}
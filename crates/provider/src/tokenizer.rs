@@ -0,0 +1,53 @@
+/// Counts tokens locally, without asking the API.
+pub trait Tokenizer: Send + Sync {
+    fn count(&self, text: &str) -> usize;
+}
+
+/// Roughly four characters per token, which holds up for English prose and code.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Heuristic;
+
+impl Tokenizer for Heuristic {
+    fn count(&self, text: &str) -> usize {
+        text.chars().count().div_ceil(4)
+    }
+}
+
+/// OpenAI's BPE encodings, exact for models that use them.
+#[cfg(feature = "tiktoken")]
+pub struct Bpe(&'static tiktoken_rs::CoreBPE);
+
+#[cfg(feature = "tiktoken")]
+impl Bpe {
+    /// The encoding `model` uses, if tiktoken knows it.
+    pub fn for_model(model: &str) -> Option<Self> {
+        tiktoken_rs::bpe_for_model(model).ok().map(Self)
+    }
+}
+
+#[cfg(feature = "tiktoken")]
+impl Tokenizer for Bpe {
+    fn count(&self, text: &str) -> usize {
+        self.0.encode_with_special_tokens(text).len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_heuristic() {
+        assert_eq!(Heuristic.count(""), 0);
+        assert_eq!(Heuristic.count("ls"), 1);
+        assert_eq!(Heuristic.count("ls -la /tmp"), 3);
+    }
+
+    #[test]
+    #[cfg(feature = "tiktoken")]
+    fn test_bpe() {
+        let bpe = Bpe::for_model("gpt-4o").unwrap();
+        assert_eq!(bpe.count("hello world"), 2);
+        assert!(Bpe::for_model("claude-3-5-sonnet-20240620").is_none());
+    }
+}
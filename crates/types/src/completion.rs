@@ -1,16 +1,19 @@
 use serde::{Deserialize, Serialize};
 
-use crate::message::Message;
+use crate::message::{Message, RichMessage, ToolUse};
 
 #[derive(Debug, Clone)]
 pub struct Request {
-    pub messages: Vec<Message>,
+    pub messages: Vec<RichMessage>,
     pub model: Option<String>,
     pub max_tokens: usize,
     pub stop_sequences: Option<Vec<String>>,
     pub stream: bool,
     pub system: Option<String>,
     pub temperature: Option<f32>,
+    pub top_p: Option<f32>,
+    pub top_k: Option<usize>,
+    pub tools: Option<Vec<Tool>>,
 }
 
 impl Request {
@@ -21,13 +24,16 @@ impl Request {
 
 #[derive(Debug, Clone)]
 pub struct RequestBuilder {
-    messages: Vec<Message>,
+    messages: Vec<RichMessage>,
     model: Option<String>,
     max_tokens: usize,
     stop_sequences: Option<Vec<String>>,
     stream: bool,
     system: Option<String>,
     temperature: Option<f32>,
+    top_p: Option<f32>,
+    top_k: Option<usize>,
+    tools: Option<Vec<Tool>>,
 }
 
 impl Default for RequestBuilder {
@@ -40,6 +46,9 @@ impl Default for RequestBuilder {
             stream: false,
             system: None,
             temperature: None,
+            top_p: None,
+            top_k: None,
+            tools: None,
         }
     }
 }
@@ -54,8 +63,8 @@ impl RequestBuilder {
         self
     }
 
-    pub fn messages(mut self, messages: Vec<Message>) -> Self {
-        self.messages.extend(messages);
+    pub fn messages<M: Into<RichMessage>>(mut self, messages: Vec<M>) -> Self {
+        self.messages.extend(messages.into_iter().map(Into::into));
         self
     }
 
@@ -84,7 +93,35 @@ impl RequestBuilder {
         self
     }
 
+    pub fn top_p(mut self, top_p: Option<f32>) -> Self {
+        self.top_p = top_p;
+        self
+    }
+
+    pub fn top_k(mut self, top_k: Option<usize>) -> Self {
+        self.top_k = top_k;
+        self
+    }
+
+    pub fn tools(mut self, tools: Option<Vec<Tool>>) -> Self {
+        self.tools = tools;
+        self
+    }
+
+    /// A temperature outside `0.0..=1.0` is clamped into it and a NaN one left out, rather than
+    /// sent for the API to reject.
     pub fn build(self) -> Request {
+        let temperature = self.temperature.and_then(|temperature| {
+            if temperature.is_nan() {
+                tracing::warn!("ignoring temperature NaN");
+                return None;
+            }
+            let clamped = temperature.clamp(0.0, 1.0);
+            if clamped != temperature {
+                tracing::warn!("temperature {} clamped to {}", temperature, clamped);
+            }
+            Some(clamped)
+        });
         Request {
             messages: self.messages,
             model: self.model,
@@ -92,12 +129,24 @@ impl RequestBuilder {
             stop_sequences: self.stop_sequences,
             stream: self.stream,
             system: self.system,
-            temperature: self.temperature,
+            temperature,
+            top_p: self.top_p,
+            top_k: self.top_k,
+            tools: self.tools,
         }
     }
 }
 
-#[derive(Debug, Clone, Deserialize, Serialize)]
+/// A tool the model may call, with a JSON schema of its input.
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
+pub struct Tool {
+    pub name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    pub input_schema: serde_json::Value,
+}
+
+#[derive(Debug, Clone, Copy, Default, Deserialize, Serialize, PartialEq, Eq)]
 pub struct Usage {
     pub input_tokens: usize,
     pub output_tokens: usize,
@@ -110,13 +159,54 @@ pub enum Content {
     Text{
         text: String,
     },
-    Other,
+    Thinking {
+        thinking: String,
+    },
+    ToolUse {
+        id: String,
+        name: String,
+        input: serde_json::Value,
+    },
+    /// A block of a type this crate doesn't handle, as received.
+    #[serde(untagged, deserialize_with = "unknown_block")]
+    Other(serde_json::Value),
+}
+
+/// Types of the blocks the block enums parse, those of other types are kept as they are.
+pub const BLOCK_TYPES: &[&str] = &["text", "thinking", "tool_use"];
+
+/// For the untagged `Other` variant of a block enum, which would otherwise take a malformed block
+/// of one of the [`BLOCK_TYPES`] too and hide why it doesn't parse.
+pub fn unknown_block<'de, D>(deserializer: D) -> Result<serde_json::Value, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let block = serde_json::Value::deserialize(deserializer)?;
+    match block["type"].as_str() {
+        Some(type_) if BLOCK_TYPES.contains(&type_) => {
+            Err(serde::de::Error::custom(format!("invalid {} block", type_)))
+        }
+        _ => Ok(block),
+    }
+}
+
+/// Stands in for the text of a block this crate doesn't handle, so it doesn't vanish.
+fn unsupported(block: &serde_json::Value) -> String {
+    format!("[unsupported: {}]", block["type"].as_str().unwrap_or("unknown"))
 }
 
 impl Content {
     pub fn text(&self) -> Option<String> {
         match self {
             Content::Text{text} => Some(text.clone()),
+            Content::Other(block) => Some(unsupported(block)),
+            _ => None,
+        }
+    }
+
+    pub fn thinking(&self) -> Option<String> {
+        match self {
+            Content::Thinking { thinking } => Some(thinking.clone()),
             _ => None,
         }
     }
@@ -124,6 +214,7 @@ impl Content {
     pub fn bytes(&self) -> Vec<u8> {
         match self {
             Content::Text{text} => text.as_bytes().to_vec(),
+            Content::Other(block) => unsupported(block).into_bytes(),
             _ => vec![],
         }
     }
@@ -133,7 +224,10 @@ impl From<Content> for Message {
     fn from(content: Content) -> Self {
         match content {
             Content::Text{text} => Message::from(text),
-            Content::Other => Message::from("".to_string()),
+            Content::Other(block) => Message::from(unsupported(&block)),
+            Content::Thinking { .. } | Content::ToolUse { .. } => {
+                Message::from("".to_string())
+            }
         }
     }
 }
@@ -153,6 +247,23 @@ pub struct Response {
     pub usage: Usage,
 }
 
+/// The assistant message of a response, with its text and the tools it calls.
+impl From<Response> for RichMessage {
+    fn from(response: Response) -> Self {
+        let mut message = RichMessage::from(Message::assistant(""));
+        for content in response.content {
+            match content {
+                Content::Text{text} => message.content.push_str(&text),
+                Content::ToolUse { id, name, input } => {
+                    message.tool_uses.push(ToolUse { id, name, input })
+                }
+                _ => {}
+            }
+        }
+        message
+    }
+}
+
 impl From<Response> for TextEvent {
     fn from(response: Response) -> Self {
         TextEvent::MessageStart {
@@ -170,9 +281,15 @@ impl From<Response> for TextEvent {
     }
 }
 
+/// An answer without any content, which some stop reasons give, comes with an `Error` saying so
+/// rather than showing as nothing at all.
 impl From<Response> for Vec<TextEvent> {
     fn from(response: Response) -> Self {
-        vec![TextEvent::from(response), TextEvent::MessageStop]
+        let empty = response.content.is_empty().then(|| TextEvent::no_content(response.stop_reason.as_ref()));
+        let mut events = vec![TextEvent::from(response)];
+        events.extend(empty);
+        events.push(TextEvent::MessageStop);
+        events
     }
 }
 
@@ -182,6 +299,19 @@ pub enum StopReason {
     MaxTokens,
     StopSequence,
     EndTurn,
+    ToolUse,
+}
+
+/// As the API writes it, e.g. `max_tokens`.
+impl std::fmt::Display for StopReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            StopReason::MaxTokens => "max_tokens",
+            StopReason::StopSequence => "stop_sequence",
+            StopReason::EndTurn => "end_turn",
+            StopReason::ToolUse => "tool_use",
+        })
+    }
 }
 
 #[derive(Debug, Deserialize, Clone, Serialize)]
@@ -205,8 +335,17 @@ pub enum TextEvent {
     },
     MessageDelta {
         delta: MessageDelta,
+        #[serde(default)]
+        usage: Option<DeltaUsage>,
     },
     MessageStop,
+    /// The stream ended before `MessageStop`. Raised locally, never sent by the API.
+    #[serde(skip)]
+    Interrupted,
+    /// Why the answer failed, sent before `Interrupted`, or why it is empty, sent before
+    /// `MessageStop`. Raised locally, never sent by the API.
+    #[serde(skip)]
+    Error{ message: String },
 }
 
 impl TextEvent {
@@ -226,6 +365,7 @@ impl TextEvent {
             }
             TextEvent::MessageStop => true,
             TextEvent::ContentBlockStop { .. } => true,
+            TextEvent::Interrupted => true,
             _ => false,
         }
     }
@@ -237,6 +377,22 @@ impl TextEvent {
             _ => false,
         }
     }
+
+    /// The `Error` sent before `MessageStop` for an answer without any content.
+    pub fn no_content(stop_reason: Option<&StopReason>) -> Self {
+        let stop_reason = stop_reason.map_or("none".to_string(), ToString::to_string);
+        tracing::warn!("answer has no content, stop_reason={}", stop_reason);
+        TextEvent::Error { message: format!("(no content, stop_reason={})", stop_reason) }
+    }
+
+    /// Why the model stopped, on the event that says so.
+    pub fn stop_reason(&self) -> Option<&StopReason> {
+        match self {
+            TextEvent::MessageStart { message } => message.stop_reason.as_ref(),
+            TextEvent::MessageDelta { delta, .. } => delta.stop_reason.as_ref(),
+            _ => None,
+        }
+    }
 }
 
 #[derive(Debug, Deserialize, Clone, Serialize)]
@@ -259,12 +415,29 @@ pub struct MessageDelta {
     pub stop_sequence: Option<String>,
 }
 
+/// Token counts so far, sent alongside a `MessageDelta`.
+#[derive(Debug, Deserialize, Clone, Serialize)]
+pub struct DeltaUsage {
+    pub output_tokens: usize,
+}
+
 #[derive(Debug, Deserialize, Clone, Serialize)]
 #[serde(rename_all = "snake_case")]
 #[serde(tag = "type")]
 pub enum ContentBlock {
     Text { text: String },
-    Other,
+    Thinking { thinking: String },
+    /// While streaming, `input` is only set once `finish` parses the JSON received.
+    ToolUse {
+        id: String,
+        name: String,
+        input: serde_json::Value,
+        #[serde(skip)]
+        partial_json: String,
+    },
+    /// A block of a type this crate doesn't handle, as received.
+    #[serde(untagged, deserialize_with = "unknown_block")]
+    Other(serde_json::Value),
 }
 
 impl ContentBlock {
@@ -273,15 +446,53 @@ impl ContentBlock {
             (ContentBlock::Text { text }, ContentDelta::TextDelta { text: ref delta }) => {
                 text.push_str(delta);
             }
+            (
+                ContentBlock::Thinking { thinking },
+                ContentDelta::ThinkingDelta {
+                    thinking: ref delta,
+                },
+            ) => {
+                thinking.push_str(delta);
+            }
+            (ContentBlock::Thinking { .. }, ContentDelta::SignatureDelta { .. }) => {}
+            (
+                ContentBlock::ToolUse { partial_json, .. },
+                ContentDelta::InputJsonDelta { partial_json: ref delta },
+            ) => {
+                partial_json.push_str(delta);
+            }
             _ => {
                 tracing::error!("Invalid delta update");
             }
         }
     }
 
+    /// Parse the input of a streamed tool use, once its `ContentBlockStop` arrives.
+    pub fn finish(&mut self) {
+        let ContentBlock::ToolUse { name, input, partial_json, .. } = self else {
+            return;
+        };
+        if partial_json.is_empty() {
+            return;
+        }
+        match serde_json::from_str(partial_json) {
+            Ok(value) => *input = value,
+            Err(e) => tracing::error!("invalid input for tool {}: {}", name, e),
+        }
+        partial_json.clear();
+    }
+
     pub fn text(&self) -> Option<String> {
         match self {
             ContentBlock::Text { text } => Some(text.clone()),
+            ContentBlock::Other(block) => Some(unsupported(block)),
+            _ => None,
+        }
+    }
+
+    pub fn thinking(&self) -> Option<String> {
+        match self {
+            ContentBlock::Thinking { thinking } => Some(thinking.clone()),
             _ => None,
         }
     }
@@ -289,6 +500,7 @@ impl ContentBlock {
     pub fn bytes(&self) -> Vec<u8> {
         match self {
             ContentBlock::Text { text } => text.as_bytes().to_vec(),
+            ContentBlock::Other(block) => unsupported(block).into_bytes(),
             _ => vec![],
         }
     }
@@ -299,6 +511,9 @@ impl ContentBlock {
 #[serde(tag = "type")]
 pub enum ContentDelta {
     TextDelta { text: String },
+    ThinkingDelta { thinking: String },
+    SignatureDelta { signature: String },
+    InputJsonDelta { partial_json: String },
     Other,
 }
 
@@ -310,6 +525,13 @@ impl ContentDelta {
         }
     }
 
+    pub fn thinking(&self) -> Option<String> {
+        match self {
+            ContentDelta::ThinkingDelta { thinking } => Some(thinking.clone()),
+            _ => None,
+        }
+    }
+
     pub fn bytes(&self) -> Vec<u8> {
         match self {
             ContentDelta::TextDelta { text } => text.as_bytes().to_vec(),
@@ -317,3 +539,32 @@ impl ContentDelta {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_temperature() {
+        let temperature = |t| Request::builder().temperature(Some(t)).build().temperature;
+        assert_eq!(temperature(0.7), Some(0.7));
+        assert_eq!(temperature(-1.0), Some(0.0));
+        assert_eq!(temperature(2.0), Some(1.0));
+        assert_eq!(temperature(f32::NAN), None);
+    }
+
+    #[test]
+    fn test_empty_response() {
+        let response: Response = serde_json::from_str(
+            r#"{"id":"msg_01","type":"message","role":"assistant","model":"claude-3-5-sonnet-20240620","content":[],"stop_reason":"max_tokens","stop_sequence":null,"usage":{"input_tokens":45,"output_tokens":0}}"#,
+        )
+        .unwrap();
+        let events = <Vec<TextEvent>>::from(response);
+        assert_eq!(events.len(), 3);
+        assert!(matches!(
+            &events[1],
+            TextEvent::Error { message } if message == "(no content, stop_reason=max_tokens)"
+        ));
+        assert!(matches!(events[2], TextEvent::MessageStop));
+    }
+}
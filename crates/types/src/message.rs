@@ -1,16 +1,20 @@
 use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Copy, Clone, Deserialize, Serialize, Eq, PartialEq)]
-#[serde(rename_all = "lowercase")]
+/// Serialized lowercase. Deserialized like [`From<&str>`], so hand-written config files can use
+/// any case.
+#[derive(Debug, Copy, Clone, Default, Deserialize, Serialize, Eq, PartialEq)]
+#[serde(rename_all = "lowercase", from = "String")]
 pub enum Role {
+    #[default]
     User,
     Assistant,
     System,
 }
 
+/// Any case, with unknown roles taken as the user's.
 impl From<&str> for Role {
     fn from(role: &str) -> Self {
-        match role {
+        match role.to_ascii_lowercase().as_str() {
             "user" => Role::User,
             "assistant" => Role::Assistant,
             "system" => Role::System,
@@ -19,18 +23,154 @@ impl From<&str> for Role {
     }
 }
 
+impl From<String> for Role {
+    fn from(role: String) -> Self {
+        Role::from(role.as_str())
+    }
+}
+
+/// A base64 encoded image sent along with the text of a message.
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq, Eq)]
+pub struct Image {
+    /// e.g. `image/png`.
+    pub media_type: String,
+    pub data: String,
+}
+
+/// A tool the model asked to call, answered with a [`ToolResult`] in the next message.
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
+pub struct ToolUse {
+    pub id: String,
+    pub name: String,
+    pub input: serde_json::Value,
+}
+
+/// What a tool call returned.
+#[derive(Debug, Clone, Default, Deserialize, Serialize, PartialEq, Eq)]
+pub struct ToolResult {
+    pub tool_use_id: String,
+    pub content: String,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub is_error: bool,
+}
+
 // Equivalent to TypedDict in Python
-#[derive(Debug, Clone, Deserialize, Serialize)]
+#[derive(Debug, Clone, Default, Deserialize, Serialize, PartialEq)]
 pub struct Message {
     pub role: Role,
     pub content: String,
 }
 
-impl From<String> for Message {
-    fn from(content: String) -> Self {
+/// A [`Message`] with what is sent along with its text, made with [`Message::with_image`],
+/// [`Message::with_tool_results`] or from a response. Reads as the plain message otherwise.
+#[derive(Debug, Clone, Default, Deserialize, Serialize, PartialEq)]
+pub struct RichMessage {
+    #[serde(flatten)]
+    pub message: Message,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub images: Vec<Image>,
+    /// Tools called in an assistant message.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tool_uses: Vec<ToolUse>,
+    /// Answers to the tool calls of the previous message, in a user message.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tool_results: Vec<ToolResult>,
+}
+
+impl From<Message> for RichMessage {
+    fn from(message: Message) -> Self {
+        Self {
+            message,
+            ..Default::default()
+        }
+    }
+}
+
+impl std::ops::Deref for RichMessage {
+    type Target = Message;
+
+    fn deref(&self) -> &Message {
+        &self.message
+    }
+}
+
+impl std::ops::DerefMut for RichMessage {
+    fn deref_mut(&mut self) -> &mut Message {
+        &mut self.message
+    }
+}
+
+impl Message {
+    pub fn user(content: impl Into<String>) -> Self {
+        Self::with_role(Role::User, content)
+    }
+
+    pub fn assistant(content: impl Into<String>) -> Self {
+        Self::with_role(Role::Assistant, content)
+    }
+
+    pub fn system(content: impl Into<String>) -> Self {
+        Self::with_role(Role::System, content)
+    }
+
+    fn with_role(role: Role, content: impl Into<String>) -> Self {
         Self {
-            role: Role::User,
-            content,
+            role,
+            content: content.into(),
+        }
+    }
+
+    /// A message of just an image, e.g. a screenshot to ask about in the next message.
+    pub fn with_image(role: Role, media_type: String, data: String) -> RichMessage {
+        RichMessage {
+            message: Self::with_role(role, ""),
+            images: vec![Image { media_type, data }],
+            ..Default::default()
+        }
+    }
+
+    /// A user message answering tool calls.
+    pub fn with_tool_results(tool_results: Vec<ToolResult>) -> RichMessage {
+        RichMessage {
+            message: Self::user(""),
+            tool_results,
+            ..Default::default()
         }
     }
 }
+
+impl From<String> for Message {
+    fn from(content: String) -> Self {
+        Self::user(content)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_role_any_case() {
+        let role = |json: &str| serde_json::from_str::<Role>(json).unwrap();
+        assert_eq!(role(r#""User""#), Role::User);
+        assert_eq!(role(r#""SYSTEM""#), Role::System);
+        assert_eq!(role(r#""Assistant""#), Role::Assistant);
+        assert_eq!(role(r#""weird""#), Role::User);
+        assert_eq!(serde_json::to_string(&Role::System).unwrap(), r#""system""#);
+    }
+
+    #[test]
+    fn test_message_round_trip() {
+        let message = Message {
+            role: Role::Assistant,
+            content: "Hello".to_string(),
+        };
+        let json = serde_json::to_string(&message).unwrap();
+        assert_eq!(json, r#"{"role":"assistant","content":"Hello"}"#);
+        assert_eq!(serde_json::from_str::<Message>(&json).unwrap(), message);
+
+        let message: Message =
+            serde_json::from_str(r#"{"role":"ASSISTANT","content":"Hi"}"#).unwrap();
+        assert_eq!(message.role, Role::Assistant);
+    }
+}
@@ -0,0 +1,77 @@
+use similar::{ChangeTag, TextDiff};
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum DiffTag {
+    Equal,
+    Insert,
+    Delete,
+}
+
+impl From<ChangeTag> for DiffTag {
+    fn from(tag: ChangeTag) -> Self {
+        match tag {
+            ChangeTag::Equal => DiffTag::Equal,
+            ChangeTag::Insert => DiffTag::Insert,
+            ChangeTag::Delete => DiffTag::Delete,
+        }
+    }
+}
+
+impl DiffTag {
+    pub fn sign(&self) -> char {
+        match self {
+            DiffTag::Equal => ' ',
+            DiffTag::Insert => '+',
+            DiffTag::Delete => '-',
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DiffLine {
+    pub tag: DiffTag,
+    pub text: String,
+}
+
+impl std::fmt::Display for DiffLine {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}{}", self.tag.sign(), self.text)
+    }
+}
+
+/// Line-based diff of `a` against `b`, without a trailing newline on each line.
+pub fn diff_lines(a: &str, b: &str) -> Vec<DiffLine> {
+    TextDiff::from_lines(a, b)
+        .iter_all_changes()
+        .map(|change| DiffLine {
+            tag: change.tag().into(),
+            text: change.value().trim_end_matches('\n').to_string(),
+        })
+        .collect()
+}
+
+/// Unified-style diff of two texts, one `+`/`-`/` ` prefixed line per change.
+pub fn diff_text(a: &str, b: &str) -> String {
+    diff_lines(a, b)
+        .iter()
+        .map(|line| format!("{}\n", line))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_diff_text() {
+        let a = "ls -l\nls -la\n";
+        let b = "ls -l\nls -lh\n";
+        assert_eq!(diff_text(a, b), " ls -l\n-ls -la\n+ls -lh\n");
+    }
+
+    #[test]
+    fn test_diff_text_equal() {
+        let a = "echo 'Hello, World!'";
+        assert_eq!(diff_text(a, a), " echo 'Hello, World!'\n");
+    }
+}
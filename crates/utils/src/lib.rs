@@ -1,3 +1,7 @@
+pub mod diff;
+pub mod fs;
+pub mod hash;
 pub mod logging;
 pub mod macros;
+pub mod paths;
 pub mod stream;
@@ -1,15 +1,23 @@
-pub fn init_logger(filename: Option<&str>) {
+/// Log to `filename`, `rgpt.log` by default, filtered by `RUST_LOG`, or if `wire_only`, to just
+/// the bodies of requests and responses, see `rgpt_caller::client::WIRE_TARGET`. Fails if the
+/// file can't be created.
+pub fn init_logger(filename: Option<&str>, wire_only: bool) -> std::io::Result<()> {
     use tracing_subscriber::fmt::format::FmtSpan;
     use tracing_subscriber::prelude::*;
 
-    let file = std::fs::File::create(filename.unwrap_or("rgpt.log")).unwrap();
+    let file = std::fs::File::create(filename.unwrap_or("rgpt.log"))?;
     let fmt_layer = tracing_subscriber::fmt::layer()
         .with_writer(file)
         .with_target(false)
         .with_span_events(FmtSpan::CLOSE);
+    let filter = match wire_only {
+        true => tracing_subscriber::EnvFilter::new("rgpt::wire=debug"),
+        false => tracing_subscriber::EnvFilter::from_default_env(),
+    };
 
     tracing_subscriber::registry()
-        .with(tracing_subscriber::EnvFilter::from_default_env())
+        .with(filter)
         .with(fmt_layer)
         .init();
+    Ok(())
 }
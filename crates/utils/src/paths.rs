@@ -0,0 +1,30 @@
+use std::path::PathBuf;
+
+const APP_DIR: &str = "rgpt";
+
+fn home() -> Option<PathBuf> {
+    std::env::var_os("HOME").map(PathBuf::from)
+}
+
+fn xdg_dir(var: &str, fallback: &str) -> Option<PathBuf> {
+    std::env::var_os(var)
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| home().map(|home| home.join(fallback)))
+        .map(|dir| dir.join(APP_DIR))
+}
+
+/// `$XDG_STATE_HOME/rgpt`, defaulting to `~/.local/state/rgpt`.
+pub fn state_dir() -> Option<PathBuf> {
+    xdg_dir("XDG_STATE_HOME", ".local/state")
+}
+
+/// `$XDG_CACHE_HOME/rgpt`, defaulting to `~/.cache/rgpt`.
+pub fn cache_dir() -> Option<PathBuf> {
+    xdg_dir("XDG_CACHE_HOME", ".cache")
+}
+
+/// `$XDG_CONFIG_HOME/rgpt`, defaulting to `~/.config/rgpt`.
+pub fn config_dir() -> Option<PathBuf> {
+    xdg_dir("XDG_CONFIG_HOME", ".config")
+}
@@ -1,6 +1,7 @@
 use std::pin::Pin;
 
-use tokio_stream::Stream;
+use rgpt_types::completion::TextEvent;
+use tokio_stream::{Stream, StreamExt as _};
 
 use pin_project_lite::pin_project;
 
@@ -44,3 +45,40 @@ where
 {
     Box::pin(StreamAdapter { stream, f })
 }
+
+/// Map a stream of events to the text they carry, dropping events without text.
+pub fn text_stream<S, E>(events: S) -> impl Stream<Item = Result<String, E>>
+where
+    S: Stream<Item = Result<TextEvent, E>>,
+{
+    events.filter_map(|event| match event {
+        Ok(event) => event.text().map(Ok),
+        Err(e) => Some(Err(e)),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EVENTS: &str = r#"{"type":"message_start","message":{"id":"msg_01","type":"message","role":"assistant","model":"claude-3-5-sonnet-20240620","content":[],"stop_reason":null,"stop_sequence":null,"usage":{"input_tokens":25,"output_tokens":1}}}
+{"type":"content_block_start","index":0,"content_block":{"type":"text","text":""}}
+{"type":"content_block_delta","index":0,"delta":{"type":"text_delta","text":"Hello"}}
+{"type":"content_block_delta","index":0,"delta":{"type":"text_delta","text":", world"}}
+{"type":"content_block_stop","index":0}
+{"type":"message_delta","delta":{"stop_reason":"end_turn","stop_sequence":null}}
+{"type":"message_stop"}"#;
+
+    #[tokio::test]
+    async fn test_text_stream() {
+        let events = EVENTS
+            .lines()
+            .map(|line| serde_json::from_str::<TextEvent>(line).map_err(|e| e.to_string()))
+            .collect::<Vec<_>>();
+        let text = text_stream(tokio_stream::iter(events))
+            .collect::<Result<Vec<String>, String>>()
+            .await
+            .unwrap();
+        assert_eq!(text.concat(), "Hello, world\n");
+    }
+}